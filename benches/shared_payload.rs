@@ -0,0 +1,48 @@
+//! Cost of handing one message to many watchers and reading the history.
+//!
+//! Run with `cargo bench --bench shared_payload`.
+
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::Instant;
+
+use mqtt_smarthome::HistoryEntry;
+
+const ITERATIONS: u32 = 100_000;
+const WATCHERS: usize = 10;
+
+fn measure(name: &str, mut run: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        run();
+    }
+    let per_iteration = start.elapsed() / ITERATIONS;
+    println!("{name:<40} {per_iteration:>10?}");
+}
+
+fn main() {
+    let topic = "zigbee2mqtt/living-room/temperature-sensor";
+    let payload =
+        r#"{"battery":97,"humidity":48.2,"linkquality":120,"temperature":21.5,"voltage":3000}"#
+            .repeat(4);
+
+    measure(&format!("String per watcher ({WATCHERS} watchers)"), || {
+        for _ in 0..WATCHERS {
+            black_box((topic.to_owned(), payload.clone()));
+        }
+    });
+    measure(&format!("Arc<str> shared ({WATCHERS} watchers)"), || {
+        let shared: (Arc<str>, Arc<str>) = (topic.into(), payload.as_str().into());
+        for _ in 0..WATCHERS {
+            black_box(shared.clone());
+        }
+    });
+
+    let entry = HistoryEntry::new(payload.as_str());
+    measure("HistoryEntry clone (last)", || {
+        black_box(entry.clone());
+    });
+    measure("Box<[u8]> payload copy (previous clone)", || {
+        black_box(Box::<[u8]>::from(entry.payload_bytes()));
+    });
+}
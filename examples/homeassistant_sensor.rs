@@ -0,0 +1,45 @@
+//! Announce a temperature sensor to Home Assistant and publish its values.
+//!
+//! Run with `cargo run --example homeassistant_sensor --features homeassistant`.
+
+use core::time::Duration;
+
+use mqtt_smarthome::discovery::SensorConfig;
+use mqtt_smarthome::MqttSmarthome;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let smarthome = MqttSmarthome::new("example", "localhost", 1883, true);
+    smarthome
+        .await_connected()
+        .await
+        .expect("failed to connect to the broker");
+
+    let config = SensorConfig {
+        name: "Example Temperature".to_owned(),
+        state_topic: "example/status/temperature".to_owned(),
+        unique_id: Some("example-temperature".to_owned()),
+        device_class: Some("temperature".to_owned()),
+        unit_of_measurement: Some("°C".to_owned()),
+        ..SensorConfig::default()
+    };
+    smarthome
+        .announce("sensor", "temperature", &config)
+        .await
+        .expect("failed to announce the sensor");
+
+    for temperature in [21.5, 21.7, 21.6] {
+        smarthome
+            .publish(&config.state_topic, temperature, true)
+            .await
+            .expect("failed to publish the temperature");
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+
+    smarthome
+        .retract("sensor", "temperature")
+        .await
+        .expect("failed to retract the sensor");
+    // Waits for the eventloop to send the retraction before the disconnect
+    smarthome.close().await;
+}
@@ -0,0 +1,30 @@
+//! Send a command which is dropped by the broker when it can not be delivered within 10 seconds.
+//!
+//! Run with `cargo run --example set_with_expiry --features v5`.
+
+use core::time::Duration;
+
+use mqtt_smarthome::MqttSmarthome;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let smarthome = MqttSmarthome::new("example", "localhost", 1883, true);
+    smarthome
+        .await_connected()
+        .await
+        .expect("failed to connect to the broker");
+
+    // Toggling the light minutes later after a broker outage would surprise everyone
+    smarthome
+        .publish_with_expiry(
+            "hue/set/lights/kitchen",
+            true,
+            false,
+            Duration::from_secs(10),
+        )
+        .await
+        .expect("failed to publish the command");
+
+    // Waits for the eventloop to send everything before the disconnect
+    smarthome.close().await;
+}
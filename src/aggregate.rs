@@ -0,0 +1,191 @@
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+
+use crate::{payload, MqttSmarthome};
+
+/// Statistics of the numeric payloads of a topic. See [`aggregate`](MqttSmarthome::aggregate).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aggregate {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub count: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct NumericTracker {
+    topics: HashMap<String, TrackedTopic>,
+}
+
+#[derive(Debug)]
+struct TrackedTopic {
+    window: Duration,
+    samples: VecDeque<(SystemTime, f32)>,
+}
+
+impl NumericTracker {
+    pub fn track(&mut self, topic: &str, window: Duration) {
+        self.topics
+            .entry(topic.to_owned())
+            .and_modify(|tracked| tracked.window = window)
+            .or_insert_with(|| TrackedTopic {
+                window,
+                samples: VecDeque::new(),
+            });
+    }
+
+    pub fn untrack(&mut self, topic: &str) {
+        self.topics.remove(topic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    pub fn record(&mut self, topic: &str, payload: &str, time: SystemTime) {
+        let Some(tracked) = self.topics.get_mut(topic) else {
+            return;
+        };
+        tracked.remove_older(time);
+        if let Some(value) = payload::as_f32(payload).filter(|value| value.is_finite()) {
+            tracked.samples.push_back((time, value));
+        }
+    }
+
+    pub fn aggregate(&self, topic: &str, window: Duration, now: SystemTime) -> Option<Aggregate> {
+        let tracked = self.topics.get(topic)?;
+        let window = window.min(tracked.window);
+        let mut values = tracked
+            .samples
+            .iter()
+            .filter(|(time, _)| now.duration_since(*time).is_ok_and(|age| age <= window))
+            .map(|(_, value)| *value)
+            .peekable();
+        let first = *values.peek()?;
+        let mut aggregate = Aggregate {
+            min: first,
+            max: first,
+            mean: 0.0,
+            count: 0,
+        };
+        let mut sum = 0.0_f64;
+        for value in values {
+            aggregate.min = aggregate.min.min(value);
+            aggregate.max = aggregate.max.max(value);
+            aggregate.count += 1;
+            sum += f64::from(value);
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        {
+            aggregate.mean = (sum / aggregate.count as f64) as f32;
+        }
+        Some(aggregate)
+    }
+}
+
+impl TrackedTopic {
+    fn remove_older(&mut self, now: SystemTime) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time).is_ok_and(|age| age > self.window))
+        {
+            self.samples.pop_front();
+        }
+    }
+}
+
+impl MqttSmarthome {
+    /// Keep the numeric payloads received on the `topic` within the `window` for [`aggregate`](Self::aggregate).
+    ///
+    /// Tracking the same topic again replaces the `window`.
+    /// The topic needs to be subscribed.
+    pub async fn track_numeric(&self, topic: &str, window: Duration) {
+        self.numeric.write().await.track(topic, window);
+        self.numeric_tracking.store(true, Ordering::Relaxed);
+    }
+
+    /// Stop tracking the `topic` and drop its samples.
+    pub async fn untrack_numeric(&self, topic: &str) {
+        let mut numeric = self.numeric.write().await;
+        numeric.untrack(topic);
+        self.numeric_tracking
+            .store(!numeric.is_empty(), Ordering::Relaxed);
+    }
+
+    /// Min, max and mean of the numeric payloads received on the `topic` within the `window`.
+    ///
+    /// Only works for topics registered via [`track_numeric`](Self::track_numeric).
+    /// The `window` is limited to the tracked one.
+    /// Payloads not parsable as number are skipped.
+    pub async fn aggregate(&self, topic: &str, window: Duration) -> Option<Aggregate> {
+        self.numeric
+            .read()
+            .await
+            .aggregate(topic, window, self.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_eq::assert_float_eq;
+
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn untracked_is_none() {
+        let mut tracker = NumericTracker::default();
+        tracker.record("foo", "42", at(1));
+        assert_eq!(tracker.aggregate("foo", Duration::MAX, at(1)), None);
+    }
+
+    #[test]
+    fn min_max_mean() {
+        let mut tracker = NumericTracker::default();
+        tracker.track("foo", Duration::from_secs(100));
+        tracker.record("foo", "20 °C", at(1));
+        tracker.record("foo", "whatever", at(2));
+        tracker.record("foo", "22.5", at(3));
+        tracker.record("foo", "18.5", at(4));
+        let aggregate = tracker.aggregate("foo", Duration::MAX, at(5)).unwrap();
+        assert_eq!(aggregate.count, 3);
+        assert_float_eq!(aggregate.min, 18.5, abs <= 0.01);
+        assert_float_eq!(aggregate.max, 22.5, abs <= 0.01);
+        assert_float_eq!(aggregate.mean, 20.333, abs <= 0.01);
+    }
+
+    #[test]
+    fn older_than_window_is_dropped() {
+        let mut tracker = NumericTracker::default();
+        tracker.track("foo", Duration::from_secs(10));
+        tracker.record("foo", "1", at(0));
+        tracker.record("foo", "2", at(5));
+        tracker.record("foo", "3", at(12));
+        assert_eq!(tracker.topics["foo"].samples.len(), 2);
+        let aggregate = tracker.aggregate("foo", Duration::MAX, at(12)).unwrap();
+        assert_eq!(aggregate.count, 2);
+        assert_float_eq!(aggregate.min, 2.0, abs <= 0.01);
+    }
+
+    #[test]
+    fn query_window_is_smaller() {
+        let mut tracker = NumericTracker::default();
+        tracker.track("foo", Duration::from_secs(100));
+        tracker.record("foo", "1", at(0));
+        tracker.record("foo", "2", at(50));
+        let aggregate = tracker
+            .aggregate("foo", Duration::from_secs(10), at(55))
+            .unwrap();
+        assert_eq!(aggregate.count, 1);
+        assert_eq!(
+            tracker.aggregate("foo", Duration::from_secs(1), at(55)),
+            None
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::sync::atomic::Ordering;
+use std::sync::PoisonError;
+
+use crate::protocol::QoS;
+
+use crate::{MqttSmarthome, PublishError};
+
+/// Additional availability topic, see [`register_availability`](MqttSmarthome::register_availability).
+#[derive(Debug, Clone)]
+pub struct Availability {
+    topic: String,
+    online: String,
+    offline: String,
+}
+
+impl MqttSmarthome {
+    /// Publish the `online_payload` retained to the `topic` on every connection to the broker.
+    ///
+    /// The MQTT last will only covers a single topic.
+    /// The `offline_payload` of the additional topics is only published on a graceful [`shutdown`](Self::shutdown), not when the connection is lost.
+    /// Registering the same `topic` again replaces its payloads.
+    ///
+    /// # Errors
+    /// Returns an error when the topic is invalid or the MQTT eventloop is gone.
+    pub async fn register_availability(
+        &self,
+        topic: &str,
+        online_payload: &str,
+        offline_payload: &str,
+    ) -> Result<(), PublishError> {
+        PublishError::check_topic(topic)?;
+        {
+            let mut availability = self
+                .availability
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            availability.retain(|existing| existing.topic != topic);
+            availability.push(Availability {
+                topic: topic.to_owned(),
+                online: online_payload.to_owned(),
+                offline: offline_payload.to_owned(),
+            });
+        }
+        if self.connected.load(Ordering::Relaxed) {
+            self.client
+                .publish(topic, QoS::AtLeastOnce, true, online_payload.to_owned())
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn availability(&self) -> Vec<Availability> {
+        self.availability
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) async fn publish_availability_online(&self) -> Result<(), PublishError> {
+        for availability in self.availability() {
+            self.client
+                .publish(
+                    availability.topic,
+                    QoS::AtLeastOnce,
+                    true,
+                    availability.online,
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Publish the offline payloads of all [registered availability topics](Self::register_availability) and disconnect from the MQTT broker.
+    ///
+    /// # Errors
+    /// Returns an error when the MQTT eventloop is gone.
+    pub async fn shutdown(&self) -> Result<(), PublishError> {
+        for availability in self.availability() {
+            self.client
+                .publish(
+                    availability.topic,
+                    QoS::AtLeastOnce,
+                    true,
+                    availability.offline,
+                )
+                .await?;
+        }
+        self.disconnect().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{self, EventLoop, MqttOptions, Request};
+
+    use super::*;
+    use crate::LastWillConfig;
+
+    fn new() -> (MqttSmarthome, EventLoop) {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), true),
+            mqttoptions,
+        );
+        smarthome.set_birth_message(None);
+        (smarthome, eventloop)
+    }
+
+    fn retained(topic: &str, payload: &'static str) -> Request {
+        protocol::publish_request(topic, QoS::AtLeastOnce, payload, true)
+    }
+
+    fn take_requests(eventloop: &mut EventLoop) -> Vec<Request> {
+        eventloop.clean();
+        eventloop.pending.drain(..).collect()
+    }
+
+    #[tokio::test]
+    async fn online_on_every_connection() {
+        let (smarthome, mut eventloop) = new();
+        smarthome
+            .register_availability("test/lamp/connected", "2", "0")
+            .await
+            .unwrap();
+        assert!(take_requests(&mut eventloop).is_empty());
+
+        smarthome.initialize_connection(false, 0).await;
+        smarthome.initialize_connection(true, 0).await;
+        assert_eq!(
+            take_requests(&mut eventloop),
+            [
+                retained("test/lamp/connected", "2"),
+                retained("test/lamp/connected", "2"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn online_immediately_when_connected() {
+        let (smarthome, mut eventloop) = new();
+        smarthome.connected.store(true, Ordering::Relaxed);
+        smarthome
+            .register_availability("test/lamp/connected", "online", "offline")
+            .await
+            .unwrap();
+        assert_eq!(
+            take_requests(&mut eventloop),
+            [retained("test/lamp/connected", "online")]
+        );
+    }
+
+    #[tokio::test]
+    async fn shutdown_publishes_offline() {
+        let (smarthome, mut eventloop) = new();
+        smarthome
+            .register_availability("test/a/connected", "2", "0")
+            .await
+            .unwrap();
+        smarthome
+            .register_availability("test/b/connected", "online", "offline")
+            .await
+            .unwrap();
+        smarthome.shutdown().await.unwrap();
+        assert_eq!(
+            take_requests(&mut eventloop),
+            [
+                retained("test/a/connected", "0"),
+                retained("test/b/connected", "offline"),
+                protocol::disconnect_request(),
+            ]
+        );
+    }
+}
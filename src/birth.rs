@@ -0,0 +1,103 @@
+use std::sync::PoisonError;
+
+use crate::protocol::QoS;
+
+use crate::MqttSmarthome;
+
+/// Message published on every connection to the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BirthMessage {
+    pub topic: String,
+    pub payload: String,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Birth {
+    /// The [`ConnectedState`](crate::ConnectedState) to the last will topic
+    #[default]
+    ConnectedState,
+    Custom(BirthMessage),
+    Disabled,
+}
+
+impl From<Option<BirthMessage>> for Birth {
+    fn from(birth: Option<BirthMessage>) -> Self {
+        birth.map_or(Self::Disabled, Self::Custom)
+    }
+}
+
+impl MqttSmarthome {
+    /// Replace the message published on every connection to the broker.
+    ///
+    /// By default the [`connected_state`](Self::connected_state) is published to the last will topic.
+    /// With `None` nothing is published automatically, for example to use [`set_connected`](Self::set_connected) only after the hardware is initialized.
+    /// Takes effect from the next connection on as the first one might already be established.
+    /// Use [`LastWillConfig::birth_message`](crate::LastWillConfig::birth_message) to configure it for the first connection.
+    pub fn set_birth_message(&self, birth: Option<BirthMessage>) {
+        *self.birth.lock().unwrap_or_else(PoisonError::into_inner) = Birth::from(birth);
+    }
+
+    pub(crate) fn birth_message(&self) -> Option<BirthMessage> {
+        match &*self.birth.lock().unwrap_or_else(PoisonError::into_inner) {
+            Birth::ConnectedState => Some(BirthMessage {
+                topic: self.last_will_topic.clone(),
+                payload: self.connected_state().payload(),
+                qos: QoS::AtLeastOnce,
+                retain: self.last_will_retain,
+            }),
+            Birth::Custom(birth) => Some(birth.clone()),
+            Birth::Disabled => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{self, MqttOptions, Request};
+
+    use super::*;
+    use crate::LastWillConfig;
+
+    async fn requests_on_connection(configure: impl FnOnce(&MqttSmarthome)) -> Vec<Request> {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), true),
+            mqttoptions,
+        );
+        configure(&smarthome);
+        smarthome.initialize_connection(false, 0).await;
+        eventloop.clean();
+        eventloop.pending.into_iter().collect()
+    }
+
+    #[tokio::test]
+    async fn default_publishes_connected_state() {
+        let requests = requests_on_connection(|_| {}).await;
+        let expected = protocol::publish_request("test/connected", QoS::AtLeastOnce, "1", true);
+        assert_eq!(requests, [expected]);
+    }
+
+    #[tokio::test]
+    async fn custom_birth_message() {
+        let requests = requests_on_connection(|smarthome| {
+            smarthome.set_birth_message(Some(BirthMessage {
+                topic: "test/availability".to_owned(),
+                payload: "online".to_owned(),
+                qos: QoS::AtMostOnce,
+                retain: false,
+            }));
+        })
+        .await;
+        let expected =
+            protocol::publish_request("test/availability", QoS::AtMostOnce, "online", false);
+        assert_eq!(requests, [expected]);
+    }
+
+    #[tokio::test]
+    async fn disabled_publishes_nothing() {
+        let requests = requests_on_connection(|smarthome| smarthome.set_birth_message(None)).await;
+        assert!(requests.is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Synchronous usage for applications not built on tokio.
+
+use core::time::Duration;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+use tokio::runtime::{Builder, Handle};
+use tokio::sync::oneshot;
+use tokio::task;
+
+use crate::protocol::MqttOptions;
+use crate::watcher::ChannelPayload;
+use crate::{HistoryEntry, IntoPayload, LastWillConfig, MqttSmarthome, PublishError};
+
+/// [`MqttSmarthome`] with synchronous methods.
+///
+/// Owns a current-thread tokio runtime driven by a background thread which also runs the MQTT eventloop.
+/// Dropping it stops the runtime without disconnecting so the broker publishes the last will.
+/// Use [`shutdown`](Self::shutdown) for a clean disconnect.
+#[derive(Debug)]
+pub struct BlockingMqttSmarthome {
+    smarthome: MqttSmarthome,
+    handle: Handle,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BlockingMqttSmarthome {
+    /// Same as [`MqttSmarthome::new`].
+    ///
+    /// # Errors
+    /// Returns an error when the runtime or its thread can not be created.
+    pub fn new(
+        base_topic: &str,
+        host: &str,
+        port: u16,
+        last_will_retain: bool,
+    ) -> std::io::Result<Self> {
+        Self::new_last_will(
+            LastWillConfig::new(format!("{base_topic}/connected"), last_will_retain),
+            MqttOptions::new(base_topic, host, port),
+        )
+    }
+
+    /// Same as [`MqttSmarthome::new_last_will`].
+    ///
+    /// # Errors
+    /// Returns an error when the runtime or its thread can not be created.
+    pub fn new_last_will(
+        last_will: LastWillConfig,
+        mqttoptions: MqttOptions,
+    ) -> std::io::Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let handle = runtime.handle().clone();
+        let (smarthome, eventloop) = MqttSmarthome::new_detached(last_will, mqttoptions);
+        let (stop, stopped) = oneshot::channel();
+
+        let thread = thread::Builder::new()
+            .name("mqtt-smarthome".to_owned())
+            .spawn({
+                let smarthome = smarthome.clone();
+                move || {
+                    runtime.block_on(async move {
+                        smarthome.set_eventloop_task(task::spawn(eventloop));
+                        _ = stopped.await;
+                    });
+                    runtime.shutdown_timeout(Duration::from_secs(1));
+                }
+            })?;
+
+        Ok(Self {
+            smarthome,
+            handle,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// The async client for everything not covered here.
+    ///
+    /// Its methods can be run via [`block_on`](Self::block_on).
+    #[must_use]
+    pub const fn smarthome(&self) -> &MqttSmarthome {
+        &self.smarthome
+    }
+
+    /// Run a future on the runtime of this client and wait for its result.
+    ///
+    /// # Panics
+    /// Panics when called from within an async context.
+    pub fn block_on<F: core::future::Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+
+    /// See [`MqttSmarthome::publish`].
+    ///
+    /// # Errors
+    /// See [`MqttSmarthome::publish`].
+    pub fn publish<P>(&self, topic: &str, payload: P, retain: bool) -> Result<(), PublishError>
+    where
+        P: IntoPayload,
+    {
+        self.block_on(self.smarthome.publish(topic, payload, retain))
+    }
+
+    /// See [`MqttSmarthome::last`].
+    #[must_use]
+    pub fn last(&self, topic: &str) -> Option<HistoryEntry> {
+        self.block_on(self.smarthome.last(topic))
+    }
+
+    /// See [`MqttSmarthome::last_float`].
+    #[must_use]
+    pub fn last_float(&self, topic: &str) -> Option<f32> {
+        self.block_on(self.smarthome.last_float(topic))
+    }
+
+    /// Same as [`MqttSmarthome::subscribe_and_watch`] with a channel of the standard library.
+    ///
+    /// The channel is unbounded, messages are forwarded until the receiver is dropped.
+    #[must_use]
+    pub fn subscribe_channel(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> mpsc::Receiver<ChannelPayload> {
+        let mut receiver = self.block_on(self.smarthome.subscribe_and_watch(topic, allow_retained));
+        let (sender, std_receiver) = mpsc::channel();
+        self.handle.spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        std_receiver
+    }
+
+    /// Publish the offline payloads of the availability topics, [close](MqttSmarthome::close) the client and stop the runtime.
+    ///
+    /// See [`MqttSmarthome::shutdown`].
+    ///
+    /// # Errors
+    /// See [`MqttSmarthome::shutdown`]. The runtime is stopped either way.
+    pub fn shutdown(self) -> Result<(), PublishError> {
+        let result = self.block_on(self.smarthome.shutdown());
+        self.block_on(self.smarthome.clone().close());
+        result
+    }
+}
+
+impl Drop for BlockingMqttSmarthome {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    fn new() -> BlockingMqttSmarthome {
+        BlockingMqttSmarthome::new("test", "localhost", 1, false).unwrap()
+    }
+
+    #[test]
+    fn publish_and_last_without_runtime() {
+        let smarthome = new();
+        smarthome.publish("temp", 21.5, false).unwrap();
+        assert_eq!(smarthome.last("temp").unwrap().payload(), "21.5");
+        assert_eq!(smarthome.last_float("temp"), Some(21.5));
+        assert!(smarthome.publish("invalid/#", "", false).is_err());
+        smarthome.shutdown().unwrap();
+    }
+
+    #[test]
+    fn subscribe_channel_receives() {
+        let smarthome = new();
+        let receiver = smarthome.subscribe_channel("sensor/#", false);
+        smarthome.block_on(handle_incoming(
+            smarthome.smarthome(),
+            "sensor/temp".to_owned(),
+            "21".to_owned(),
+            false,
+        ));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)),
+            Ok(("sensor/temp".to_owned(), "21".to_owned()))
+        );
+    }
+
+    #[test]
+    fn drop_stops_the_runtime() {
+        let smarthome = new();
+        let receiver = smarthome.subscribe_channel("foo", false);
+        drop(smarthome);
+        assert!(receiver.recv_timeout(Duration::from_secs(1)).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use core::fmt;
+#[cfg(any(test, feature = "testing"))]
+use core::time::Duration;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::SystemTime;
+
+use crate::MqttSmarthome;
+
+/// Source of the current time for history entries, expiry and the watchdog, see [`set_clock`](MqttSmarthome::set_clock).
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system time, used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock only moving when told to, for deterministic tests of time based features.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug)]
+pub struct MockClock {
+    now: std::sync::Mutex<SystemTime>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl MockClock {
+    #[must_use]
+    pub const fn new(start: SystemTime) -> Self {
+        Self {
+            now: std::sync::Mutex::new(start),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) += duration;
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+/// The replaceable clock shared by every part of one client.
+#[derive(Clone)]
+pub struct SharedClock(Arc<RwLock<Arc<dyn Clock>>>);
+
+impl SharedClock {
+    pub fn now(&self) -> SystemTime {
+        self.0.read().unwrap_or_else(PoisonError::into_inner).now()
+    }
+
+    fn replace(&self, clock: Arc<dyn Clock>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = clock;
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(SystemClock))))
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SharedClock").field(&self.now()).finish()
+    }
+}
+
+impl MqttSmarthome {
+    /// Replace the clock used for the time of history entries, their expiry, [`since_last_received`](Self::since_last_received) and the [`watchdog`](Self::watchdog).
+    ///
+    /// Defaults to the [`SystemClock`]. Keep an [`Arc`] of the clock to move it in tests.
+    /// Timers like intervals and timeouts still use the tokio time.
+    pub fn set_clock<C>(&self, clock: C)
+    where
+        C: Clock + 'static,
+    {
+        self.clock.replace(Arc::new(clock));
+    }
+
+    pub(crate) fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_advances() {
+        let clock = MockClock::new(SystemTime::UNIX_EPOCH);
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(5));
+        clock.set(SystemTime::UNIX_EPOCH);
+        assert_eq!(clock.now(), SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn shared_clock_is_replaced_for_every_clone() {
+        let shared = SharedClock::default();
+        let other = shared.clone();
+        let clock = Arc::new(MockClock::new(SystemTime::UNIX_EPOCH));
+        shared.replace(clock.clone());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(other.now(), SystemTime::UNIX_EPOCH + Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,79 @@
+use core::time::Duration;
+use std::sync::atomic::Ordering;
+use std::sync::PoisonError;
+
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use crate::MqttSmarthome;
+
+/// How long [`close`](MqttSmarthome::close) waits for the eventloop to send the disconnect.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl MqttSmarthome {
+    pub(crate) fn set_eventloop_task(&self, task: JoinHandle<()>) {
+        *self
+            .eventloop_task
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(task);
+    }
+
+    /// [Disconnect](Self::disconnect), wait for the eventloop to end and close every channel.
+    ///
+    /// The eventloop is cancelled when it is not connected or does not end within a few seconds.
+    /// Receivers of watchers, taps and events end afterwards so consumers unblock.
+    /// This affects every clone of the client.
+    pub async fn close(self) {
+        let task = self
+            .eventloop_task
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        // The disconnect is only sent while connected
+        let disconnecting =
+            self.disconnect().await.is_ok() && self.connected.load(Ordering::Relaxed);
+        if let Some(mut task) = task {
+            let finished = disconnecting && timeout(CLOSE_TIMEOUT, &mut task).await.is_ok();
+            if !finished {
+                task.abort();
+                _ = task.await;
+            }
+        }
+        self.close_channels().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::pending;
+
+    use tokio::task;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn close_cancels_unconnected_eventloop() {
+        let smarthome = MqttSmarthome::new("test", "localhost", 1, false);
+        let mut receiver = smarthome.watch("foo", false).await;
+        let mut events = smarthome.connection_events();
+        smarthome.clone().close().await;
+        assert!(smarthome
+            .eventloop_task
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none());
+        assert_eq!(receiver.recv().await, None);
+        while events.recv().await.is_some() {}
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_aborts_eventloop_not_ending() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.connected.store(true, Ordering::Relaxed);
+        let task = task::spawn(pending::<()>());
+        let abort = task.abort_handle();
+        smarthome.set_eventloop_task(task);
+        smarthome.close().await;
+        assert!(abort.is_finished());
+    }
+}
@@ -0,0 +1,183 @@
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task;
+
+use crate::watcher::{Watcher, WatcherSender};
+use crate::{HistoryEntry, MqttSmarthome};
+
+impl MqttSmarthome {
+    /// Subscribe to both topics and get their latest entries every time either of them receives a message.
+    ///
+    /// See [`combine_latest_many`](Self::combine_latest_many).
+    pub async fn combine_latest(
+        &self,
+        topic_a: &str,
+        topic_b: &str,
+    ) -> Receiver<(Option<HistoryEntry>, Option<HistoryEntry>)> {
+        let mut combined = self.combine_latest_many(&[topic_a, topic_b]).await;
+        let (sender, receiver) = channel(25);
+        task::spawn(async move {
+            loop {
+                let latest = tokio::select! {
+                    latest = combined.recv() => match latest {
+                        Some(latest) => latest,
+                        None => break,
+                    },
+                    () = sender.closed() => break,
+                };
+                let mut latest = latest.into_iter();
+                let pair = (latest.next().flatten(), latest.next().flatten());
+                if sender.send(pair).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Subscribe to the `topics` and get their latest entries in the same order every time any of them receives a message.
+    ///
+    /// The entries start with the ones in the history. Nothing is sent until the first message arrives.
+    /// Retained messages count as messages as they might be the first known state.
+    /// The `topics` are compared exactly, wildcards are not supported.
+    /// A topic given multiple times is subscribed and watched once and fills all of its positions.
+    /// # Panics
+    /// Panics when a topic contains a wildcard.
+    /// With the [history disabled](Self::set_history_enabled) the entries are created from the received payloads.
+    pub async fn combine_latest_many(
+        &self,
+        topics: &[&str],
+    ) -> Receiver<Vec<Option<HistoryEntry>>> {
+        assert!(
+            topics.iter().all(|topic| !topic.contains(['+', '#'])),
+            "wildcards are not supported"
+        );
+        let (updates_sender, mut updates) = channel(25);
+        for (index, topic) in topics.iter().enumerate() {
+            if topics[..index].contains(topic) {
+                continue;
+            }
+            self.subscribe(topic).await;
+            let watcher =
+                Watcher::with_sender(topic, true, WatcherSender::Payload(updates_sender.clone()));
+            self.watchers.write().await.push(watcher);
+        }
+        drop(updates_sender);
+
+        let topics = topics
+            .iter()
+            .map(|topic| (*topic).to_owned())
+            .collect::<Vec<_>>();
+        let mut latest = topics
+            .iter()
+            .map(|topic| self.history.get(topic))
+            .collect::<Vec<_>>();
+        let (sender, receiver) = channel(25);
+        let smarthome = self.clone();
+        task::spawn(async move {
+            loop {
+                let (topic, payload) = tokio::select! {
+                    update = updates.recv() => match update {
+                        Some(update) => update,
+                        None => break,
+                    },
+                    () = sender.closed() => break,
+                };
+                let entry = smarthome
+                    .history
+                    .get(&topic)
+                    .unwrap_or_else(|| HistoryEntry::new_at(payload, smarthome.now()));
+                for (known, latest) in topics.iter().zip(&mut latest) {
+                    if *known == topic {
+                        *latest = Some(entry.clone());
+                    }
+                }
+                if sender.send(latest.clone()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn emits_pair_on_every_update() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "dark".to_owned(), "true".to_owned(), true).await;
+        let mut combined = smarthome.combine_latest("motion", "dark").await;
+        assert!(combined.try_recv().is_err(), "nothing before an update");
+
+        handle_incoming(&smarthome, "motion".to_owned(), "on".to_owned(), false).await;
+        let (motion, dark) = combined.recv().await.unwrap();
+        assert_eq!(motion.unwrap().payload(), "on");
+        assert_eq!(dark.unwrap().payload(), "true");
+
+        handle_incoming(&smarthome, "dark".to_owned(), "false".to_owned(), false).await;
+        let (motion, dark) = combined.recv().await.unwrap();
+        assert_eq!(motion.unwrap().payload(), "on");
+        assert_eq!(dark.unwrap().payload(), "false");
+        assert_eq!(smarthome.subscriptions().await, ["dark", "motion"]);
+    }
+
+    #[tokio::test]
+    async fn many_keeps_order_without_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_history_enabled(false);
+        let mut combined = smarthome.combine_latest_many(&["a", "b", "c"]).await;
+        handle_incoming(&smarthome, "c".to_owned(), "3".to_owned(), false).await;
+        handle_incoming(&smarthome, "other".to_owned(), "0".to_owned(), false).await;
+        handle_incoming(&smarthome, "a".to_owned(), "1".to_owned(), false).await;
+
+        let payloads = |latest: Vec<Option<HistoryEntry>>| {
+            latest
+                .into_iter()
+                .map(|entry| entry.map(|entry| entry.payload().into_owned()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            payloads(combined.recv().await.unwrap()),
+            [None, None, Some("3".to_owned())]
+        );
+        assert_eq!(
+            payloads(combined.recv().await.unwrap()),
+            [Some("1".to_owned()), None, Some("3".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn dropped_receiver_removes_watchers() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let combined = smarthome.combine_latest("a", "b").await;
+        drop(combined);
+        for _ in 0..10 {
+            task::yield_now().await;
+        }
+        handle_incoming(&smarthome, "a".to_owned(), "1".to_owned(), false).await;
+        assert_eq!(smarthome.watchers.read().await.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn repeated_topic_is_watched_once() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut combined = smarthome.combine_latest_many(&["a", "b", "a"]).await;
+        assert_eq!(smarthome.watchers.read().await.len(), 2);
+        handle_incoming(&smarthome, "a".to_owned(), "1".to_owned(), false).await;
+        let latest = combined.recv().await.unwrap();
+        assert_eq!(latest[0].as_ref().unwrap().payload(), "1");
+        assert!(latest[1].is_none());
+        assert_eq!(latest[2].as_ref().unwrap().payload(), "1");
+        assert!(combined.try_recv().is_err(), "one update per message");
+    }
+
+    #[tokio::test]
+    #[should_panic = "wildcards are not supported"]
+    async fn wildcard_panics() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.combine_latest_many(&["a", "b/+"]).await;
+    }
+}
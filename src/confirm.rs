@@ -0,0 +1,158 @@
+use core::time::Duration;
+
+use tokio::time::{timeout_at, Instant};
+
+use crate::payload;
+use crate::watcher::{RemoveWatcherOnDrop, Watcher};
+use crate::{HistoryEntry, IntoPayload, MqttSmarthome, SetError};
+
+impl MqttSmarthome {
+    /// Publish `payload` to the `set_topic` and wait for the `status_topic` to report it.
+    ///
+    /// The reported payload is accepted when it is equal to the requested one or both are the same boolean state.
+    ///
+    /// # Errors
+    /// Returns [`SetError::Publish`] when publishing to the `set_topic` failed,
+    /// [`SetError::TimedOut`] when nothing was reported within `max_wait`
+    /// or [`SetError::Differs`] when only other values were reported.
+    pub async fn set_and_confirm<P>(
+        &self,
+        set_topic: &str,
+        status_topic: &str,
+        payload: P,
+        max_wait: Duration,
+    ) -> Result<HistoryEntry, SetError>
+    where
+        P: IntoPayload,
+    {
+        let deadline = Instant::now() + max_wait;
+        let payload = payload.into_payload();
+        let requested = String::from_utf8_lossy(&payload).into_owned();
+
+        self.subscribe(status_topic).await;
+        let (watcher, mut receiver) = Watcher::new(status_topic, false);
+        let _remove = RemoveWatcherOnDrop {
+            watchers: self.watchers.clone(),
+            active: watcher.activation(),
+        };
+        self.watchers.write().await.push(watcher);
+
+        self.publish(set_topic, payload, false).await?;
+
+        let mut reported = None;
+        while let Ok(Some((_, payload))) = timeout_at(deadline, receiver.recv()).await {
+            if is_confirmation(&requested, &payload) {
+                // The history holds the time and source of the report unless it was already replaced
+                let entry = self
+                    .history
+                    .get(status_topic)
+                    .filter(|entry| entry.payload() == payload)
+                    .unwrap_or_else(|| HistoryEntry::new_at(payload, self.now()));
+                return Ok(entry);
+            }
+            reported = Some(payload);
+        }
+        Err(
+            reported.map_or(SetError::TimedOut, |reported| SetError::Differs {
+                reported,
+            }),
+        )
+    }
+}
+
+fn is_confirmation(requested: &str, reported: &str) -> bool {
+    if requested == reported {
+        return true;
+    }
+    match (payload::as_bool(requested), payload::as_bool(reported)) {
+        (Some(requested), Some(reported)) => requested == reported,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use crate::clock::MockClock;
+    use crate::{handle_incoming, EntrySource, PublishError};
+
+    const START: SystemTime = SystemTime::UNIX_EPOCH;
+
+    #[rstest::rstest]
+    #[case("42", "42", true)]
+    #[case("42", "43", false)]
+    #[case("on", "true", true)]
+    #[case("ON", "1", true)]
+    #[case("off", "true", false)]
+    #[case("on", "whatever", false)]
+    fn confirmation(#[case] requested: &str, #[case] reported: &str, #[case] expected: bool) {
+        assert_eq!(is_confirmation(requested, reported), expected);
+    }
+
+    async fn set_and_report(reports: &[&str]) -> Result<HistoryEntry, SetError> {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let clock = Arc::new(MockClock::new(START));
+        smarthome.set_clock(clock.clone());
+        let setting = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move {
+                smarthome
+                    .set_and_confirm("light/set", "light/status", "on", Duration::from_secs(5))
+                    .await
+            }
+        });
+        while smarthome.last("light/set").await.is_none() {
+            tokio::task::yield_now().await;
+        }
+        for report in reports {
+            clock.advance(Duration::from_secs(1));
+            handle_incoming(
+                &smarthome,
+                "light/status".to_owned(),
+                (*report).to_owned(),
+                false,
+            )
+            .await;
+        }
+        setting.await.unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn confirmed() {
+        let entry = set_and_report(&["off", "true"]).await.unwrap();
+        assert_eq!(entry.payload(), "true");
+        assert_eq!(entry.time(), START + Duration::from_secs(2));
+        assert_eq!(entry.source(), EntrySource::Incoming);
+        assert!(!entry.retained());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn differs() {
+        let result = set_and_report(&["off"]).await;
+        assert!(matches!(
+            result.unwrap_err(),
+            SetError::Differs { reported } if reported == "off"
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_set_topic() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let result = smarthome
+            .set_and_confirm("light/+/set", "light/status", "on", Duration::from_secs(5))
+            .await;
+        assert!(matches!(
+            result.unwrap_err(),
+            SetError::Publish(PublishError::InvalidTopic { .. })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out() {
+        let result = set_and_report(&[]).await;
+        assert!(matches!(result.unwrap_err(), SetError::TimedOut));
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::Ordering;
+
+use crate::protocol::QoS;
+
+use crate::{MqttSmarthome, PublishError};
+
+/// Value of the connected topic as defined by the mqtt-smarthome convention.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectedState {
+    /// The daemon is not connected to the broker. This is the last will.
+    Offline = 0,
+    /// The daemon is connected to the broker but not to its hardware.
+    #[default]
+    BrokerOnly = 1,
+    /// The daemon is connected to the broker and its hardware.
+    Full = 2,
+}
+
+impl ConnectedState {
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Offline,
+            2 => Self::Full,
+            _ => Self::BrokerOnly,
+        }
+    }
+
+    pub(crate) fn payload(self) -> String {
+        (self as u8).to_string()
+    }
+}
+
+impl MqttSmarthome {
+    /// Publish the `state` to the connected topic.
+    ///
+    /// The state is remembered and published again after every reconnect.
+    ///
+    /// # Errors
+    /// Returns an error when the MQTT eventloop is gone.
+    pub async fn set_connected(&self, state: ConnectedState) -> Result<(), PublishError> {
+        self.connected_state.store(state as u8, Ordering::Relaxed);
+        self.client
+            .publish(
+                &self.last_will_topic,
+                QoS::AtLeastOnce,
+                self.last_will_retain,
+                state.payload(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// The state remembered by [`set_connected`](Self::set_connected).
+    #[must_use]
+    pub fn connected_state(&self) -> ConnectedState {
+        ConnectedState::from_u8(self.connected_state.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_is_numeric() {
+        assert_eq!(ConnectedState::Offline.payload(), "0");
+        assert_eq!(ConnectedState::BrokerOnly.payload(), "1");
+        assert_eq!(ConnectedState::Full.payload(), "2");
+    }
+
+    #[tokio::test]
+    async fn state_is_remembered() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        assert_eq!(smarthome.connected_state(), ConnectedState::BrokerOnly);
+        smarthome.set_connected(ConnectedState::Full).await.unwrap();
+        assert_eq!(smarthome.connected_state(), ConnectedState::Full);
+    }
+}
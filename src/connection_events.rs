@@ -0,0 +1,140 @@
+use std::sync::PoisonError;
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::{ConnectionFailure, MqttSmarthome};
+
+/// Changes of the connection to the MQTT broker, see [`connection_events`](MqttSmarthome::connection_events).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The broker accepted the connection.
+    ///
+    /// `session_present` is true when the broker resumed a [persistent session](crate::protocol::set_persistent_session).
+    Connected {
+        session_present: bool,
+    },
+    Disconnected,
+    /// The client stopped reconnecting, see [`set_max_reconnect_attempts`](MqttSmarthome::set_max_reconnect_attempts).
+    ///
+    /// This is the last event, the channel is closed afterwards.
+    GaveUp,
+    /// The broker rejected the credentials. The client stopped reconnecting as retrying does not help.
+    ///
+    /// This is the last event, the channel is closed afterwards.
+    AuthenticationFailed,
+}
+
+/// State of the connection for [`await_connected`](MqttSmarthome::await_connected).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Connecting,
+    Connected,
+    /// Subscriptions and birth message are handed to the client
+    Initialized,
+    Failed(ConnectionFailure),
+}
+
+impl MqttSmarthome {
+    /// Get notified about connection changes.
+    ///
+    /// Events are skipped while the receiver has 25 unread events.
+    #[must_use]
+    pub fn connection_events(&self) -> Receiver<ConnectionEvent> {
+        let (sender, receiver) = channel(25);
+        self.connection_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    /// Wait until the client [gave up](Self::has_given_up) reconnecting.
+    pub(crate) async fn wait_given_up(&self) {
+        let mut link = self.link.subscribe();
+        _ = link.wait_for(|link| matches!(link, Link::Failed(_))).await;
+    }
+
+    /// Wait until the broker accepted the connection. Returns immediately when already connected.
+    ///
+    /// # Errors
+    /// Returns why the client stopped reconnecting, for example because of wrong credentials.
+    pub async fn await_connected(&self) -> Result<(), ConnectionFailure> {
+        let mut link = self.link.subscribe();
+        let link = link
+            .wait_for(|link| *link != Link::Connecting)
+            .await
+            .map_or(Link::Connecting, |link| *link);
+        match link {
+            Link::Failed(failure) => Err(failure),
+            Link::Connecting | Link::Connected | Link::Initialized => Ok(()),
+        }
+    }
+
+    /// Same as [`await_connected`](Self::await_connected) but also waits until the subscriptions and the birth message of the connection are handed to the client.
+    ///
+    /// # Errors
+    /// Returns why the client stopped reconnecting, for example because of wrong credentials.
+    pub async fn await_initialized(&self) -> Result<(), ConnectionFailure> {
+        let mut link = self.link.subscribe();
+        let link = link
+            .wait_for(|link| matches!(link, Link::Initialized | Link::Failed(_)))
+            .await
+            .map_or(Link::Connecting, |link| *link);
+        match link {
+            Link::Failed(failure) => Err(failure),
+            Link::Connecting | Link::Connected | Link::Initialized => Ok(()),
+        }
+    }
+
+    pub(crate) fn forward_connection_event(&self, event: ConnectionEvent) {
+        self.connection_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|sender| !matches!(sender.try_send(event), Err(TrySendError::Closed(_))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn await_connected_waits_for_connection() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let waiting = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move { smarthome.await_connected().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        smarthome.link.send_replace(Link::Connected);
+        assert_eq!(waiting.await.unwrap(), Ok(()));
+        assert_eq!(smarthome.await_connected().await, Ok(()));
+    }
+
+    #[test]
+    fn events_are_forwarded() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.connection_events();
+        smarthome.forward_connection_event(ConnectionEvent::Connected {
+            session_present: true,
+        });
+        smarthome.forward_connection_event(ConnectionEvent::Disconnected);
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(ConnectionEvent::Connected {
+                session_present: true
+            })
+        );
+        assert_eq!(receiver.try_recv(), Ok(ConnectionEvent::Disconnected));
+    }
+
+    #[test]
+    fn dropped_receiver_is_removed() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        drop(smarthome.connection_events());
+        smarthome.forward_connection_event(ConnectionEvent::Disconnected);
+        assert!(smarthome.connection_events.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,98 @@
+//! Helpers for the `base/status/item`, `base/set/item` and `base/get/item` topics of the [mqtt-smarthome convention](https://github.com/mqtt-smarthome/mqtt-smarthome).
+
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task;
+
+use crate::{IntoPayload, MqttSmarthome, PublishError};
+
+impl MqttSmarthome {
+    /// Publish the `payload` to `{base_topic}/status/{item}`.
+    ///
+    /// # Errors
+    /// Returns an error when the item is not a valid topic or the MQTT eventloop is gone.
+    pub async fn publish_status<P: IntoPayload>(
+        &self,
+        item: &str,
+        payload: P,
+        retain: bool,
+    ) -> Result<(), PublishError> {
+        let topic = format!("{}/status/{item}", self.base_topic);
+        self.publish(&topic, payload, retain).await
+    }
+
+    /// Subscribe to `{base_topic}/set/{item_filter}` and get the item path without the prefix together with the payload.
+    pub async fn subscribe_set(&self, item_filter: &str) -> Receiver<(String, String)> {
+        self.subscribe_items("set", item_filter).await
+    }
+
+    /// Subscribe to `{base_topic}/get/{item_filter}` and get the item path without the prefix together with the payload.
+    pub async fn subscribe_get(&self, item_filter: &str) -> Receiver<(String, String)> {
+        self.subscribe_items("get", item_filter).await
+    }
+
+    async fn subscribe_items(&self, kind: &str, item_filter: &str) -> Receiver<(String, String)> {
+        let prefix = format!("{}/{kind}/", self.base_topic);
+        let mut input = self
+            .subscribe_and_watch(&format!("{prefix}{item_filter}"), false)
+            .await;
+        let (sender, receiver) = channel(25);
+        task::spawn(async move {
+            while let Some((topic, payload)) = input.recv().await {
+                let Some(item) = topic.strip_prefix(&prefix) else {
+                    continue;
+                };
+                if sender.send((item.to_owned(), payload)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn status_is_below_base_topic() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .publish_status("lamp/brightness", 42, true)
+            .await
+            .unwrap();
+        let entry = smarthome.last("test/status/lamp/brightness").await.unwrap();
+        assert_eq!(entry.payload(), "42");
+    }
+
+    #[tokio::test]
+    async fn set_strips_multi_level_prefix() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_set("lamp/#").await;
+        assert!(smarthome.is_subscribed("test/set/lamp/brightness").await);
+        handle_incoming(
+            &smarthome,
+            "test/set/lamp/brightness".to_owned(),
+            "42",
+            false,
+        )
+        .await;
+        assert_eq!(
+            receiver.recv().await,
+            Some(("lamp/brightness".to_owned(), "42".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn get_only_gets_get() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_get("+").await;
+        handle_incoming(&smarthome, "test/set/lamp".to_owned(), "1", false).await;
+        handle_incoming(&smarthome, "test/get/lamp".to_owned(), "", false).await;
+        assert_eq!(
+            receiver.recv().await,
+            Some(("lamp".to_owned(), String::new()))
+        );
+    }
+}
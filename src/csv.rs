@@ -0,0 +1,109 @@
+//! Export the history as CSV with the columns `topic,unix_millis,payload`.
+
+use crate::MqttSmarthome;
+
+/// Quote the `field` according to RFC 4180 when it contains a comma, quote or line break.
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+impl MqttSmarthome {
+    /// Export the last `HistoryEntry` of every topic as CSV sorted by topic.
+    ///
+    /// The columns are `topic,unix_millis,payload` and lines end with `\r\n` as specified by RFC 4180.
+    /// With a MQTT topic `filter` only matching topics are exported, an invalid `filter` matches nothing.
+    pub async fn history_csv(&self, filter: Option<&str>) -> String {
+        let entries = if let Some(filter) = filter {
+            self.last_matching(filter).await
+        } else {
+            let mut entries = self
+                .history_snapshot()
+                .await
+                .into_iter()
+                .collect::<Vec<_>>();
+            entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            entries
+        };
+        let mut csv = String::from("topic,unix_millis,payload\r\n");
+        for (topic, entry) in entries {
+            csv += &escape_csv_field(&topic);
+            csv.push(',');
+            if let Some(unix_millis) = entry.unix_millis() {
+                csv += &unix_millis.to_string();
+            }
+            csv.push(',');
+            csv += &escape_csv_field(&entry.payload());
+            csv += "\r\n";
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::time::SystemTime;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("", "")]
+    #[case("42", "42")]
+    #[case("a b", "a b")]
+    #[case("1,5", "\"1,5\"")]
+    #[case("say \"hi\"", "\"say \"\"hi\"\"\"")]
+    #[case("a\nb", "\"a\nb\"")]
+    #[case("a\r\nb", "\"a\r\nb\"")]
+    #[case(r#"{"a":1,"b":"c"}"#, r#""{""a"":1,""b"":""c""}""#)]
+    fn escape(#[case] field: &str, #[case] expected: &str) {
+        assert_eq!(escape_csv_field(field), expected);
+    }
+
+    #[tokio::test]
+    async fn exports_sorted_by_topic() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.history.insert_at(
+            "b/status/temp",
+            "21,5",
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+        );
+        smarthome.history.insert_at(
+            "a/set/temp",
+            "22",
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+        );
+        assert_eq!(
+            smarthome.history_csv(None).await,
+            "topic,unix_millis,payload\r\na/set/temp,1000,22\r\nb/status/temp,2000,\"21,5\"\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn exports_matching_filter() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.history.insert_at(
+            "b/status/temp",
+            "21",
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+        );
+        smarthome.history.insert_at(
+            "a/set/temp",
+            "22",
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+        );
+        assert_eq!(
+            smarthome.history_csv(Some("+/status/#")).await,
+            "topic,unix_millis,payload\r\nb/status/temp,2000,21\r\n"
+        );
+        assert_eq!(
+            smarthome.history_csv(Some("#/invalid")).await,
+            "topic,unix_millis,payload\r\n"
+        );
+    }
+}
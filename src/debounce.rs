@@ -0,0 +1,160 @@
+use core::time::Duration;
+use std::collections::HashMap;
+
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task;
+use tokio::time::{sleep_until, Instant};
+
+use crate::watcher::ChannelPayload;
+
+struct TopicState {
+    last_delivered: Instant,
+    pending: Option<String>,
+}
+
+/// Debounce the messages of `input` per topic.
+///
+/// The first message of a topic is delivered right away.
+/// Messages arriving within `window` of the last delivered one are held back and only the latest of them is delivered once the `window` expired.
+pub fn debounce(input: Receiver<ChannelPayload>, window: Duration) -> Receiver<ChannelPayload> {
+    let (sender, receiver) = channel(25);
+    task::spawn(debounce_task(input, sender, window));
+    receiver
+}
+
+async fn debounce_task(
+    mut input: Receiver<ChannelPayload>,
+    output: Sender<ChannelPayload>,
+    window: Duration,
+) {
+    let mut topics = HashMap::<String, TopicState>::new();
+    loop {
+        let next_deadline = topics
+            .values()
+            .filter(|state| state.pending.is_some())
+            .map(|state| state.last_delivered + window)
+            .min();
+
+        tokio::select! {
+            received = input.recv() => {
+                let Some((topic, payload)) = received else {
+                    break;
+                };
+                let now = Instant::now();
+                match topics.get_mut(&topic) {
+                    Some(state) if now < state.last_delivered + window => {
+                        state.pending = Some(payload);
+                    }
+                    _ => {
+                        topics.insert(topic.clone(), TopicState { last_delivered: now, pending: None });
+                        if output.send((topic, payload)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            () = sleep_until(next_deadline.unwrap_or_else(Instant::now)), if next_deadline.is_some() => {
+                let now = Instant::now();
+                for (topic, state) in &mut topics {
+                    if now < state.last_delivered + window {
+                        continue;
+                    }
+                    if let Some(payload) = state.pending.take() {
+                        state.last_delivered = now;
+                        if output.send((topic.clone(), payload)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                topics.retain(|_, state| state.pending.is_some() || now < state.last_delivered + window);
+            }
+        }
+    }
+
+    // Input is gone, deliver what is still held back
+    #[allow(clippy::iter_over_hash_type)]
+    for (topic, state) in topics {
+        if let Some(payload) = state.pending {
+            if output.send((topic, payload)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::advance;
+
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(2);
+
+    fn message(topic: &str, payload: &str) -> ChannelPayload {
+        (topic.to_owned(), payload.to_owned())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn leading_message_is_delivered_immediately() {
+        let (sender, input) = channel(25);
+        let mut output = debounce(input, WINDOW);
+        let start = Instant::now();
+        sender.send(message("foo", "true")).await.unwrap();
+        assert_eq!(output.recv().await, Some(message("foo", "true")));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn trailing_message_is_delivered_after_window() {
+        let (sender, input) = channel(25);
+        let mut output = debounce(input, WINDOW);
+        let start = Instant::now();
+        sender.send(message("foo", "1")).await.unwrap();
+        assert_eq!(output.recv().await, Some(message("foo", "1")));
+
+        advance(Duration::from_millis(500)).await;
+        sender.send(message("foo", "2")).await.unwrap();
+        sender.send(message("foo", "3")).await.unwrap();
+
+        assert_eq!(output.recv().await, Some(message("foo", "3")));
+        assert_eq!(start.elapsed(), WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn topics_are_independent() {
+        let (sender, input) = channel(25);
+        let mut output = debounce(input, WINDOW);
+        sender.send(message("foo", "1")).await.unwrap();
+        sender.send(message("foo", "2")).await.unwrap();
+        sender.send(message("bar", "1")).await.unwrap();
+        assert_eq!(output.recv().await, Some(message("foo", "1")));
+        assert_eq!(output.recv().await, Some(message("bar", "1")));
+        assert_eq!(output.recv().await, Some(message("foo", "2")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn message_after_window_is_leading_again() {
+        let (sender, input) = channel(25);
+        let mut output = debounce(input, WINDOW);
+        sender.send(message("foo", "1")).await.unwrap();
+        assert_eq!(output.recv().await, Some(message("foo", "1")));
+
+        advance(WINDOW * 2).await;
+        let before = Instant::now();
+        sender.send(message("foo", "2")).await.unwrap();
+        assert_eq!(output.recv().await, Some(message("foo", "2")));
+        assert_eq!(before.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_is_flushed_when_input_closes() {
+        let (sender, input) = channel(25);
+        let mut output = debounce(input, WINDOW);
+        sender.send(message("foo", "1")).await.unwrap();
+        sender.send(message("foo", "2")).await.unwrap();
+        drop(sender);
+        assert_eq!(output.recv().await, Some(message("foo", "1")));
+        assert_eq!(output.recv().await, Some(message("foo", "2")));
+        assert_eq!(output.recv().await, None);
+    }
+}
@@ -0,0 +1,100 @@
+use core::fmt;
+use core::fmt::Write as _;
+use std::sync::atomic::Ordering;
+
+use crate::MqttSmarthome;
+
+/// Value behind a lock which is `None` when the lock is currently held elsewhere.
+struct OrLocked<T>(Option<T>);
+
+impl<T: fmt::Debug> fmt::Debug for OrLocked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => f.write_str("<locked>"),
+        }
+    }
+}
+
+impl fmt::Debug for MqttSmarthome {
+    /// Never blocks on the internal locks, values currently locked elsewhere are shown as `<locked>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subscriptions = self.subscribed.try_read().ok().map(|set| set.len());
+        let watchers = self.watchers.try_read().ok().map(|list| list.len());
+        let history_entries = self.history.try_len();
+        f.debug_struct("MqttSmarthome")
+            .field("last_will_topic", &self.last_will_topic)
+            .field("last_will_retain", &self.last_will_retain)
+            .field("connected", &self.connected.load(Ordering::Relaxed))
+            .field("subscriptions", &OrLocked(subscriptions))
+            .field("watchers", &OrLocked(watchers))
+            .field("history_entries", &OrLocked(history_entries))
+            .field("last_received_ago", &self.last_received_ago())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MqttSmarthome {
+    /// Human readable overview of the client state including every subscription.
+    ///
+    /// Unlike the `Debug` output this waits for the internal locks.
+    pub async fn debug_report(&self) -> String {
+        let mut report = String::new();
+        _ = writeln!(report, "last will topic: {}", self.last_will_topic);
+        _ = writeln!(report, "last will retain: {}", self.last_will_retain);
+        _ = writeln!(
+            report,
+            "connected: {}",
+            self.connected.load(Ordering::Relaxed)
+        );
+        _ = writeln!(
+            report,
+            "last received: {:?} ago",
+            self.since_last_received().await
+        );
+        _ = writeln!(report, "watchers: {}", self.watchers.read().await.len());
+        _ = writeln!(report, "history entries: {}", self.history.len());
+        let subscriptions = self.subscriptions().await;
+        _ = writeln!(report, "subscriptions: {}", subscriptions.len());
+        for subscription in subscriptions {
+            _ = writeln!(report, "  {subscription}");
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn debug_shows_counts() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.subscribe("foo/#").await;
+        handle_incoming(&smarthome, "foo/bar".to_owned(), "1", false).await;
+        let debug = format!("{smarthome:?}");
+        assert!(debug.contains("last_will_topic: \"test/connected\""));
+        assert!(debug.contains("subscriptions: 1"));
+        assert!(debug.contains("history_entries: 1"));
+        assert!(debug.contains("last_received_ago: Some("));
+    }
+
+    #[tokio::test]
+    async fn debug_does_not_block_on_locks() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let _watchers = smarthome.watchers.write().await;
+        let debug = format!("{smarthome:?}");
+        assert!(debug.contains("watchers: <locked>"));
+        assert!(debug.contains("subscriptions: 0"));
+    }
+
+    #[tokio::test]
+    async fn report_lists_subscriptions() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.subscribe("b").await;
+        smarthome.subscribe("a/#").await;
+        let report = smarthome.debug_report().await;
+        assert!(report.contains("subscriptions: 2\n  a/#\n  b\n"));
+    }
+}
@@ -0,0 +1,207 @@
+//! [Home Assistant MQTT discovery](https://www.home-assistant.io/integrations/mqtt/#mqtt-discovery) configs.
+//!
+//! Configs are announced with [`announce`](crate::MqttSmarthome::announce) and removed again with [`retract`](crate::MqttSmarthome::retract).
+
+use crate::json::escape_json_string;
+use crate::{MqttSmarthome, PublishError};
+
+/// Config which can be announced to Home Assistant.
+pub trait DiscoveryConfig {
+    /// The config as JSON object using the field names of Home Assistant.
+    fn to_json(&self) -> String;
+}
+
+/// Encode the fields as JSON object skipping the absent ones.
+fn json_object(fields: &[(&str, Option<&str>)]) -> String {
+    let fields = fields
+        .iter()
+        .filter_map(|(key, value)| {
+            value.map(|value| format!("{}:{}", escape_json_string(key), escape_json_string(value)))
+        })
+        .collect::<Vec<_>>();
+    format!("{{{}}}", fields.join(","))
+}
+
+/// [MQTT Sensor](https://www.home-assistant.io/integrations/sensor.mqtt/)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SensorConfig {
+    pub name: String,
+    pub state_topic: String,
+    pub unique_id: Option<String>,
+    /// For example `temperature` or `humidity`.
+    pub device_class: Option<String>,
+    pub unit_of_measurement: Option<String>,
+    pub value_template: Option<String>,
+}
+
+impl DiscoveryConfig for SensorConfig {
+    fn to_json(&self) -> String {
+        json_object(&[
+            ("name", Some(&self.name)),
+            ("state_topic", Some(&self.state_topic)),
+            ("unique_id", self.unique_id.as_deref()),
+            ("device_class", self.device_class.as_deref()),
+            ("unit_of_measurement", self.unit_of_measurement.as_deref()),
+            ("value_template", self.value_template.as_deref()),
+        ])
+    }
+}
+
+/// [MQTT Binary sensor](https://www.home-assistant.io/integrations/binary_sensor.mqtt/)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BinarySensorConfig {
+    pub name: String,
+    pub state_topic: String,
+    pub unique_id: Option<String>,
+    /// For example `door` or `motion`.
+    pub device_class: Option<String>,
+    /// Home Assistant defaults to `ON`.
+    pub payload_on: Option<String>,
+    /// Home Assistant defaults to `OFF`.
+    pub payload_off: Option<String>,
+}
+
+impl DiscoveryConfig for BinarySensorConfig {
+    fn to_json(&self) -> String {
+        json_object(&[
+            ("name", Some(&self.name)),
+            ("state_topic", Some(&self.state_topic)),
+            ("unique_id", self.unique_id.as_deref()),
+            ("device_class", self.device_class.as_deref()),
+            ("payload_on", self.payload_on.as_deref()),
+            ("payload_off", self.payload_off.as_deref()),
+        ])
+    }
+}
+
+/// [MQTT Switch](https://www.home-assistant.io/integrations/switch.mqtt/)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SwitchConfig {
+    pub name: String,
+    pub command_topic: String,
+    pub state_topic: Option<String>,
+    pub unique_id: Option<String>,
+    /// Home Assistant defaults to `ON`.
+    pub payload_on: Option<String>,
+    /// Home Assistant defaults to `OFF`.
+    pub payload_off: Option<String>,
+}
+
+impl DiscoveryConfig for SwitchConfig {
+    fn to_json(&self) -> String {
+        json_object(&[
+            ("name", Some(&self.name)),
+            ("command_topic", Some(&self.command_topic)),
+            ("state_topic", self.state_topic.as_deref()),
+            ("unique_id", self.unique_id.as_deref()),
+            ("payload_on", self.payload_on.as_deref()),
+            ("payload_off", self.payload_off.as_deref()),
+        ])
+    }
+}
+
+impl MqttSmarthome {
+    /// Node id used in the discovery topics, derived from the base topic.
+    fn discovery_node_id(&self) -> String {
+        self.base_topic
+            .chars()
+            .map(|char| {
+                if char.is_ascii_alphanumeric() || char == '-' || char == '_' {
+                    char
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+
+    fn discovery_topic(&self, component: &str, object_id: &str) -> String {
+        let node_id = self.discovery_node_id();
+        format!("homeassistant/{component}/{node_id}/{object_id}/config")
+    }
+
+    /// Publish the retained discovery `config` to `homeassistant/{component}/{node_id}/{object_id}/config`.
+    ///
+    /// The `node_id` is derived from the base topic.
+    ///
+    /// # Errors
+    /// Returns an error when the topic is invalid or the client request queue is gone.
+    pub async fn announce(
+        &self,
+        component: &str,
+        object_id: &str,
+        config: &(impl DiscoveryConfig + Sync),
+    ) -> Result<(), PublishError> {
+        let topic = self.discovery_topic(component, object_id);
+        self.publish(&topic, config.to_json(), true).await
+    }
+
+    /// Remove the discovery config announced with [`announce`](Self::announce).
+    ///
+    /// # Errors
+    /// Returns an error when the topic is invalid or the client request queue is gone.
+    pub async fn retract(&self, component: &str, object_id: &str) -> Result<(), PublishError> {
+        let topic = self.discovery_topic(component, object_id);
+        self.clear_retained(&topic).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensor_skips_absent_fields() {
+        let config = SensorConfig {
+            name: "Temperature".to_owned(),
+            state_topic: "hue/status/temp".to_owned(),
+            unit_of_measurement: Some("°C".to_owned()),
+            ..SensorConfig::default()
+        };
+        assert_eq!(
+            config.to_json(),
+            r#"{"name":"Temperature","state_topic":"hue/status/temp","unit_of_measurement":"°C"}"#
+        );
+    }
+
+    #[test]
+    fn values_are_escaped() {
+        let config = BinarySensorConfig {
+            name: "Say \"hi\"".to_owned(),
+            state_topic: "a".to_owned(),
+            ..BinarySensorConfig::default()
+        };
+        assert_eq!(
+            config.to_json(),
+            r#"{"name":"Say \"hi\"","state_topic":"a"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn announce_and_retract() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let config = SwitchConfig {
+            name: "Lamp".to_owned(),
+            command_topic: "test/set/lamp".to_owned(),
+            ..SwitchConfig::default()
+        };
+        smarthome.announce("switch", "lamp", &config).await.unwrap();
+        let topic = "homeassistant/switch/test/lamp/config";
+        let entry = smarthome.last(topic).await.unwrap();
+        assert_eq!(entry.payload(), config.to_json());
+        assert!(entry.retained());
+
+        smarthome.retract("switch", "lamp").await.unwrap();
+        assert!(smarthome.last(topic).await.is_none());
+    }
+
+    #[test]
+    fn node_id_is_sanitized() {
+        let mqttoptions = crate::protocol::MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, _eventloop) = MqttSmarthome::new_without_eventloop(
+            crate::LastWillConfig::new("my home/pi.1/connected".to_owned(), false),
+            mqttoptions,
+        );
+        assert_eq!(smarthome.discovery_node_id(), "my_home_pi_1");
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::atomic::Ordering;
+use std::sync::PoisonError;
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::watcher::ChannelPayload;
+use crate::{logging, MqttSmarthome};
+
+impl MqttSmarthome {
+    /// Only pretend to publish. Disabled by default and can be toggled at any time.
+    ///
+    /// While enabled, publishes made via this client are not sent to the broker.
+    /// They are logged, announced via [`dry_run_publishes`](Self::dry_run_publishes) and still inserted into the history so following logic behaves as if they were published.
+    /// Subscriptions, incoming messages and the messages of the client itself like the birth message and availability are unaffected.
+    pub fn set_dry_run(&self, enabled: bool) {
+        self.dry_run.store(enabled, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.load(Ordering::Relaxed)
+    }
+
+    /// Get the `(topic, payload)` of every publish skipped by the [dry run](Self::set_dry_run).
+    ///
+    /// Publishes are skipped while the channel is full.
+    #[must_use]
+    pub fn dry_run_publishes(&self) -> Receiver<ChannelPayload> {
+        let (sender, receiver) = channel(100);
+        self.dry_run_publishes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    /// Handle the publish instead of sending it when in dry run mode.
+    ///
+    /// Returns false when the message should actually be published.
+    pub(crate) async fn dry_run_publish(&self, topic: &str, payload: &[u8], retain: bool) -> bool {
+        if !self.announce_dry_run(topic, payload, retain) {
+            return false;
+        }
+        self.insert_history(topic, payload, retain).await;
+        true
+    }
+
+    /// Log and announce the publish when in dry run mode without touching the history.
+    ///
+    /// Returns false when the message should actually be published.
+    pub(crate) fn announce_dry_run(&self, topic: &str, payload: &[u8], retain: bool) -> bool {
+        if !self.is_dry_run() {
+            return false;
+        }
+        let payload = String::from_utf8_lossy(payload);
+        logging::status!(topic = topic, retain = retain; "MQTT dry run publish {topic} (retain {retain}): {payload}");
+        self.dry_run_publishes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|sender| {
+                match sender.try_send((topic.to_owned(), payload.clone().into_owned())) {
+                    Ok(()) | Err(TrySendError::Full(_)) => true,
+                    Err(TrySendError::Closed(_)) => false,
+                }
+            });
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{MqttOptions, Request};
+    use crate::{LastWillConfig, MqttSmarthome};
+
+    fn smarthome_with_requests() -> (MqttSmarthome, crate::protocol::EventLoop) {
+        MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            MqttOptions::new("test", "localhost", 1883),
+        )
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_sending_but_records() {
+        let (smarthome, mut eventloop) = smarthome_with_requests();
+        smarthome.set_dry_run(true);
+        let mut publishes = smarthome.dry_run_publishes();
+        smarthome.publish("lamp/set", "on", true).await.unwrap();
+        smarthome
+            .publish_many(vec![("a".to_owned(), "1".to_owned(), false)])
+            .await
+            .unwrap();
+        smarthome
+            .publish_with_retry("b", "2", false, 1, core::time::Duration::ZERO)
+            .await
+            .unwrap();
+        smarthome.clear_retained("lamp/set").await.unwrap();
+
+        eventloop.clean();
+        assert!(eventloop.pending.is_empty(), "{:?}", eventloop.pending);
+        assert_eq!(smarthome.last("a").await.unwrap().payload(), "1");
+        assert_eq!(smarthome.last("b").await.unwrap().payload(), "2");
+        assert!(smarthome.last("lamp/set").await.is_none());
+        assert_eq!(smarthome.metrics().messages_published, 0);
+        for expected in [("lamp/set", "on"), ("a", "1"), ("b", "2"), ("lamp/set", "")] {
+            let (topic, payload) = publishes.try_recv().unwrap();
+            assert_eq!((topic.as_str(), payload.as_str()), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_can_be_toggled() {
+        let (smarthome, mut eventloop) = smarthome_with_requests();
+        smarthome.set_dry_run(true);
+        smarthome.publish("foo", "1", false).await.unwrap();
+        assert!(smarthome.is_dry_run());
+        smarthome.set_dry_run(false);
+        smarthome.publish("foo", "2", false).await.unwrap();
+
+        eventloop.clean();
+        let requests = eventloop.pending.drain(..).collect::<Vec<_>>();
+        assert_eq!(requests.len(), 1, "{requests:?}");
+        let Request::Publish(publish) = &requests[0] else {
+            panic!("expected a publish: {requests:?}");
+        };
+        assert_eq!(&publish.payload[..], b"2");
+    }
+}
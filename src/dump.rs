@@ -0,0 +1,151 @@
+use core::fmt::Write as _;
+use core::time::Duration;
+use std::time::SystemTime;
+
+use crate::{HistoryEntry, MqttSmarthome};
+
+/// Format a duration with its two most significant units like `3m12s` or `2d5h`.
+pub fn humanize_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86400, 'd'),
+        (secs / 3600 % 24, 'h'),
+        (secs / 60 % 60, 'm'),
+        (secs % 60, 's'),
+    ];
+    let first = units
+        .iter()
+        .position(|(value, _)| *value > 0)
+        .unwrap_or(units.len() - 1);
+    let mut humanized = String::new();
+    for (value, unit) in units[first..].iter().take(2) {
+        if *value > 0 || first == units.len() - 1 {
+            _ = write!(humanized, "{value}{unit}");
+        }
+    }
+    humanized
+}
+
+fn truncate(payload: &str, max_len: usize) -> String {
+    if payload.chars().count() <= max_len {
+        return payload.to_owned();
+    }
+    let mut truncated = payload.chars().take(max_len).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+fn format_lines(
+    entries: &[(String, HistoryEntry)],
+    max_payload_len: usize,
+    now: SystemTime,
+) -> String {
+    let width = entries
+        .iter()
+        .map(|(topic, _)| topic.len())
+        .max()
+        .unwrap_or_default();
+    let mut dump = String::new();
+    for (topic, entry) in entries {
+        _ = writeln!(
+            dump,
+            "{topic:width$} {:>6} {}",
+            humanize_duration(entry.age_at(now)),
+            truncate(&entry.payload(), max_payload_len),
+        );
+    }
+    dump
+}
+
+impl MqttSmarthome {
+    /// Human readable overview of the history with one line per topic sorted by topic.
+    ///
+    /// Each line contains the topic, the age of the last entry and its payload truncated to `max_payload_len` characters.
+    pub async fn dump_history(&self, max_payload_len: usize) -> String {
+        let mut entries = self
+            .history_snapshot()
+            .await
+            .into_iter()
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        format_lines(&entries, max_payload_len, self.now())
+    }
+
+    /// Same as [`dump_history`](Self::dump_history) but sorted by age with the oldest entry first.
+    pub async fn dump_history_by_age(&self, max_payload_len: usize) -> String {
+        let mut entries = self
+            .history_snapshot()
+            .await
+            .into_iter()
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|(a_topic, a), (b_topic, b)| {
+            a.time().cmp(&b.time()).then_with(|| a_topic.cmp(b_topic))
+        });
+        format_lines(&entries, max_payload_len, self.now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case(0, "0s")]
+    #[case(5, "5s")]
+    #[case(60, "1m")]
+    #[case(192, "3m12s")]
+    #[case(3600 + 5, "1h")]
+    #[case(2 * 3600 + 5 * 60 + 7, "2h5m")]
+    #[case(86400 + 3 * 3600, "1d3h")]
+    #[case(40 * 86400, "40d")]
+    fn humanize(#[case] secs: u64, #[case] expected: &str) {
+        assert_eq!(humanize_duration(Duration::from_secs(secs)), expected);
+    }
+
+    #[test]
+    fn humanize_ignores_subsecond() {
+        assert_eq!(humanize_duration(Duration::from_millis(1500)), "1s");
+    }
+
+    #[rstest]
+    #[case("short", 10, "short")]
+    #[case("exactly", 7, "exactly")]
+    #[case("too long", 3, "too…")]
+    #[case("äöü", 2, "äö…")]
+    fn truncates(#[case] payload: &str, #[case] max_len: usize, #[case] expected: &str) {
+        assert_eq!(truncate(payload, max_len), expected);
+    }
+
+    #[tokio::test]
+    async fn dump_sorted_by_topic() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .history
+            .insert_at("b", "1", SystemTime::now() - Duration::from_secs(5));
+        smarthome.history.insert_at(
+            "aa",
+            "something long",
+            SystemTime::now() - Duration::from_secs(192),
+        );
+        assert_eq!(
+            smarthome.dump_history(4).await,
+            "aa  3m12s some…\nb      5s 1\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn dump_sorted_by_age() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .history
+            .insert_at("a", "1", SystemTime::now() - Duration::from_secs(5));
+        smarthome
+            .history
+            .insert_at("b", "2", SystemTime::now() - Duration::from_secs(100));
+        let dump = smarthome.dump_history_by_age(10).await;
+        let topics = dump.lines().map(|line| &line[..1]).collect::<Vec<_>>();
+        assert_eq!(topics, ["b", "a"]);
+    }
+}
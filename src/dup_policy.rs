@@ -0,0 +1,160 @@
+use std::collections::VecDeque;
+use std::sync::PoisonError;
+
+use crate::protocol::Publish;
+use crate::MqttSmarthome;
+
+/// Amount of packet ids remembered by [`DupPolicy::DeliverOnceByPkid`].
+const RECENT_PKIDS: u16 = 64;
+
+/// Handling of publishes the broker marked as duplicate, see [`set_dup_policy`](MqttSmarthome::set_dup_policy).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DupPolicy {
+    /// Skip every duplicate.
+    /// The original delivery might have been lost before it was acknowledged, so the message might never arrive.
+    #[default]
+    Drop,
+    /// Deliver every duplicate, which might result in a message being handled twice.
+    Deliver,
+    /// Deliver duplicates unless a message with the same packet id was received recently.
+    DeliverOnceByPkid,
+}
+
+#[derive(Debug, Default)]
+pub struct DupFilter {
+    policy: DupPolicy,
+    /// Most recently received packet ids, the most recent last
+    recent: VecDeque<u16>,
+}
+
+impl DupFilter {
+    const fn new(policy: DupPolicy) -> Self {
+        Self {
+            policy,
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// Whether the publish with the `pkid` is delivered.
+    fn deliver(&mut self, pkid: u16, dup: bool) -> bool {
+        match self.policy {
+            DupPolicy::Drop => !dup,
+            DupPolicy::Deliver => true,
+            // QoS 0 messages have no packet id and are never duplicates
+            DupPolicy::DeliverOnceByPkid if pkid == 0 => true,
+            DupPolicy::DeliverOnceByPkid => {
+                let seen = self.recent.iter().position(|recent| *recent == pkid);
+                if let Some(index) = seen {
+                    self.recent.remove(index);
+                }
+                self.recent.push_back(pkid);
+                if self.recent.len() > usize::from(RECENT_PKIDS) {
+                    self.recent.pop_front();
+                }
+                !dup || seen.is_none()
+            }
+        }
+    }
+}
+
+impl MqttSmarthome {
+    /// Choose how publishes marked as duplicate (DUP) by the broker are handled.
+    /// The default [`DupPolicy::Drop`] skips them.
+    ///
+    /// Changing the policy forgets the recently received packet ids.
+    /// Channels with [manual acknowledgement](Self::subscribe_channel_manual_ack) get duplicates regardless of the policy.
+    pub fn set_dup_policy(&self, policy: DupPolicy) {
+        *self
+            .dup_filter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = DupFilter::new(policy);
+    }
+
+    pub(crate) fn deliver_publish(&self, publish: &Publish) -> bool {
+        self.dup_filter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .deliver(publish.pkid, publish.dup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{self, QoS, Request};
+
+    #[rstest::rstest]
+    #[case::drop(DupPolicy::Drop, [true, false, true, false])]
+    #[case::deliver(DupPolicy::Deliver, [true, true, true, true])]
+    #[case::once(DupPolicy::DeliverOnceByPkid, [true, true, true, false])]
+    fn policy_works(#[case] policy: DupPolicy, #[case] expected: [bool; 4]) {
+        let mut filter = DupFilter::new(policy);
+        let delivered = [(1, false), (2, true), (3, false), (3, true)]
+            .map(|(pkid, dup)| filter.deliver(pkid, dup));
+        assert_eq!(delivered, expected);
+    }
+
+    #[test]
+    fn duplicate_of_duplicate_is_dropped() {
+        let mut filter = DupFilter::new(DupPolicy::DeliverOnceByPkid);
+        assert!(filter.deliver(5, true));
+        assert!(!filter.deliver(5, true));
+    }
+
+    #[test]
+    fn reused_pkid_is_delivered() {
+        let mut filter = DupFilter::new(DupPolicy::DeliverOnceByPkid);
+        assert!(filter.deliver(5, false));
+        assert!(filter.deliver(5, false));
+    }
+
+    #[test]
+    fn qos0_is_not_remembered() {
+        let mut filter = DupFilter::new(DupPolicy::DeliverOnceByPkid);
+        assert!(filter.deliver(0, false));
+        assert!(filter.deliver(0, true));
+        assert!(filter.recent.is_empty());
+    }
+
+    #[test]
+    fn oldest_pkid_is_evicted() {
+        let mut filter = DupFilter::new(DupPolicy::DeliverOnceByPkid);
+        for pkid in 1..=RECENT_PKIDS {
+            filter.deliver(pkid, false);
+        }
+        filter.deliver(1000, false);
+        assert_eq!(filter.recent.len(), usize::from(RECENT_PKIDS));
+        assert!(filter.deliver(1, true), "evicted pkid is delivered again");
+        assert!(!filter.deliver(RECENT_PKIDS, true));
+    }
+
+    #[test]
+    fn seen_pkid_is_refreshed() {
+        let mut filter = DupFilter::new(DupPolicy::DeliverOnceByPkid);
+        for pkid in 1..=RECENT_PKIDS {
+            filter.deliver(pkid, false);
+        }
+        // Refreshing 1 makes 2 the least recently seen
+        assert!(!filter.deliver(1, true));
+        filter.deliver(1000, false);
+        assert!(!filter.deliver(1, true));
+        assert!(filter.deliver(2, true));
+    }
+
+    #[test]
+    fn changing_policy_forgets_pkids() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_dup_policy(DupPolicy::DeliverOnceByPkid);
+        let Request::Publish(mut publish) =
+            protocol::publish_request("foo", QoS::AtLeastOnce, "1", false)
+        else {
+            unreachable!();
+        };
+        publish.pkid = 7;
+        assert!(smarthome.deliver_publish(&publish));
+        publish.dup = true;
+        assert!(!smarthome.deliver_publish(&publish));
+        smarthome.set_dup_policy(DupPolicy::DeliverOnceByPkid);
+        assert!(smarthome.deliver_publish(&publish));
+    }
+}
@@ -0,0 +1,248 @@
+use core::fmt;
+
+/// Error of publishing a message.
+#[derive(Debug)]
+pub enum PublishError {
+    /// The topic is empty, contains wildcards or is reserved by starting with `$`.
+    InvalidTopic { topic: String },
+    /// The client failed to queue the message, for example because the eventloop is gone.
+    Client(crate::protocol::ClientError),
+}
+
+impl PublishError {
+    pub(crate) fn check_topic(topic: &str) -> Result<(), Self> {
+        if topic.is_empty() || topic.starts_with('$') || !rumqttc::mqttbytes::valid_topic(topic) {
+            return Err(Self::InvalidTopic {
+                topic: topic.to_owned(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTopic { topic } => write!(f, "invalid topic to publish to: {topic:?}"),
+            Self::Client(error) => write!(f, "failed to publish: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidTopic { .. } => None,
+            Self::Client(error) => Some(error),
+        }
+    }
+}
+
+impl From<crate::protocol::ClientError> for PublishError {
+    fn from(error: crate::protocol::ClientError) -> Self {
+        Self::Client(error)
+    }
+}
+
+/// Why the connection to the broker failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionFailure {
+    /// The broker rejected the credentials or does not allow this client. Retrying does not help.
+    AuthenticationFailed,
+    /// The broker refused the connection for another reason, for example because it is unavailable.
+    Refused,
+    /// The broker could not be reached or the connection broke.
+    Network,
+    /// Anything else like a protocol violation.
+    Other,
+}
+
+impl fmt::Display for ConnectionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AuthenticationFailed => "authentication with the broker failed",
+            Self::Refused => "broker refused the connection",
+            Self::Network => "connection to the broker failed",
+            Self::Other => "connection to the broker failed unexpectedly",
+        })
+    }
+}
+
+impl std::error::Error for ConnectionFailure {}
+
+/// Error of [`subscribe_confirmed`](crate::MqttSmarthome::subscribe_confirmed).
+#[derive(Debug)]
+pub enum SubscribeError {
+    /// The client failed to queue the subscription, for example because the eventloop is gone.
+    Client(crate::protocol::ClientError),
+    /// The broker rejected the subscription, for example because of its access control.
+    Rejected {
+        filter: String,
+        code: crate::protocol::SubscribeReasonCode,
+    },
+    /// The connection was lost before the broker acknowledged the subscription.
+    ///
+    /// The subscription is still sent again on the next connection.
+    ConnectionLost,
+}
+
+impl fmt::Display for SubscribeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Client(error) => write!(f, "failed to subscribe: {error}"),
+            Self::Rejected { filter, code } => {
+                write!(f, "subscription to {filter:?} was rejected: {code:?}")
+            }
+            Self::ConnectionLost => {
+                f.write_str("connection was lost before the subscription was acknowledged")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SubscribeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Client(error) => Some(error),
+            Self::Rejected { .. } | Self::ConnectionLost => None,
+        }
+    }
+}
+
+impl From<crate::protocol::ClientError> for SubscribeError {
+    fn from(error: crate::protocol::ClientError) -> Self {
+        Self::Client(error)
+    }
+}
+
+/// Error of [`set_and_confirm`](crate::MqttSmarthome::set_and_confirm).
+#[derive(Debug)]
+pub enum SetError {
+    /// Publishing to the set topic failed.
+    Publish(PublishError),
+    /// The status topic did not report anything within the time.
+    TimedOut,
+    /// The status topic reported a value differing from the requested one.
+    Differs { reported: String },
+}
+
+impl fmt::Display for SetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Publish(error) => error.fmt(f),
+            Self::TimedOut => f.write_str("status was not reported in time"),
+            Self::Differs { reported } => {
+                write!(f, "status reported a different value: {reported}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Publish(error) => Some(error),
+            Self::TimedOut | Self::Differs { .. } => None,
+        }
+    }
+}
+
+impl From<PublishError> for SetError {
+    fn from(error: PublishError) -> Self {
+        Self::Publish(error)
+    }
+}
+
+/// Error of [`publish_json`](crate::MqttSmarthome::publish_json).
+// Same size as the PublishError returned by every other publish
+#[cfg(feature = "json")]
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+pub enum PublishJsonError {
+    /// The value could not be serialized, for example because a map has non-string keys.
+    Serialize(serde_json::Error),
+    Publish(PublishError),
+}
+
+#[cfg(feature = "json")]
+impl fmt::Display for PublishJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serialize(error) => write!(f, "failed to serialize the payload: {error}"),
+            Self::Publish(error) => error.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl std::error::Error for PublishJsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Serialize(error) => Some(error),
+            Self::Publish(error) => Some(error),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for PublishJsonError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Serialize(error)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<PublishError> for PublishJsonError {
+    fn from(error: PublishError) -> Self {
+        Self::Publish(error)
+    }
+}
+
+/// Error of [`publish_many`](crate::MqttSmarthome::publish_many).
+#[derive(Debug)]
+pub struct PublishManyError {
+    /// Index of the message which failed. Messages before it were published.
+    pub index: usize,
+    pub error: PublishError,
+}
+
+impl fmt::Display for PublishManyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to publish message {}: {}",
+            self.index, self.error
+        )
+    }
+}
+
+impl std::error::Error for PublishManyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case("foo/bar", true)]
+    #[case("foo bar/baz", true)]
+    #[case("", false)]
+    #[case("foo/+/bar", false)]
+    #[case("foo/#", false)]
+    #[case("$SYS/broker", false)]
+    fn check_topic(#[case] topic: &str, #[case] expected: bool) {
+        assert_eq!(PublishError::check_topic(topic).is_ok(), expected);
+    }
+
+    #[test]
+    fn invalid_topic_display_contains_topic() {
+        let error = PublishError::check_topic("light/+/set").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid topic to publish to: \"light/+/set\""
+        );
+    }
+}
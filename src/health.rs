@@ -0,0 +1,70 @@
+use core::time::Duration;
+use std::sync::atomic::Ordering;
+
+use crate::MqttSmarthome;
+
+/// Overview of the client state. See [`health`](MqttSmarthome::health).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// The connection to the broker is established.
+    pub connected: bool,
+    /// Time since the last received message.
+    pub last_received: Option<Duration>,
+    pub subscription_count: usize,
+    /// Connected and the last message was received recently enough.
+    pub healthy: bool,
+}
+
+impl MqttSmarthome {
+    /// Check the state of the client.
+    ///
+    /// It is considered healthy when connected and the last message was received within `max_quiet`.
+    pub async fn health(&self, max_quiet: Duration) -> Health {
+        let connected = self.connected.load(Ordering::Relaxed);
+        let last_received = self.since_last_received().await;
+        let subscription_count = self.subscribed.read().await.len();
+        let healthy = connected && last_received.is_some_and(|ago| ago <= max_quiet);
+        Health {
+            connected,
+            last_received,
+            subscription_count,
+            healthy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn not_connected_is_unhealthy() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.subscribe("foo").await;
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        let health = smarthome.health(Duration::from_mins(1)).await;
+        assert!(!health.connected);
+        assert!(health.last_received.is_some());
+        assert_eq!(health.subscription_count, 1);
+        assert!(!health.healthy);
+    }
+
+    #[tokio::test]
+    async fn nothing_received_is_unhealthy() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.connected.store(true, Ordering::Relaxed);
+        let health = smarthome.health(Duration::from_mins(1)).await;
+        assert_eq!(health.last_received, None);
+        assert!(!health.healthy);
+    }
+
+    #[tokio::test]
+    async fn connected_and_received_is_healthy() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.connected.store(true, Ordering::Relaxed);
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        let health = smarthome.health(Duration::from_mins(1)).await;
+        assert!(health.healthy);
+    }
+}
@@ -0,0 +1,653 @@
+use core::time::Duration;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+use crate::clock::SharedClock;
+use crate::HistoryEntry;
+
+/// Amount of independently locked parts of the [`History`].
+const SHARDS: usize = 16;
+
+/// Last known entries per topic with optional limits.
+///
+/// The topics are spread over shards by their hash.
+/// Writing a topic only locks its shard so reading other topics is not blocked.
+/// The locks are never held across an `.await`.
+#[derive(Debug)]
+pub struct History {
+    shards: [RwLock<Shard>; SHARDS],
+    hasher: RandomState,
+    clock: SharedClock,
+    enabled: AtomicBool,
+    limits: RwLock<Limits>,
+    next_expire_check: Mutex<Option<SystemTime>>,
+    /// Every topic by the time of its newest entry, oldest first.
+    ///
+    /// Only locked while holding the lock of the shard of the topic or no shard at all.
+    ages: Mutex<BTreeSet<(SystemTime, String)>>,
+}
+
+#[derive(Debug, Default)]
+struct Shard {
+    /// Newest entry per topic
+    entries: HashMap<String, HistoryEntry>,
+    /// Older entries of topics keeping more than one entry, oldest first
+    older: HashMap<String, VecDeque<HistoryEntry>>,
+}
+
+#[derive(Debug, Default)]
+struct Limits {
+    entries_per_topic: usize,
+    entries_per_topic_prefix: Vec<(String, usize)>,
+    max_entries: Option<usize>,
+    max_age: Option<Duration>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::with_clock(SharedClock::default())
+    }
+}
+
+impl History {
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Self {
+            shards: core::array::from_fn(|_| RwLock::default()),
+            hasher: RandomState::new(),
+            clock,
+            enabled: AtomicBool::new(true),
+            limits: RwLock::default(),
+            next_expire_check: Mutex::new(None),
+            ages: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl Limits {
+    fn entries_per_topic(&self, topic: &str) -> usize {
+        self.entries_per_topic_prefix
+            .iter()
+            .filter(|(prefix, _)| topic.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.entries_per_topic, |(_, amount)| *amount)
+            .max(1)
+    }
+
+    fn is_expired(&self, entry: &HistoryEntry, now: SystemTime) -> bool {
+        self.max_age.is_some_and(|max_age| {
+            now.duration_since(entry.time())
+                .is_ok_and(|age| age > max_age)
+        })
+    }
+}
+
+impl Shard {
+    /// Insert the entry and return the previous one of the topic.
+    fn insert(
+        &mut self,
+        topic: String,
+        entry: HistoryEntry,
+        keep_older: usize,
+    ) -> Option<HistoryEntry> {
+        if keep_older == 0 {
+            self.older.remove(&topic);
+        }
+        let previous = self.entries.insert(topic.clone(), entry);
+        if let (Some(previous), true) = (&previous, keep_older > 0) {
+            let older = self.older.entry(topic).or_default();
+            older.push_back(previous.clone());
+            while older.len() > keep_older {
+                older.pop_front();
+            }
+        }
+        previous
+    }
+
+    /// Remove every entry of the topic and return the newest one.
+    fn remove(&mut self, topic: &str) -> Option<HistoryEntry> {
+        self.older.remove(topic);
+        self.entries.remove(topic)
+    }
+
+    /// Remove the expired entries and return the times and topics of the removed newest ones.
+    fn remove_expired(&mut self, limits: &Limits, now: SystemTime) -> Vec<(SystemTime, String)> {
+        let is_young = |entry: &HistoryEntry| !limits.is_expired(entry, now);
+        let mut removed = Vec::new();
+        self.entries.retain(|topic, entry| {
+            let keep = is_young(entry);
+            if !keep {
+                removed.push((entry.time(), topic.clone()));
+            }
+            keep
+        });
+        self.older.retain(|_, older| {
+            older.retain(is_young);
+            !older.is_empty()
+        });
+        removed
+    }
+}
+
+impl History {
+    fn limits(&self) -> RwLockReadGuard<'_, Limits> {
+        self.limits.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn shard_index(&self, topic: &str) -> usize {
+        let hash = self.hasher.hash_one(topic).to_le_bytes();
+        usize::from(hash[0]) % SHARDS
+    }
+
+    fn ages(&self) -> MutexGuard<'_, BTreeSet<(SystemTime, String)>> {
+        self.ages.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replace the age of the topic after its newest entry changed from `previous` to `time`.
+    fn update_age(&self, topic: String, previous: Option<&HistoryEntry>, time: SystemTime) {
+        let mut ages = self.ages();
+        if let Some(previous) = previous {
+            ages.remove(&(previous.time(), topic.clone()));
+        }
+        ages.insert((time, topic));
+    }
+
+    fn read(&self, topic: &str) -> RwLockReadGuard<'_, Shard> {
+        self.shards[self.shard_index(topic)]
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self, topic: &str) -> RwLockWriteGuard<'_, Shard> {
+        self.shards[self.shard_index(topic)]
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_shards(&self) -> impl Iterator<Item = RwLockReadGuard<'_, Shard>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn write_shards(&self) -> impl Iterator<Item = RwLockWriteGuard<'_, Shard>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.write().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Disabling removes every entry and makes inserts do nothing.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            for mut shard in self.write_shards() {
+                *shard = Shard::default();
+            }
+            self.ages().clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Limit the amount of topics and the age of the entries.
+    ///
+    /// When there are more topics than `max_entries` the ones with the oldest entries are removed first.
+    /// Entries older than `max_age` are treated as absent and removed eventually.
+    pub fn set_limits(&self, max_entries: Option<usize>, max_age: Option<Duration>) {
+        {
+            let mut limits = self.limits.write().unwrap_or_else(PoisonError::into_inner);
+            limits.max_entries = max_entries;
+            limits.max_age = max_age;
+        }
+        *self
+            .next_expire_check
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+        self.remove_expired(self.clock.now());
+        self.remove_above_max_entries();
+    }
+
+    /// Keep the given amount of entries per topic. At least one entry is always kept.
+    pub fn set_entries_per_topic(&self, entries_per_topic: usize) {
+        self.limits
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entries_per_topic = entries_per_topic;
+    }
+
+    /// Keep the given amount of entries for topics starting with the `prefix`.
+    ///
+    /// When multiple prefixes match a topic the longest one is used.
+    pub fn set_entries_per_topic_prefix(&self, prefix: &str, entries_per_topic: usize) {
+        let mut limits = self.limits.write().unwrap_or_else(PoisonError::into_inner);
+        limits
+            .entries_per_topic_prefix
+            .retain(|(existing, _)| existing != prefix);
+        limits
+            .entries_per_topic_prefix
+            .push((prefix.to_owned(), entries_per_topic));
+    }
+
+    pub fn get(&self, topic: &str) -> Option<HistoryEntry> {
+        self.get_and_then(topic, |entry| Some(entry.clone()))
+    }
+
+    /// Apply `f` to the newest entry of the topic while its shard is locked.
+    pub fn get_and_then<T>(
+        &self,
+        topic: &str,
+        f: impl FnOnce(&HistoryEntry) -> Option<T>,
+    ) -> Option<T> {
+        let now = self.clock.now();
+        let limits = self.limits();
+        self.read(topic)
+            .entries
+            .get(topic)
+            .filter(|entry| !limits.is_expired(entry, now))
+            .and_then(f)
+    }
+
+    /// Entries of the topic from oldest to newest.
+    pub fn history_of(&self, topic: &str) -> Vec<HistoryEntry> {
+        let now = self.clock.now();
+        let limits = self.limits();
+        let shard = self.read(topic);
+        shard
+            .older
+            .get(topic)
+            .into_iter()
+            .flatten()
+            .chain(shard.entries.get(topic))
+            .filter(|entry| !limits.is_expired(entry, now))
+            .cloned()
+            .collect()
+    }
+
+    /// Insert the entry and return the previous one of the topic.
+    pub fn insert(&self, topic: String, entry: HistoryEntry) -> Option<HistoryEntry> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let now = entry.time();
+        let keep_older = self.limits().entries_per_topic(&topic) - 1;
+        let mut shard = self.write(&topic);
+        let previous = shard.insert(topic.clone(), entry, keep_older);
+        self.update_age(topic, previous.as_ref(), now);
+        drop(shard);
+        let previous = previous.filter(|previous| !self.limits().is_expired(previous, now));
+        self.evict(now);
+        previous
+    }
+
+    /// Insert the `payload` as received at `time`, for tests needing entries of a certain age.
+    #[cfg(test)]
+    pub fn insert_at(&self, topic: &str, payload: &str, time: SystemTime) {
+        self.insert(topic.to_owned(), HistoryEntry::new_at(payload, time));
+    }
+
+    /// Insert an entry from an earlier run unless there is already a newer one.
+    ///
+    /// Returns whether the entry was inserted.
+    pub fn restore(&self, topic: String, entry: HistoryEntry) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let now = entry.time();
+        let keep_older = self.limits().entries_per_topic(&topic) - 1;
+        {
+            let mut shard = self.write(&topic);
+            if shard
+                .entries
+                .get(&topic)
+                .is_some_and(|existing| existing.time() >= entry.time())
+            {
+                return false;
+            }
+            let previous = shard.insert(topic.clone(), entry, keep_older);
+            self.update_age(topic, previous.as_ref(), now);
+            drop(shard);
+        }
+        self.evict(now);
+        true
+    }
+
+    /// Remove every entry of the topic.
+    pub fn remove(&self, topic: &str) {
+        let mut shard = self.write(topic);
+        if let Some(removed) = shard.remove(topic) {
+            self.ages().remove(&(removed.time(), topic.to_owned()));
+        }
+    }
+
+    /// Apply `f` to the newest entry of every topic and collect the results which are `Some`.
+    ///
+    /// Only one shard is locked at a time.
+    pub fn filter_map<T>(&self, mut f: impl FnMut(&String, &HistoryEntry) -> Option<T>) -> Vec<T> {
+        let now = self.clock.now();
+        let limits = self.limits();
+        let mut result = Vec::new();
+        for shard in self.read_shards() {
+            result.extend(
+                shard
+                    .entries
+                    .iter()
+                    .filter(|(_, entry)| !limits.is_expired(entry, now))
+                    .filter_map(|(topic, entry)| f(topic, entry)),
+            );
+        }
+        result
+    }
+
+    /// Newest entry of every topic
+    pub fn snapshot(&self) -> HashMap<String, HistoryEntry> {
+        self.filter_map(|topic, entry| Some((topic.clone(), entry.clone())))
+            .into_iter()
+            .collect()
+    }
+
+    /// Every known topic, unsorted.
+    pub fn topics(&self) -> Vec<String> {
+        self.filter_map(|topic, _| Some(topic.clone()))
+    }
+
+    /// Amount of known topics.
+    pub fn len(&self) -> usize {
+        self.filter_map(|_, _| Some(())).len()
+    }
+
+    /// Amount of known topics or `None` when a shard is currently written.
+    pub fn try_len(&self) -> Option<usize> {
+        let now = self.clock.now();
+        let limits = self.limits.try_read().ok()?;
+        let mut len = 0;
+        for shard in &self.shards {
+            let shard = shard.try_read().ok()?;
+            len += shard
+                .entries
+                .values()
+                .filter(|entry| !limits.is_expired(entry, now))
+                .count();
+        }
+        Some(len)
+    }
+
+    fn evict(&self, now: SystemTime) {
+        let max_age = self.limits().max_age;
+        if let Some(max_age) = max_age {
+            // Checking every entry on every insert is wasteful, expired ones are skipped on read anyway
+            let is_due = {
+                let mut next = self
+                    .next_expire_check
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let is_due = next.is_none_or(|next| next <= now);
+                if is_due {
+                    *next = Some(now + max_age / 2);
+                }
+                is_due
+            };
+            if is_due {
+                self.remove_expired(now);
+            }
+        }
+        self.remove_above_max_entries();
+    }
+
+    fn remove_expired(&self, now: SystemTime) {
+        let limits = self.limits();
+        if limits.max_age.is_none() {
+            return;
+        }
+        let removed = self
+            .write_shards()
+            .flat_map(|mut shard| shard.remove_expired(&limits, now))
+            .collect::<Vec<_>>();
+        drop(limits);
+        let mut ages = self.ages();
+        for age in removed {
+            ages.remove(&age);
+        }
+    }
+
+    fn remove_above_max_entries(&self) {
+        let Some(max_entries) = self.limits().max_entries else {
+            return;
+        };
+        loop {
+            // Taking the oldest out of the ages first makes concurrent inserts evict each excess once
+            let mut ages = self.ages();
+            if ages.len() <= max_entries {
+                return;
+            }
+            let Some((time, topic)) = ages.pop_first() else {
+                return;
+            };
+            drop(ages);
+            let mut shard = self.write(&topic);
+            // A newer entry inserted meanwhile has its own age and stays
+            if shard
+                .entries
+                .get(&topic)
+                .is_some_and(|entry| entry.time() == time)
+            {
+                shard.remove(&topic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_at(payload: &str, secs: u64) -> HistoryEntry {
+        HistoryEntry::new_at(payload, SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Stored topics including expired ones
+    fn sorted_keys(history: &History) -> Vec<String> {
+        let mut keys = history
+            .read_shards()
+            .flat_map(|shard| shard.entries.keys().cloned().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys
+    }
+
+    fn has_older(history: &History) -> bool {
+        history.read_shards().any(|shard| !shard.older.is_empty())
+    }
+
+    #[test]
+    fn unlimited_keeps_everything() {
+        let history = History::default();
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.insert("b".to_owned(), entry_at("2", 2));
+        assert_eq!(sorted_keys(&history), ["a", "b"]);
+    }
+
+    #[test]
+    fn insert_returns_previous() {
+        let history = History::default();
+        assert!(history.insert("a".to_owned(), entry_at("1", 1)).is_none());
+        let previous = history.insert("a".to_owned(), entry_at("2", 2));
+        assert_eq!(previous.unwrap().payload(), "1");
+    }
+
+    #[test]
+    fn max_entries_evicts_oldest() {
+        let history = History::default();
+        history.set_limits(Some(2), None);
+        history.insert("a".to_owned(), entry_at("1", 3));
+        history.insert("b".to_owned(), entry_at("2", 1));
+        history.insert("c".to_owned(), entry_at("3", 2));
+        assert_eq!(sorted_keys(&history), ["a", "c"]);
+        history.insert("b".to_owned(), entry_at("4", 4));
+        assert_eq!(sorted_keys(&history), ["a", "b"]);
+    }
+
+    #[test]
+    fn concurrent_inserts_keep_max_entries() {
+        let history = History::default();
+        history.set_limits(Some(10), None);
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let history = &history;
+                scope.spawn(move || {
+                    for index in 0..100 {
+                        history.insert(format!("{thread}/{index}"), HistoryEntry::new("1"));
+                    }
+                });
+            }
+        });
+        assert_eq!(sorted_keys(&history).len(), 10);
+        assert_eq!(history.ages().len(), 10);
+    }
+
+    #[test]
+    fn ages_follow_removed_topics() {
+        let history = History::default();
+        history.set_limits(Some(2), Some(Duration::from_secs(10)));
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.insert("b".to_owned(), entry_at("2", 2));
+        history.remove("a");
+        history.insert("c".to_owned(), entry_at("3", 3));
+        assert_eq!(sorted_keys(&history), ["b", "c"]);
+        history.insert("d".to_owned(), entry_at("4", 20));
+        assert_eq!(sorted_keys(&history), ["d"]);
+        assert_eq!(history.ages().len(), 1);
+    }
+
+    #[test]
+    fn disabled_keeps_nothing() {
+        let history = History::default();
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.set_enabled(false);
+        assert!(sorted_keys(&history).is_empty());
+        assert!(history.insert("a".to_owned(), entry_at("2", 2)).is_none());
+        assert!(!history.restore("b".to_owned(), entry_at("3", 3)));
+        assert!(sorted_keys(&history).is_empty());
+        history.set_enabled(true);
+        history.insert("a".to_owned(), entry_at("4", 4));
+        assert_eq!(sorted_keys(&history), ["a"]);
+    }
+
+    #[test]
+    fn set_limits_evicts_immediately() {
+        let history = History::default();
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.insert("b".to_owned(), entry_at("2", 2));
+        history.set_limits(Some(1), None);
+        assert_eq!(sorted_keys(&history), ["b"]);
+    }
+
+    #[test]
+    fn max_age_evicts_expired_on_insert() {
+        let history = History::default();
+        history.set_limits(None, Some(Duration::from_secs(10)));
+        history.insert("a".to_owned(), entry_at("1", 100));
+        history.insert("b".to_owned(), entry_at("2", 105));
+        history.insert("c".to_owned(), entry_at("3", 112));
+        assert_eq!(sorted_keys(&history), ["b", "c"]);
+    }
+
+    #[test]
+    fn expired_previous_is_absent() {
+        let history = History::default();
+        history.set_limits(None, Some(Duration::from_secs(10)));
+        history.insert("a".to_owned(), entry_at("1", 100));
+        assert!(history.insert("a".to_owned(), entry_at("2", 200)).is_none());
+    }
+
+    fn payloads(entries: &[HistoryEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| entry.payload().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn single_entry_per_topic_by_default() {
+        let history = History::default();
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.insert("a".to_owned(), entry_at("2", 2));
+        assert_eq!(payloads(&history.history_of("a")), ["2"]);
+        assert!(!has_older(&history));
+    }
+
+    #[test]
+    fn history_of_is_oldest_to_newest() {
+        let history = History::default();
+        history.set_entries_per_topic(3);
+        for (payload, secs) in [("1", 1), ("2", 2), ("3", 3), ("4", 4)] {
+            history.insert("a".to_owned(), entry_at(payload, secs));
+        }
+        assert_eq!(payloads(&history.history_of("a")), ["2", "3", "4"]);
+        assert_eq!(history.get("a").unwrap().payload(), "4");
+        assert!(history.history_of("b").is_empty());
+    }
+
+    #[test]
+    fn entries_per_topic_prefix_longest_wins() {
+        let history = History::default();
+        history.set_entries_per_topic_prefix("a/", 3);
+        history.set_entries_per_topic_prefix("a/b/", 2);
+        for (payload, secs) in [("1", 1), ("2", 2), ("3", 3)] {
+            history.insert("a/b/c".to_owned(), entry_at(payload, secs));
+            history.insert("a/c".to_owned(), entry_at(payload, secs));
+            history.insert("b".to_owned(), entry_at(payload, secs));
+        }
+        assert_eq!(payloads(&history.history_of("a/b/c")), ["2", "3"]);
+        assert_eq!(payloads(&history.history_of("a/c")), ["1", "2", "3"]);
+        assert_eq!(payloads(&history.history_of("b")), ["3"]);
+    }
+
+    #[test]
+    fn max_entries_evicts_older_entries_too() {
+        let history = History::default();
+        history.set_entries_per_topic(2);
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.insert("a".to_owned(), entry_at("2", 2));
+        history.set_limits(Some(1), None);
+        history.insert("b".to_owned(), entry_at("3", 3));
+        assert!(history.history_of("a").is_empty());
+        assert!(!has_older(&history));
+    }
+
+    #[test]
+    fn restore_keeps_newer() {
+        let history = History::default();
+        history.insert("a".to_owned(), entry_at("new", 2));
+        assert!(!history.restore("a".to_owned(), entry_at("old", 1)));
+        assert!(history.restore("b".to_owned(), entry_at("old", 1)));
+        assert!(history.restore("a".to_owned(), entry_at("newer", 3)));
+        assert_eq!(history.get("a").unwrap().payload(), "newer");
+    }
+
+    #[test]
+    fn remove_removes_older_entries_too() {
+        let history = History::default();
+        history.set_entries_per_topic(2);
+        history.insert("a".to_owned(), entry_at("1", 1));
+        history.insert("a".to_owned(), entry_at("2", 2));
+        history.remove("a");
+        assert!(history.history_of("a").is_empty());
+        assert!(!has_older(&history));
+    }
+
+    #[test]
+    fn expired_is_absent_on_read() {
+        let history = History::default();
+        history.set_limits(None, Some(Duration::from_mins(1)));
+        history.insert("old".to_owned(), entry_at("1", 100));
+        history.insert("new".to_owned(), HistoryEntry::new("2"));
+        assert!(history.get("old").is_none());
+        assert!(history.get("new").is_some());
+        assert_eq!(history.topics(), ["new"]);
+    }
+}
@@ -1,12 +1,81 @@
 use core::time::Duration;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::SystemTime;
 
 use crate::payload;
 
+/// Where the payload of a [`HistoryEntry`] came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum EntrySource {
+    /// Received from the broker
+    #[default]
+    Incoming,
+    /// Published by this client
+    Published,
+}
+
+/// Cloning is cheap as the payload is shared.
+///
+/// With the `serde` feature the time is (de)serialized as unix millis and the payload as string.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SerdeEntry", from = "SerdeEntry")
+)]
 pub struct HistoryEntry {
     time: SystemTime,
-    payload: Box<str>,
+    payload: Arc<[u8]>,
+    retained: bool,
+    source: EntrySource,
+    truncated: bool,
+}
+
+/// Serialized form of a [`HistoryEntry`]
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerdeEntry {
+    time: u64,
+    payload: String,
+    #[serde(default)]
+    retained: bool,
+    #[serde(default)]
+    source: EntrySource,
+    #[serde(default)]
+    truncated: bool,
+}
+
+#[cfg(feature = "serde")]
+impl From<HistoryEntry> for SerdeEntry {
+    fn from(entry: HistoryEntry) -> Self {
+        Self {
+            time: entry.unix_millis().unwrap_or_default(),
+            payload: entry.payload().into_owned(),
+            retained: entry.retained,
+            source: entry.source,
+            truncated: entry.truncated,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SerdeEntry> for HistoryEntry {
+    fn from(entry: SerdeEntry) -> Self {
+        Self::new_at(
+            entry.payload,
+            SystemTime::UNIX_EPOCH + Duration::from_millis(entry.time),
+        )
+        .with_retained(entry.retained)
+        .with_source(entry.source)
+        .with_truncated(entry.truncated)
+    }
 }
 
 impl HistoryEntry {
@@ -15,35 +84,203 @@ impl HistoryEntry {
     where
         I: Into<Box<str>>,
     {
+        Self::new_at(payload, SystemTime::now())
+    }
+
+    /// Entry received at the given `time`, for example to build entries in tests.
+    #[must_use]
+    pub fn new_at<I>(payload: I, time: SystemTime) -> Self
+    where
+        I: Into<Box<str>>,
+    {
+        Self::from_bytes_at(payload.into().into_boxed_bytes(), time)
+    }
+
+    /// Entry of a payload which might not be valid UTF-8.
+    #[must_use]
+    pub fn from_bytes<I>(payload: I) -> Self
+    where
+        I: Into<Box<[u8]>>,
+    {
+        Self::from_bytes_at(payload, SystemTime::now())
+    }
+
+    /// Entry of a payload which might not be valid UTF-8 received at the given `time`.
+    pub(crate) fn from_bytes_at<I>(payload: I, time: SystemTime) -> Self
+    where
+        I: Into<Box<[u8]>>,
+    {
+        let payload: Box<[u8]> = payload.into();
         Self {
-            time: SystemTime::now(),
+            time,
             payload: payload.into(),
+            retained: false,
+            source: EntrySource::Incoming,
+            truncated: false,
         }
     }
 
+    #[must_use]
+    pub const fn with_retained(mut self, retained: bool) -> Self {
+        self.retained = retained;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_source(mut self, source: EntrySource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Whether the payload was a retained message.
+    ///
+    /// Retained messages received on subscribing might be outdated compared to the device.
+    #[must_use]
+    pub const fn retained(&self) -> bool {
+        self.retained
+    }
+
+    pub(crate) const fn with_truncated(mut self, truncated: bool) -> Self {
+        self.truncated = truncated;
+        self
+    }
+
+    /// Whether the payload was cut as it was larger than the [maximum payload size](crate::MqttSmarthome::set_max_payload_bytes).
+    #[must_use]
+    pub const fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    #[must_use]
+    pub const fn source(&self) -> EntrySource {
+        self.source
+    }
+
+    /// When the payload was received or published
+    #[must_use]
+    pub const fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Milliseconds since the unix epoch of the [`time`](Self::time). `None` when it is before the epoch.
+    #[must_use]
+    pub fn unix_millis(&self) -> Option<u64> {
+        let since_epoch = self.time.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        u64::try_from(since_epoch.as_millis()).ok()
+    }
+
     #[must_use]
     pub fn ago(&self) -> Duration {
-        SystemTime::now()
-            .duration_since(self.time)
-            .unwrap_or_default()
+        self.age_at(SystemTime::now())
+    }
+
+    /// Time passed between the entry and `now`. Zero when the entry is newer.
+    #[must_use]
+    pub fn age_at(&self, now: SystemTime) -> Duration {
+        now.duration_since(self.time).unwrap_or_default()
     }
 
     #[must_use]
     pub fn as_boolean(&self) -> bool {
-        payload::is_true(&self.payload)
+        payload::is_true(&self.payload())
+    }
+
+    /// See [`payload::as_bool`].
+    #[must_use]
+    pub fn as_bool_strict(&self) -> Option<bool> {
+        payload::as_bool(&self.payload())
     }
 
     #[must_use]
     pub fn as_float(&self) -> Option<f32> {
-        self.payload
-            .split(char::is_whitespace)
-            .find(|str| !str.is_empty())?
-            .parse::<f32>()
-            .ok()
+        payload::as_f32(&self.payload())
+    }
+
+    /// See [`payload::as_f64`].
+    #[must_use]
+    pub fn as_double(&self) -> Option<f64> {
+        payload::as_f64(&self.payload())
+    }
+
+    /// See [`payload::as_celsius`].
+    #[must_use]
+    pub fn as_celsius(&self) -> Option<f32> {
+        payload::as_celsius(&self.payload())
+    }
+
+    /// See [`payload::as_percent`].
+    #[must_use]
+    pub fn as_percent(&self) -> Option<f32> {
+        payload::as_percent(&self.payload())
+    }
+
+    /// See [`payload::as_duration`].
+    #[must_use]
+    pub fn as_duration(&self) -> Option<Duration> {
+        payload::as_duration(&self.payload())
+    }
+
+    /// See [`payload::as_timestamp`].
+    #[must_use]
+    pub fn as_timestamp(&self) -> Option<SystemTime> {
+        payload::as_timestamp(&self.payload())
+    }
+
+    /// Parse the payload with surrounding whitespace trimmed.
+    #[must_use]
+    pub fn parse<T>(&self) -> Option<T>
+    where
+        T: FromStr,
+    {
+        self.payload().trim().parse().ok()
+    }
+
+    /// See [`payload::as_i64`].
+    #[must_use]
+    pub fn as_int(&self) -> Option<i64> {
+        payload::as_i64(&self.payload())
+    }
+
+    /// See [`payload::json_pointer`].
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn json_pointer(&self, pointer: &str) -> Option<serde_json::Value> {
+        payload::json_pointer(&self.payload(), pointer)
+    }
+
+    /// See [`payload::json_f32`].
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn json_f32(&self, pointer: &str) -> Option<f32> {
+        payload::json_f32(&self.payload(), pointer)
+    }
+
+    /// See [`payload::json_bool`].
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn json_bool(&self, pointer: &str) -> Option<bool> {
+        payload::json_bool(&self.payload(), pointer)
+    }
+
+    /// Deserialize the JSON payload. `None` when it is not valid JSON for `T`, including trailing characters after it.
+    #[cfg(feature = "json")]
+    #[must_use]
+    pub fn as_json<T>(&self) -> Option<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        serde_json::from_slice(&self.payload).ok()
+    }
+
+    /// The payload as string. Invalid UTF-8 sequences are replaced with `�`.
+    #[must_use]
+    pub fn payload(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.payload)
     }
 
+    /// The payload as it was received or published
     #[must_use]
-    pub const fn payload(&self) -> &str {
+    pub fn payload_bytes(&self) -> &[u8] {
         &self.payload
     }
 }
@@ -52,11 +289,91 @@ impl HistoryEntry {
 mod tests {
     use super::*;
 
+    #[test]
+    fn clone_shares_payload() {
+        let entry = HistoryEntry::new("42");
+        let clone = entry.clone();
+        assert!(core::ptr::eq(entry.payload_bytes(), clone.payload_bytes()));
+    }
+
     #[rstest::rstest]
     fn payload_stays_payload(#[values("42", "666")] payload: &str) {
         assert_eq!(HistoryEntry::new(payload.to_owned()).payload(), payload);
     }
 
+    #[test]
+    fn invalid_utf8_is_lossy() {
+        let entry = HistoryEntry::from_bytes(b"21.5 \xb0C".as_slice());
+        assert_eq!(entry.payload(), "21.5 \u{fffd}C");
+        assert_eq!(entry.payload_bytes(), b"21.5 \xb0C");
+        assert_eq!(entry.as_float(), Some(21.5));
+    }
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum Mode {
+        Heat,
+        Cool,
+    }
+
+    impl FromStr for Mode {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "heat" => Ok(Self::Heat),
+                "cool" => Ok(Self::Cool),
+                _ => Err(()),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_u16() {
+        assert_eq!(HistoryEntry::new(" 8080\n").parse::<u16>(), Some(8080));
+        assert_eq!(HistoryEntry::new("70000").parse::<u16>(), None);
+    }
+
+    #[test]
+    fn parse_enum() {
+        assert_eq!(HistoryEntry::new("cool ").parse::<Mode>(), Some(Mode::Cool));
+        assert_eq!(HistoryEntry::new("heat").parse::<Mode>(), Some(Mode::Heat));
+        assert_eq!(HistoryEntry::new("dry").parse::<Mode>(), None);
+    }
+
+    #[test]
+    fn time_is_the_given_one() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_714_753_320_123);
+        let entry = HistoryEntry::new_at("42", time);
+        assert_eq!(entry.time(), time);
+        assert_eq!(entry.unix_millis(), Some(1_714_753_320_123));
+    }
+
+    #[test]
+    fn unix_millis_before_epoch() {
+        let entry = HistoryEntry::new_at("42", SystemTime::UNIX_EPOCH - Duration::from_secs(1));
+        assert_eq!(entry.unix_millis(), None);
+    }
+
+    #[test]
+    fn defaults_to_live_incoming() {
+        let entry = HistoryEntry::new("42");
+        assert!(!entry.retained());
+        assert_eq!(entry.source(), EntrySource::Incoming);
+        let entry = entry
+            .with_retained(true)
+            .with_source(EntrySource::Published);
+        assert!(entry.retained());
+        assert_eq!(entry.source(), EntrySource::Published);
+    }
+
+    #[test]
+    fn new_is_now() {
+        let before = SystemTime::now();
+        let entry = HistoryEntry::new("42");
+        assert!(entry.time() >= before);
+        assert!(entry.time() <= SystemTime::now());
+    }
+
     #[test]
     fn ago_works() {
         let entry = HistoryEntry::new("42".to_owned());
@@ -67,6 +384,14 @@ mod tests {
         assert!(ago < 300);
     }
 
+    #[test]
+    fn age_at_works() {
+        let entry = HistoryEntry::new_at("42", SystemTime::UNIX_EPOCH + Duration::from_secs(10));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(25);
+        assert_eq!(entry.age_at(now), Duration::from_secs(15));
+        assert_eq!(entry.age_at(SystemTime::UNIX_EPOCH), Duration::ZERO);
+    }
+
     #[test]
     fn payload_as_boolean() {
         assert!(HistoryEntry::new("true".to_owned()).as_boolean());
@@ -90,4 +415,57 @@ mod tests {
             _ => panic!("Assertion failed:\n{actual:?} should be\n{expected:?}"),
         }
     }
+
+    #[rstest::rstest]
+    #[case::empty("", None)]
+    #[case::text("test", None)]
+    #[case::number("42", Some(42.0))]
+    #[case::unit("12.3 °C", Some(12.3))]
+    #[case::indent(" 2.4 °C", Some(2.4))]
+    #[case::precise("98765.4321 kWh", Some(98_765.432_1))]
+    fn payload_as_double(#[case] input: &str, #[case] expected: Option<f64>) {
+        let actual = HistoryEntry::new(input.to_owned()).as_double();
+        match (actual, expected) {
+            (None, None) => {} // All fine
+            (Some(actual), Some(expected)) => {
+                float_eq::assert_float_eq!(actual, expected, abs <= 0.000_001);
+            }
+            _ => panic!("Assertion failed:\n{actual:?} should be\n{expected:?}"),
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_714_750_000_123);
+        let entry = HistoryEntry::new_at("21.5", time)
+            .with_retained(true)
+            .with_source(EntrySource::Published);
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(
+            json,
+            r#"{"time":1714750000123,"payload":"21.5","retained":true,"source":"published","truncated":false}"#
+        );
+        let back: HistoryEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.time(), time);
+        assert_eq!(back.payload(), "21.5");
+        assert!(back.retained());
+        assert_eq!(back.source(), EntrySource::Published);
+        assert!(!back.truncated());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_snapshot_with_defaults() {
+        let snapshot: std::collections::HashMap<String, HistoryEntry> =
+            serde_json::from_str(r#"{"lamp/status":{"time":1000,"payload":"on"}}"#).unwrap();
+        let entry = &snapshot["lamp/status"];
+        assert_eq!(
+            entry.time(),
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1)
+        );
+        assert_eq!(entry.payload(), "on");
+        assert!(!entry.retained());
+        assert_eq!(entry.source(), EntrySource::Incoming);
+    }
 }
@@ -0,0 +1,348 @@
+//! Publish devices following the [Homie 4 convention](https://homieiot.github.io/specification/spec-core-v4_0_0/).
+//!
+//! ```no_run
+//! # async fn example(smarthome: &mqtt_smarthome::MqttSmarthome) {
+//! use mqtt_smarthome::homie::{HomieDatatype, HomieDevice, HomieNode, HomieProperty};
+//!
+//! let device = HomieDevice::new(smarthome, "thermostat", "Thermostat").node(
+//!     HomieNode::new("living", "Living room", "thermostat")
+//!         .property(HomieProperty::new("temperature", "Temperature", HomieDatatype::Float).unit("°C"))
+//!         .property(HomieProperty::new("target", "Target", HomieDatatype::Float).unit("°C").settable()),
+//! );
+//! device.publish_structure().await.unwrap();
+//! device.set_value("living", "temperature", 21.5).await.unwrap();
+//! # }
+//! ```
+
+use core::fmt;
+
+use crate::{IntoPayload, LastWillConfig, MqttSmarthome, PublishError};
+
+const BASE_TOPIC: &str = "homie";
+const HOMIE_VERSION: &str = "4.0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomieDatatype {
+    Integer,
+    Float,
+    Boolean,
+    String,
+    Enum,
+    Color,
+    Datetime,
+    Duration,
+}
+
+impl fmt::Display for HomieDatatype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Integer => "integer",
+            Self::Float => "float",
+            Self::Boolean => "boolean",
+            Self::String => "string",
+            Self::Enum => "enum",
+            Self::Color => "color",
+            Self::Datetime => "datetime",
+            Self::Duration => "duration",
+        })
+    }
+}
+
+/// Lifecycle state of a device published as `$state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomieState {
+    Init,
+    Ready,
+    Disconnected,
+    Sleeping,
+    Lost,
+    Alert,
+}
+
+impl fmt::Display for HomieState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Init => "init",
+            Self::Ready => "ready",
+            Self::Disconnected => "disconnected",
+            Self::Sleeping => "sleeping",
+            Self::Lost => "lost",
+            Self::Alert => "alert",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HomieProperty {
+    id: String,
+    name: String,
+    datatype: HomieDatatype,
+    unit: Option<String>,
+    format: Option<String>,
+    settable: bool,
+}
+
+impl HomieProperty {
+    #[must_use]
+    pub fn new(id: &str, name: &str, datatype: HomieDatatype) -> Self {
+        Self {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            datatype,
+            unit: None,
+            format: None,
+            settable: false,
+        }
+    }
+
+    #[must_use]
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.to_owned());
+        self
+    }
+
+    /// Required for the datatypes `enum` (like `on,off`) and `color` (`rgb` or `hsv`).
+    #[must_use]
+    pub fn format(mut self, format: &str) -> Self {
+        self.format = Some(format.to_owned());
+        self
+    }
+
+    /// Commands are accepted on the `set` topic of the property.
+    #[must_use]
+    pub const fn settable(mut self) -> Self {
+        self.settable = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HomieNode {
+    id: String,
+    name: String,
+    node_type: String,
+    properties: Vec<HomieProperty>,
+}
+
+impl HomieNode {
+    #[must_use]
+    pub fn new(id: &str, name: &str, node_type: &str) -> Self {
+        Self {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            node_type: node_type.to_owned(),
+            properties: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn property(mut self, property: HomieProperty) -> Self {
+        self.properties.push(property);
+        self
+    }
+}
+
+/// Device following the Homie convention below `homie/{device_id}`.
+///
+/// Create the client with the [`last_will`](Self::last_will) of the device so the broker sets the `$state` to `lost` on an unexpected disconnect.
+/// Use [`set_state`](Self::set_state) to publish the state on a graceful shutdown.
+#[derive(Debug, Clone)]
+pub struct HomieDevice {
+    smarthome: MqttSmarthome,
+    id: String,
+    name: String,
+    nodes: Vec<HomieNode>,
+}
+
+impl HomieDevice {
+    #[must_use]
+    pub fn new(smarthome: &MqttSmarthome, device_id: &str, name: &str) -> Self {
+        Self {
+            smarthome: smarthome.clone(),
+            id: device_id.to_owned(),
+            name: name.to_owned(),
+            nodes: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn node(mut self, node: HomieNode) -> Self {
+        self.nodes.push(node);
+        self
+    }
+
+    /// Last will setting the `$state` of the device to `lost`, see [`MqttSmarthome::new_last_will`].
+    ///
+    /// The [birth message](LastWillConfig::birth_message) is disabled as it would publish the connected state to the `$state`.
+    #[must_use]
+    pub fn last_will(device_id: &str) -> LastWillConfig {
+        LastWillConfig::new(format!("{BASE_TOPIC}/{device_id}/$state"), true)
+            .payload(&HomieState::Lost.to_string())
+            .birth_message(None)
+    }
+
+    fn topic(&self, suffix: &str) -> String {
+        format!("{BASE_TOPIC}/{}/{suffix}", self.id)
+    }
+
+    /// All retained attribute topics with their payloads except the `$state`.
+    #[must_use]
+    pub fn structure(&self) -> Vec<(String, String)> {
+        let node_ids = self
+            .nodes
+            .iter()
+            .map(|node| node.id.as_str())
+            .collect::<Vec<_>>();
+        let mut structure = vec![
+            (self.topic("$homie"), HOMIE_VERSION.to_owned()),
+            (self.topic("$name"), self.name.clone()),
+            (self.topic("$nodes"), node_ids.join(",")),
+        ];
+        for node in &self.nodes {
+            let property_ids = node
+                .properties
+                .iter()
+                .map(|property| property.id.as_str())
+                .collect::<Vec<_>>();
+            let node_topic = |suffix: &str| self.topic(&format!("{}/{suffix}", node.id));
+            structure.push((node_topic("$name"), node.name.clone()));
+            structure.push((node_topic("$type"), node.node_type.clone()));
+            structure.push((node_topic("$properties"), property_ids.join(",")));
+            for property in &node.properties {
+                let property_topic =
+                    |suffix: &str| node_topic(&format!("{}/{suffix}", property.id));
+                structure.push((property_topic("$name"), property.name.clone()));
+                structure.push((property_topic("$datatype"), property.datatype.to_string()));
+                if let Some(unit) = &property.unit {
+                    structure.push((property_topic("$unit"), unit.clone()));
+                }
+                if let Some(format) = &property.format {
+                    structure.push((property_topic("$format"), format.clone()));
+                }
+                if property.settable {
+                    structure.push((property_topic("$settable"), "true".to_owned()));
+                }
+            }
+        }
+        structure
+    }
+
+    /// Publish the `$state` of the device.
+    ///
+    /// # Errors
+    /// Returns an error when the device id is not a valid topic or the MQTT eventloop is gone.
+    pub async fn set_state(&self, state: HomieState) -> Result<(), PublishError> {
+        self.smarthome
+            .publish(&self.topic("$state"), state.to_string(), true)
+            .await
+    }
+
+    /// Publish the whole [`structure`](Self::structure) surrounded by the `$state` `init` and `ready`.
+    ///
+    /// # Errors
+    /// Returns an error when an id is not a valid topic or the MQTT eventloop is gone.
+    pub async fn publish_structure(&self) -> Result<(), PublishError> {
+        self.set_state(HomieState::Init).await?;
+        for (topic, payload) in self.structure() {
+            self.smarthome.publish(&topic, payload, true).await?;
+        }
+        self.set_state(HomieState::Ready).await
+    }
+
+    /// Publish the current value of a property.
+    ///
+    /// # Errors
+    /// Returns an error when an id is not a valid topic or the MQTT eventloop is gone.
+    pub async fn set_value<P: IntoPayload>(
+        &self,
+        node_id: &str,
+        property_id: &str,
+        payload: P,
+    ) -> Result<(), PublishError> {
+        let topic = self.topic(&format!("{node_id}/{property_id}"));
+        self.smarthome.publish(&topic, payload, true).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(smarthome: &MqttSmarthome) -> HomieDevice {
+        HomieDevice::new(smarthome, "thermostat", "Thermostat").node(
+            HomieNode::new("living", "Living room", "thermostat")
+                .property(
+                    HomieProperty::new("temperature", "Temperature", HomieDatatype::Float)
+                        .unit("°C"),
+                )
+                .property(
+                    HomieProperty::new("mode", "Mode", HomieDatatype::Enum)
+                        .format("heat,off")
+                        .settable(),
+                ),
+        )
+    }
+
+    #[test]
+    fn structure_follows_spec() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let structure = device(&smarthome).structure();
+        let expected = [
+            ("homie/thermostat/$homie", "4.0"),
+            ("homie/thermostat/$name", "Thermostat"),
+            ("homie/thermostat/$nodes", "living"),
+            ("homie/thermostat/living/$name", "Living room"),
+            ("homie/thermostat/living/$type", "thermostat"),
+            ("homie/thermostat/living/$properties", "temperature,mode"),
+            ("homie/thermostat/living/temperature/$name", "Temperature"),
+            ("homie/thermostat/living/temperature/$datatype", "float"),
+            ("homie/thermostat/living/temperature/$unit", "°C"),
+            ("homie/thermostat/living/mode/$name", "Mode"),
+            ("homie/thermostat/living/mode/$datatype", "enum"),
+            ("homie/thermostat/living/mode/$format", "heat,off"),
+            ("homie/thermostat/living/mode/$settable", "true"),
+        ]
+        .map(|(topic, payload)| (topic.to_owned(), payload.to_owned()));
+        assert_eq!(structure, expected);
+    }
+
+    #[test]
+    fn last_will_is_lost_state() {
+        let last_will = HomieDevice::last_will("thermostat");
+        assert_eq!(last_will.topic, "homie/thermostat/$state");
+        assert_eq!(last_will.payload, "lost");
+        assert!(last_will.retain);
+        assert_eq!(last_will.birth, crate::birth::Birth::Disabled);
+    }
+
+    #[tokio::test]
+    async fn publish_structure_ends_ready() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let device = device(&smarthome);
+        device.publish_structure().await.unwrap();
+        let state = smarthome.last("homie/thermostat/$state").await.unwrap();
+        assert_eq!(state.payload(), "ready");
+        assert!(state.retained());
+        let datatype = smarthome
+            .last("homie/thermostat/living/temperature/$datatype")
+            .await
+            .unwrap();
+        assert_eq!(datatype.payload(), "float");
+    }
+
+    #[tokio::test]
+    async fn set_value_publishes_retained() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let device = device(&smarthome);
+        device
+            .set_value("living", "temperature", 21.5)
+            .await
+            .unwrap();
+        let value = smarthome
+            .last("homie/thermostat/living/temperature")
+            .await
+            .unwrap();
+        assert_eq!(value.payload(), "21.5");
+        assert!(value.retained());
+    }
+}
@@ -0,0 +1,105 @@
+//! Export numeric history entries in the `InfluxDB` line protocol.
+
+use core::fmt::Write as _;
+use std::time::SystemTime;
+
+use crate::{payload, MqttSmarthome};
+
+/// Escape commas, spaces and equal signs as required for tag keys and values.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        if matches!(char, ',' | ' ' | '=' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+/// Escape commas and spaces as required for measurement names.
+fn escape_measurement(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        if matches!(char, ',' | ' ' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+impl MqttSmarthome {
+    /// Encode the last `HistoryEntry` of every topic matching the MQTT topic `filter` in the `InfluxDB` line protocol.
+    ///
+    /// Each line looks like `measurement,topic=hue/status/temp value=21.5 1714750000000000000` with the timestamp in nanoseconds.
+    /// Entries without a numeric payload are skipped.
+    pub async fn to_line_protocol(&self, measurement: &str, filter: &str) -> String {
+        let measurement = escape_measurement(measurement);
+        let mut lines = String::new();
+        for (topic, entry) in self.last_matching(filter).await {
+            let Some(value) = payload::as_f64(&entry.payload()) else {
+                continue;
+            };
+            let Ok(since_epoch) = entry.time().duration_since(SystemTime::UNIX_EPOCH) else {
+                continue;
+            };
+            _ = writeln!(
+                lines,
+                "{measurement},topic={} value={value} {}",
+                escape_tag(&topic),
+                since_epoch.as_nanos()
+            );
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use rstest::rstest;
+
+    use super::*;
+
+    #[rstest]
+    #[case("hue/status/temp", "hue/status/temp")]
+    #[case("living room/temp", r"living\ room/temp")]
+    #[case("a,b=c", r"a\,b\=c")]
+    #[case(r"a\b", r"a\\b")]
+    fn escape_tag_works(#[case] value: &str, #[case] expected: &str) {
+        assert_eq!(escape_tag(value), expected);
+    }
+
+    #[test]
+    fn escape_measurement_keeps_equal_sign() {
+        assert_eq!(escape_measurement("a b,c=d"), r"a\ b\,c=d");
+    }
+
+    #[tokio::test]
+    async fn numeric_entries_are_encoded() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_750_000);
+        smarthome.history.insert_at("hue/status/temp", "21.5", time);
+        smarthome
+            .history
+            .insert_at("hue/status/name", "kitchen", time);
+        smarthome.history.insert_at("hue/set/temp", "20", time);
+        assert_eq!(
+            smarthome.to_line_protocol("sensor", "hue/status/#").await,
+            "sensor,topic=hue/status/temp value=21.5 1714750000000000000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn values_keep_their_precision() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_750_000);
+        smarthome.history.insert_at("energy", "1234567.89", time);
+        assert_eq!(
+            smarthome.to_line_protocol("meter", "energy").await,
+            "meter,topic=energy value=1234567.89 1714750000000000000\n"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::task::{self, JoinHandle};
+
+use crate::connection_events::Link;
+use crate::{logging, MqttSmarthome};
+
+/// Restoring the state on the broker after every `ConnAck`.
+///
+/// Only one initialization runs at a time.
+/// Each `ConnAck` starts a new generation, initializations of older connections stop at the next step.
+#[derive(Debug, Default)]
+pub struct Initialization {
+    generation: AtomicU64,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl Initialization {
+    fn is_current(&self, generation: u64) -> bool {
+        self.generation.load(Ordering::SeqCst) == generation
+    }
+}
+
+impl MqttSmarthome {
+    pub(crate) fn spawn_initialization(&self, session_present: bool) -> JoinHandle<()> {
+        let generation = self
+            .initialization
+            .generation
+            .fetch_add(1, Ordering::SeqCst)
+            + 1;
+        let smarthome = self.clone();
+        task::spawn(async move {
+            if smarthome
+                .initialize_connection(session_present, generation)
+                .await
+            {
+                logging::status!(client_id = smarthome.client_id.as_str(); "MQTT connection fully initialized");
+            }
+        })
+    }
+
+    /// Restore the state on the broker. Returns false when a newer connection took over.
+    ///
+    /// A resumed [persistent session](crate::protocol::set_persistent_session) still has the subscriptions on the broker.
+    pub(crate) async fn initialize_connection(
+        &self,
+        session_present: bool,
+        generation: u64,
+    ) -> bool {
+        let _running = self.initialization.running.lock().await;
+        let is_current = || self.initialization.is_current(generation);
+
+        if !is_current() {
+            return false;
+        }
+        self.flush_offline_buffer().await;
+
+        if !is_current() {
+            return false;
+        }
+        if !session_present {
+            let prioritized = self.prioritized();
+            let filters: [Vec<_>; 2] = self
+                .subscribed
+                .read()
+                .await
+                .with_qos()
+                .into_iter()
+                .partition::<Vec<_>, _>(|(filter, _)| prioritized.contains(filter))
+                .into();
+            // The broker sends the retained messages of the high priority filters first
+            for filters in filters {
+                if let Err(error) = self.send_subscribe(filters, None).await {
+                    self.request_failed("subscribe after reconnect", &error);
+                    return false;
+                }
+            }
+        }
+
+        if !is_current() {
+            return false;
+        }
+        if let Some(birth) = self.birth_message() {
+            if let Err(error) = self
+                .client
+                .publish(birth.topic, birth.qos, birth.retain, birth.payload)
+                .await
+            {
+                self.request_failed("publish connected", &error);
+                return false;
+            }
+        }
+        if let Err(error) = self.publish_availability_online().await {
+            self.request_failed("publish availability", &error);
+            return false;
+        }
+
+        self.link.send_if_modified(|link| {
+            let initialized = *link == Link::Connected && is_current();
+            if initialized {
+                *link = Link::Initialized;
+            }
+            initialized
+        });
+        is_current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{MqttOptions, Request};
+
+    use super::*;
+    use crate::LastWillConfig;
+
+    #[tokio::test]
+    async fn rapid_connections_initialize_once() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.subscribe("b/#").await;
+        smarthome.subscribe("a/#").await;
+        eventloop.clean();
+        eventloop.pending.clear();
+
+        smarthome.link.send_replace(Link::Connected);
+        let first = smarthome.spawn_initialization(false);
+        let second = smarthome.spawn_initialization(false);
+        first.await.unwrap();
+        second.await.unwrap();
+        smarthome.await_initialized().await.unwrap();
+
+        eventloop.clean();
+        let requests = eventloop.pending.drain(..).collect::<Vec<_>>();
+        assert_eq!(requests.len(), 2, "{requests:?}");
+        let Request::Subscribe(subscribe) = &requests[0] else {
+            panic!("expected a subscribe first: {requests:?}");
+        };
+        let filters = subscribe
+            .filters
+            .iter()
+            .map(|filter| filter.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(filters, ["a/#", "b/#"]);
+        assert!(matches!(requests[1], Request::Publish(_)));
+    }
+
+    #[tokio::test]
+    async fn no_subscribe_without_subscriptions() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.set_birth_message(None);
+        assert!(smarthome.initialize_connection(false, 0).await);
+        eventloop.clean();
+        assert!(eventloop.pending.is_empty());
+    }
+}
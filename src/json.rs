@@ -0,0 +1,311 @@
+//! Minimal JSON parser for reading fields out of device payloads.
+
+use core::fmt::Write as _;
+
+/// Nesting of arrays and objects deeper than this is treated as invalid to not overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Self>),
+    Object(Vec<(String, Self)>),
+}
+
+impl JsonValue {
+    /// Parse a complete JSON document.
+    pub fn parse(input: &str) -> Option<Self> {
+        let (value, rest) = parse_value(input, 0)?;
+        rest.trim_start().is_empty().then_some(value)
+    }
+
+    /// Value of the `key` when this is an object.
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(fields) => fields
+                .iter()
+                .find(|(field, _)| field == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub const fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+fn parse_value(input: &str, depth: usize) -> Option<(JsonValue, &str)> {
+    let input = input.trim_start();
+    match input.chars().next()? {
+        '{' | '[' if depth >= MAX_DEPTH => None,
+        '{' => parse_object(&input[1..], depth + 1),
+        '[' => parse_array(&input[1..], depth + 1),
+        '"' => parse_json_string(input).map(|(string, rest)| (JsonValue::String(string), rest)),
+        't' => input
+            .strip_prefix("true")
+            .map(|rest| (JsonValue::Bool(true), rest)),
+        'f' => input
+            .strip_prefix("false")
+            .map(|rest| (JsonValue::Bool(false), rest)),
+        'n' => input
+            .strip_prefix("null")
+            .map(|rest| (JsonValue::Null, rest)),
+        _ => {
+            let end = number_len(input)?;
+            let number = input[..end].parse::<f64>().ok()?;
+            Some((JsonValue::Number(number), &input[end..]))
+        }
+    }
+}
+
+/// Length of the JSON number at the start of `input`: `-?(0|[1-9][0-9]*)(.[0-9]+)?([eE][+-]?[0-9]+)?`
+fn number_len(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    let digits_from = |from: usize| {
+        bytes
+            .iter()
+            .skip(from)
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+    let mut end = usize::from(bytes.first() == Some(&b'-'));
+    match bytes.get(end)? {
+        b'0' => end += 1,
+        b'1'..=b'9' => end += digits_from(end),
+        _ => return None,
+    }
+    if bytes.get(end) == Some(&b'.') {
+        let fraction = digits_from(end + 1);
+        if fraction == 0 {
+            return None;
+        }
+        end += 1 + fraction;
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        end += 1;
+        if matches!(bytes.get(end), Some(b'+' | b'-')) {
+            end += 1;
+        }
+        let exponent = digits_from(end);
+        if exponent == 0 {
+            return None;
+        }
+        end += exponent;
+    }
+    Some(end)
+}
+
+fn parse_array(input: &str, depth: usize) -> Option<(JsonValue, &str)> {
+    let mut values = Vec::new();
+    if let Some(rest) = input.trim_start().strip_prefix(']') {
+        return Some((JsonValue::Array(values), rest));
+    }
+    let mut rest = input;
+    loop {
+        let (value, after) = parse_value(rest, depth)?;
+        values.push(value);
+        let after = after.trim_start();
+        if let Some(after) = after.strip_prefix(',') {
+            rest = after;
+        } else {
+            return Some((JsonValue::Array(values), after.strip_prefix(']')?));
+        }
+    }
+}
+
+fn parse_object(input: &str, depth: usize) -> Option<(JsonValue, &str)> {
+    let mut fields = Vec::new();
+    if let Some(rest) = input.trim_start().strip_prefix('}') {
+        return Some((JsonValue::Object(fields), rest));
+    }
+    let mut rest = input;
+    loop {
+        let (key, after) = parse_json_string(rest.trim_start())?;
+        let (value, after) = parse_value(after.trim_start().strip_prefix(':')?, depth)?;
+        fields.push((key, value));
+        let after = after.trim_start();
+        if let Some(after) = after.strip_prefix(',') {
+            rest = after;
+        } else {
+            return Some((JsonValue::Object(fields), after.strip_prefix('}')?));
+        }
+    }
+}
+
+pub fn escape_json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\r' => result += "\\r",
+            '\t' => result += "\\t",
+            char if char.is_control() => {
+                _ = write!(result, "\\u{:04x}", u32::from(char));
+            }
+            char => result.push(char),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Parse a JSON string at the start of `input` and return it together with the rest of the input.
+pub fn parse_json_string(input: &str) -> Option<(String, &str)> {
+    let mut chars = input.strip_prefix('"')?.char_indices();
+    let mut result = String::new();
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '"' => return Some((result, &input[index + 2..])),
+            '\\' => {
+                let (_, escaped) = chars.next()?;
+                let unescaped = match escaped {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let mut code = parse_hex4(&mut chars)?;
+                        if (0xD800..0xDC00).contains(&code) {
+                            if chars.next()?.1 != '\\' || chars.next()?.1 != 'u' {
+                                return None;
+                            }
+                            let low = parse_hex4(&mut chars)?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + low.checked_sub(0xDC00)?;
+                        }
+                        char::from_u32(code)?
+                    }
+                    _ => return None,
+                };
+                result.push(unescaped);
+            }
+            char => result.push(char),
+        }
+    }
+    None
+}
+
+fn parse_hex4(chars: &mut core::str::CharIndices) -> Option<u32> {
+    let mut code = 0;
+    for _ in 0..4 {
+        code = code * 16 + chars.next()?.1.to_digit(16)?;
+    }
+    Some(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::plain("foo/bar")]
+    #[case::empty("")]
+    #[case::quotes(r#"{"temperature":21.5,"name":"a \"b\""}"#)]
+    #[case::backslash(r"C:\path")]
+    #[case::newline("first\nsecond\r\n")]
+    #[case::unicode("12.3 °C 🌡")]
+    #[case::control("\u{1}\u{1f}")]
+    fn json_string_roundtrip(#[case] value: &str) {
+        let escaped = escape_json_string(value);
+        assert_eq!(parse_json_string(&escaped), Some((value.to_owned(), "")));
+    }
+
+    #[test]
+    fn json_string_surrogate_pair() {
+        assert_eq!(
+            parse_json_string(r#""\ud83c\udf21","#),
+            Some(("🌡".to_owned(), ","))
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::unterminated(r#""foo"#)]
+    #[case::unknown_escape(r#""\x""#)]
+    #[case::no_quote("foo")]
+    fn json_string_invalid(#[case] input: &str) {
+        assert_eq!(parse_json_string(input), None);
+    }
+
+    #[test]
+    fn parses_nested() {
+        let value =
+            JsonValue::parse(r#" {"a": [1, -2.5e1, true, null], "b": {"c": "d\"e"}, "f": {}} "#)
+                .unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&JsonValue::Array(vec![
+                JsonValue::Number(1.0),
+                JsonValue::Number(-25.0),
+                JsonValue::Bool(true),
+                JsonValue::Null,
+            ]))
+        );
+        assert_eq!(
+            value
+                .get("b")
+                .and_then(|b| b.get("c"))
+                .and_then(JsonValue::as_str),
+            Some("d\"e")
+        );
+        assert_eq!(value.get("f"), Some(&JsonValue::Object(Vec::new())));
+        assert_eq!(value.get("missing"), None);
+    }
+
+    #[rstest::rstest]
+    #[case("0", 0.0)]
+    #[case("-0", 0.0)]
+    #[case("10", 10.0)]
+    #[case("1.5", 1.5)]
+    #[case("-2.5e-3", -0.0025)]
+    #[case("1E+2", 100.0)]
+    fn parses_numbers(#[case] input: &str, #[case] expected: f64) {
+        assert_eq!(JsonValue::parse(input), Some(JsonValue::Number(expected)));
+    }
+
+    #[rstest::rstest]
+    #[case("")]
+    #[case("{")]
+    #[case(r#"{"a":1,}"#)]
+    #[case(r#"{"a" 1}"#)]
+    #[case("[1 2]")]
+    #[case("tru")]
+    #[case("1 2")]
+    #[case("+1")]
+    #[case(".5")]
+    #[case("01")]
+    #[case("-")]
+    #[case("1.")]
+    #[case("1e")]
+    #[case("[1.e5]")]
+    fn invalid_is_none(#[case] input: &str) {
+        assert_eq!(JsonValue::parse(input), None);
+    }
+
+    #[test]
+    fn deep_nesting_is_none() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(JsonValue::parse(&nested(MAX_DEPTH)).is_some());
+        assert_eq!(JsonValue::parse(&nested(MAX_DEPTH + 1)), None);
+        assert_eq!(JsonValue::parse(&"[{\"a\":".repeat(500_000)), None);
+    }
+}
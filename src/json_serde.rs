@@ -0,0 +1,202 @@
+//! Publish and receive payloads serialized as JSON with serde.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task;
+
+use crate::logging::debug;
+use crate::{HistoryEntry, MqttSmarthome, PublishJsonError};
+
+impl MqttSmarthome {
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_json())` without cloning the entry
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_json<T>(&self, topic: &str) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.history.get_and_then(topic, HistoryEntry::as_json)
+    }
+
+    /// Same as [`subscribe_and_watch`](Self::subscribe_and_watch) but the payloads are deserialized from JSON.
+    ///
+    /// Payloads which are not valid JSON for `T` are skipped instead of closing the channel.
+    pub async fn subscribe_json_channel<T>(
+        &self,
+        filter: &str,
+        allow_retained: bool,
+    ) -> Receiver<(String, T)>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let mut input = self.subscribe_and_watch(filter, allow_retained).await;
+        let (sender, receiver) = channel(25);
+        task::spawn(async move {
+            loop {
+                let (topic, payload) = tokio::select! {
+                    message = input.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    () = sender.closed() => break,
+                };
+                let value = match serde_json::from_str(&payload) {
+                    Ok(value) => value,
+                    Err(error) => {
+                        debug!(topic = topic.as_str(); "MQTT payload of {topic} skipped as it is not the expected JSON: {error}");
+                        continue;
+                    }
+                };
+                if sender.send((topic, value)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    /// Serialize the `value` as JSON and [`publish`](Self::publish) it.
+    ///
+    /// The serialized string is added to the history like any other published payload.
+    ///
+    /// # Errors
+    /// Returns [`PublishJsonError::Serialize`] when the `value` can not be represented as JSON.
+    /// Returns [`PublishJsonError::Publish`] when publishing fails.
+    pub async fn publish_json<T>(
+        &self,
+        topic: &str,
+        value: &T,
+        retain: bool,
+    ) -> Result<(), PublishJsonError>
+    where
+        T: Serialize + Sync + ?Sized,
+    {
+        let payload = serde_json::to_string(value)?;
+        self.publish(topic, payload, retain).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::handle_incoming;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct State {
+        temperature: f32,
+        humidity: Option<f32>,
+    }
+
+    #[derive(Serialize)]
+    struct Command {
+        state: &'static str,
+        brightness: u8,
+    }
+
+    #[tokio::test]
+    async fn published_json_is_in_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let command = Command {
+            state: "ON",
+            brightness: 42,
+        };
+        smarthome
+            .publish_json("lamp/set", &command, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            smarthome.last("lamp/set").await.unwrap().payload(),
+            r#"{"state":"ON","brightness":42}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn serialize_error_is_returned() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let value = HashMap::from([((1, 2), "non-string key")]);
+        let result = smarthome.publish_json("lamp/set", &value, false).await;
+        assert!(matches!(result, Err(PublishJsonError::Serialize(_))));
+        assert!(smarthome.last("lamp/set").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn invalid_topic_is_a_publish_error() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let result = smarthome.publish_json("lamp/+", &42, false).await;
+        assert!(matches!(result, Err(PublishJsonError::Publish(_))));
+    }
+
+    #[tokio::test]
+    async fn last_as_json_with_optional_fields() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(
+            &smarthome,
+            "sensor".to_owned(),
+            r#"{"temperature":21.5,"battery":100}"#,
+            false,
+        )
+        .await;
+        assert_eq!(
+            smarthome.last_as_json::<State>("sensor").await,
+            Some(State {
+                temperature: 21.5,
+                humidity: None
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn trailing_garbage_is_not_json() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(
+            &smarthome,
+            "sensor".to_owned(),
+            r#"{"temperature":21.5} garbage"#,
+            false,
+        )
+        .await;
+        assert_eq!(smarthome.last_as_json::<State>("sensor").await, None);
+        let entry = smarthome.last("sensor").await.unwrap();
+        assert_eq!(entry.as_json::<State>(), None);
+    }
+
+    #[tokio::test]
+    async fn json_channel_skips_unparsable() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome
+            .subscribe_json_channel::<State>("sensor/#", false)
+            .await;
+        handle_incoming(&smarthome, "sensor/a".to_owned(), "offline", false).await;
+        handle_incoming(
+            &smarthome,
+            "sensor/a".to_owned(),
+            r#"{"temperature":20}x"#,
+            false,
+        )
+        .await;
+        handle_incoming(
+            &smarthome,
+            "sensor/b".to_owned(),
+            r#"{"temperature":19.5,"humidity":40}"#,
+            false,
+        )
+        .await;
+        assert_eq!(
+            receiver.recv().await,
+            Some((
+                "sensor/b".to_owned(),
+                State {
+                    temperature: 19.5,
+                    humidity: Some(40.0)
+                }
+            ))
+        );
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(smarthome.subscriptions().await, ["sensor/#"]);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::birth::Birth;
+use crate::protocol::{self, LastWill, QoS};
+use crate::BirthMessage;
+
+/// Last will set on the connection. See [`new_last_will`](crate::MqttSmarthome::new_last_will).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastWillConfig {
+    pub topic: String,
+    pub payload: String,
+    pub qos: QoS,
+    pub retain: bool,
+    pub(crate) birth: Birth,
+}
+
+impl LastWillConfig {
+    /// Last will with the payload `0` and [`QoS::AtLeastOnce`].
+    #[must_use]
+    pub fn new(topic: String, retain: bool) -> Self {
+        Self {
+            topic,
+            payload: "0".to_owned(),
+            qos: QoS::AtLeastOnce,
+            retain,
+            birth: Birth::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn payload(mut self, payload: &str) -> Self {
+        payload.clone_into(&mut self.payload);
+        self
+    }
+
+    #[must_use]
+    pub const fn qos(mut self, qos: QoS) -> Self {
+        self.qos = qos;
+        self
+    }
+
+    /// Message published on every connection, starting with the first one. See [`set_birth_message`](crate::MqttSmarthome::set_birth_message).
+    #[must_use]
+    pub fn birth_message(mut self, birth: Option<BirthMessage>) -> Self {
+        self.birth = Birth::from(birth);
+        self
+    }
+
+    pub(crate) fn to_last_will(&self) -> LastWill {
+        protocol::last_will(&self.topic, &self.payload, self.qos, self.retain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::MqttOptions;
+
+    use super::*;
+    use crate::MqttSmarthome;
+
+    #[test]
+    fn defaults_are_kept() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (_smarthome, eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), true),
+            mqttoptions,
+        );
+        let expected = protocol::last_will("test/connected", "0", QoS::AtLeastOnce, true);
+        assert_eq!(protocol::configured_last_will(&eventloop), Some(expected));
+    }
+
+    #[test]
+    fn custom_will_and_birth_topic() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let last_will = LastWillConfig::new("test/availability".to_owned(), false)
+            .payload("offline")
+            .qos(QoS::AtMostOnce);
+        let (smarthome, eventloop) = MqttSmarthome::new_without_eventloop(last_will, mqttoptions);
+        let expected = protocol::last_will("test/availability", "offline", QoS::AtMostOnce, false);
+        assert_eq!(protocol::configured_last_will(&eventloop), Some(expected));
+        assert_eq!(
+            smarthome.birth_message().unwrap().topic,
+            "test/availability"
+        );
+    }
+
+    #[test]
+    fn birth_message_from_config() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let last_will = LastWillConfig::new("test/connected".to_owned(), false).birth_message(None);
+        let (smarthome, _eventloop) = MqttSmarthome::new_without_eventloop(last_will, mqttoptions);
+        assert_eq!(smarthome.birth_message(), None);
+    }
+}
@@ -0,0 +1,129 @@
+use core::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use tokio::time::{timeout, Instant};
+
+use crate::protocol::QoS;
+use crate::watcher::{RemoveWatcherOnDrop, Watcher};
+use crate::MqttSmarthome;
+
+/// Makes the topic of every measurement unique within the process.
+static NEXT_PROBE: AtomicU64 = AtomicU64::new(0);
+
+impl MqttSmarthome {
+    /// Measure the time a message needs from being published until it is received back from the broker.
+    ///
+    /// Subscribes to a temporary topic below `{base_topic}/$latency/`, publishes the current time to it and waits for it to arrive through the eventloop.
+    /// The publish goes to the broker directly, so it is neither buffered, throttled nor affected by the [dry run](Self::set_dry_run).
+    /// Returns `None` when the subscription or publish failed or the message did not arrive within `max_wait`.
+    ///
+    /// The subscription, the watcher and the history of the topic are removed afterwards.
+    pub async fn measure_latency(&self, max_wait: Duration) -> Option<Duration> {
+        let topic = format!(
+            "{}/$latency/{}/{}",
+            self.base_topic,
+            self.client_id.replace(['+', '#', '/'], "_"),
+            NEXT_PROBE.fetch_add(1, Ordering::Relaxed),
+        );
+        let (watcher, mut receiver) = Watcher::new(&topic, false);
+        let remove = RemoveWatcherOnDrop {
+            watchers: self.watchers.clone(),
+            active: watcher.activation(),
+        };
+        self.watchers.write().await.push(watcher);
+
+        let latency = timeout(max_wait, async {
+            self.subscribe_confirmed(&topic).await.ok()?;
+            let millis = self
+                .now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis();
+            let start = Instant::now();
+            self.client
+                .publish(&topic, QoS::AtLeastOnce, false, millis.to_string())
+                .await
+                .ok()?;
+            receiver.recv().await?;
+            Some(start.elapsed())
+        })
+        .await
+        .ok()
+        .flatten();
+
+        drop(remove);
+        self.unsubscribe(&topic).await;
+        self.history.remove(&topic);
+        latency
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::task;
+
+    use super::*;
+    use crate::protocol::{self, EventLoop, MqttOptions, Request, SubscribeReasonCode};
+    use crate::{handle_incoming, LastWillConfig};
+
+    async fn next_request<T>(
+        eventloop: &mut EventLoop,
+        mut f: impl FnMut(Request) -> Option<T>,
+    ) -> T {
+        loop {
+            eventloop.clean();
+            if let Some(found) = eventloop.pending.drain(..).find_map(&mut f) {
+                return found;
+            }
+            task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_is_measured_and_cleaned_up() {
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            MqttOptions::new("test", "localhost", 1883),
+        );
+        let measuring = task::spawn({
+            let smarthome = smarthome.clone();
+            async move { smarthome.measure_latency(Duration::from_secs(5)).await }
+        });
+
+        let subscribe = next_request(&mut eventloop, |request| match request {
+            Request::Subscribe(subscribe) => Some(subscribe),
+            _ => None,
+        })
+        .await;
+        let topic = subscribe.filters[0].path.clone();
+        assert!(topic.starts_with("test/$latency/test/"), "{topic}");
+        smarthome.subscribe_sent(1);
+        let success = SubscribeReasonCode::Success(QoS::AtLeastOnce);
+        smarthome
+            .handle_suback(&protocol::suback(1, vec![success]))
+            .await;
+        let publish = next_request(&mut eventloop, |request| match request {
+            Request::Publish(publish) => Some(publish),
+            _ => None,
+        })
+        .await;
+        handle_incoming(&smarthome, topic, publish.payload, false).await;
+
+        assert!(measuring.await.unwrap().is_some());
+        assert_eq!(smarthome.watchers.read().await.len(), 0);
+        assert!(smarthome.subscriptions().await.is_empty());
+        assert!(smarthome.topics().await.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_is_none_and_cleaned_up() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        assert_eq!(
+            smarthome.measure_latency(Duration::from_secs(1)).await,
+            None
+        );
+        assert_eq!(smarthome.watchers.read().await.len(), 0);
+        assert!(smarthome.subscriptions().await.is_empty());
+    }
+}
@@ -1,29 +1,180 @@
+// The client error of MQTT 5 contains the whole request
+#![cfg_attr(feature = "v5", allow(clippy::result_large_err))]
+
+use core::future::Future;
 use core::time::Duration;
-use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::SystemTime;
 
-use rumqttc::{AsyncClient, EventLoop, LastWill, MqttOptions, QoS};
+use self::protocol::{AsyncClient, EventLoop, MqttOptions, QoS};
+use bytes::Bytes;
 use tokio::sync::mpsc::error::TrySendError;
-use tokio::sync::mpsc::Receiver;
-use tokio::sync::RwLock;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{watch, RwLock};
 use tokio::task;
-use tokio::time::sleep;
+use tokio::time::{sleep, timeout};
 
-pub use self::history_entry::HistoryEntry;
-use self::watcher::Watcher;
+pub use self::aggregate::Aggregate;
+use self::aggregate::NumericTracker;
+use self::availability::Availability;
+use self::birth::Birth;
+pub use self::birth::BirthMessage;
+#[cfg(feature = "testing")]
+pub use self::clock::MockClock;
+use self::clock::SharedClock;
+pub use self::clock::{Clock, SystemClock};
+pub use self::connected_state::ConnectedState;
+pub use self::connection_events::ConnectionEvent;
+use self::connection_events::Link;
+use self::dup_policy::DupFilter;
+pub use self::dup_policy::DupPolicy;
+#[cfg(feature = "json")]
+pub use self::error::PublishJsonError;
+pub use self::error::{
+    ConnectionFailure, PublishError, PublishManyError, SetError, SubscribeError,
+};
+pub use self::health::Health;
+use self::history::History;
+pub use self::history_entry::{EntrySource, HistoryEntry};
+use self::initialization::Initialization;
+pub use self::last_will::LastWillConfig;
+use self::manual_ack::ManualAckWatcher;
+use self::metrics::Metrics;
+pub use self::metrics::MetricsSnapshot;
+use self::offline_buffer::OfflineBuffer;
+pub use self::oversized::OversizedPayload;
+pub use self::payload::{BoolVocabulary, IntoPayload};
+pub use self::prepared::PreparedSubscription;
+pub use self::priority::Priority;
+use self::rate_limit::TokenBucket;
+pub use self::raw_events::RAW_EVENTS_CAPACITY;
+pub use self::republish::Republishing;
+pub use self::scheduled::ScheduledPublish;
+pub use self::smarthome_client::{BoxFuture, SmarthomeClient};
+use self::subscribe_ack::SubscribeAcks;
+use self::subscriptions::{Added, Subscriptions};
+pub use self::tap::ReceivedMessage;
+pub use self::tasmota::{TasmotaPrefixes, TasmotaState};
+#[cfg(feature = "testing")]
+pub use self::testing::FakeSmarthome;
+pub use self::topic_stats::TopicStats;
+use self::topic_stats::TopicStatsCollector;
+pub use self::watchdog::WatchdogEvent;
+pub use self::watcher::Edge;
+use self::watcher::{Derived, MatchedSender, RemoveWatcherOnDrop, Watcher};
+use self::watchers::Watchers;
 
+mod aggregate;
+mod availability;
+mod birth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod clock;
+mod close;
+mod combine;
+mod confirm;
+mod connected_state;
+mod connection_events;
+mod convention;
+mod csv;
+mod debounce;
+mod debug;
+#[cfg(feature = "homeassistant")]
+pub mod discovery;
+mod dry_run;
+mod dump;
+mod dup_policy;
+mod error;
+mod health;
+mod history;
 mod history_entry;
+pub mod homie;
+#[cfg(feature = "influx")]
+mod influx;
+mod initialization;
+mod json;
+#[cfg(feature = "json")]
+mod json_serde;
+mod last_will;
+mod latency;
+mod logging;
+mod loopback;
+mod manual_ack;
+mod metrics;
+mod offline_buffer;
+mod oversized;
 pub mod payload;
+mod persist;
+mod prepared;
+mod priority;
+pub mod protocol;
+mod rate_limit;
+mod raw_events;
+mod reconnect;
+mod republish;
+mod scheduled;
+mod smarthome_client;
+mod subscribe_ack;
+mod subscriptions;
+mod tap;
+mod tasmota;
+#[cfg(feature = "testing")]
+mod testing;
+mod topic_filter;
+mod topic_stats;
+mod watchdog;
 mod watcher;
+mod watchers;
 
 #[derive(Clone)]
 pub struct MqttSmarthome {
+    availability: Arc<Mutex<Vec<Availability>>>,
+    base_topic: String,
+    birth: Arc<Mutex<Birth>>,
+    bool_vocabulary: Arc<RwLock<BoolVocabulary>>,
     client: AsyncClient,
-    history: Arc<RwLock<HashMap<String, HistoryEntry>>>,
+    client_id: String,
+    clock: SharedClock,
+    connected: Arc<AtomicBool>,
+    connected_state: Arc<AtomicU8>,
+    connection_events: Arc<Mutex<Vec<Sender<ConnectionEvent>>>>,
+    dry_run: Arc<AtomicBool>,
+    dry_run_publishes: Arc<Mutex<Vec<Sender<watcher::ChannelPayload>>>>,
+    dup_filter: Arc<Mutex<DupFilter>>,
+    eventloop_task: Arc<Mutex<Option<task::JoinHandle<()>>>>,
+    gave_up: Arc<AtomicBool>,
+    history: Arc<History>,
+    initialization: Arc<Initialization>,
+    /// Milliseconds since the Unix epoch, 0 before the first message
+    last_received: Arc<AtomicU64>,
     last_will_retain: bool,
     last_will_topic: String,
-    subscribed: Arc<RwLock<HashSet<String>>>,
-    watchers: Arc<RwLock<Vec<Watcher>>>,
+    link: Arc<watch::Sender<Link>>,
+    loopback_local_publishes: Arc<AtomicBool>,
+    manual_ack_watchers: Arc<Mutex<Vec<ManualAckWatcher>>>,
+    manual_acks: bool,
+    max_reconnect_attempts: Arc<Mutex<Option<u32>>>,
+    metrics: Arc<Metrics>,
+    numeric: Arc<RwLock<NumericTracker>>,
+    /// Whether any topic is tracked by `numeric`, checked without locking it on every message
+    numeric_tracking: Arc<AtomicBool>,
+    max_payload_bytes: Arc<Mutex<Option<(usize, OversizedPayload)>>>,
+    offline_buffer: Arc<Mutex<Option<OfflineBuffer>>>,
+    prioritized: Arc<Mutex<Vec<String>>>,
+    rate_limit: Arc<Mutex<Option<TokenBucket>>>,
+    record_own_publishes: Arc<AtomicBool>,
+    raw_events: Arc<Mutex<Vec<Sender<protocol::Event>>>>,
+    scheduled: Arc<Mutex<HashMap<String, ScheduledPublish>>>,
+    subscribe_acks: Arc<SubscribeAcks>,
+    subscribed: Arc<RwLock<Subscriptions>>,
+    taps: Arc<Mutex<Vec<Sender<ReceivedMessage>>>>,
+    tasmota_prefixes: Arc<Mutex<TasmotaPrefixes>>,
+    topic_stats: Arc<RwLock<TopicStatsCollector>>,
+    /// Whether the `topic_stats` record, checked without locking them on every message
+    topic_stats_enabled: Arc<AtomicBool>,
+    watchers: Arc<RwLock<Watchers>>,
 }
 
 impl MqttSmarthome {
@@ -34,46 +185,148 @@ impl MqttSmarthome {
         Self::new_options(last_will_topic, last_will_retain, mqttoptions)
     }
 
+    /// Create the client with custom [`MqttOptions`].
+    ///
+    /// The base topic is everything of the `last_will_topic` before its last level, so `base/connected` results in `base`.
     #[must_use]
     pub fn new_options(
         last_will_topic: String,
         last_will_retain: bool,
-        mut mqttoptions: MqttOptions,
+        mqttoptions: MqttOptions,
     ) -> Self {
-        mqttoptions.set_last_will(LastWill::new(
-            &last_will_topic,
-            "0",
-            QoS::AtLeastOnce,
-            last_will_retain,
-        ));
+        Self::new_last_will(
+            LastWillConfig::new(last_will_topic, last_will_retain),
+            mqttoptions,
+        )
+    }
+
+    /// Same as [`new_options`](Self::new_options) with a custom payload and `QoS` of the last will.
+    ///
+    /// The default [birth message](LastWillConfig::birth_message) is still the [`ConnectedState`] published to the topic of the last will.
+    #[must_use]
+    pub fn new_last_will(last_will: LastWillConfig, mqttoptions: MqttOptions) -> Self {
+        let (smarthome, eventloop) = Self::new_detached(last_will, mqttoptions);
+        smarthome.set_eventloop_task(task::spawn(eventloop));
+        smarthome
+    }
+
+    /// Same as [`new_last_will`](Self::new_last_will) but the eventloop is returned instead of spawned.
+    ///
+    /// Nothing is sent or received until the returned future is polled, for example in an own `JoinSet` or selected against a shutdown signal.
+    /// The future ends after a [disconnect](Self::disconnect) or when giving up on reconnecting.
+    /// No tokio runtime is needed to call this.
+    /// As the task is not known to the client, [`close`](Self::close) can not wait for it.
+    pub fn new_detached(
+        last_will: LastWillConfig,
+        mqttoptions: MqttOptions,
+    ) -> (Self, impl Future<Output = ()> + Send + 'static) {
+        let (smarthome, eventloop) = Self::new_without_eventloop(last_will, mqttoptions);
+        let future = {
+            let smarthome = smarthome.clone();
+            async move {
+                handle_eventloop(&smarthome, eventloop).await;
+            }
+        };
+        (smarthome, future)
+    }
+
+    fn new_without_eventloop(
+        last_will: LastWillConfig,
+        mut mqttoptions: MqttOptions,
+    ) -> (Self, EventLoop) {
+        mqttoptions.set_last_will(last_will.to_last_will());
+        let LastWillConfig {
+            topic: last_will_topic,
+            retain: last_will_retain,
+            birth,
+            ..
+        } = last_will;
 
+        let base_topic = last_will_topic
+            .rsplit_once('/')
+            .map_or(last_will_topic.as_str(), |(base, _)| base)
+            .to_owned();
+        let client_id = mqttoptions.client_id();
+        let manual_acks = mqttoptions.manual_acks();
         let (client, eventloop) = AsyncClient::new(mqttoptions, 100);
+        let clock = SharedClock::default();
 
         let smarthome = Self {
+            availability: Arc::new(Mutex::new(Vec::new())),
+            base_topic,
+            birth: Arc::new(Mutex::new(birth)),
+            bool_vocabulary: Arc::new(RwLock::new(BoolVocabulary::default())),
             client,
-            history: Arc::new(RwLock::new(HashMap::new())),
+            client_id,
+            clock: clock.clone(),
+            connected: Arc::new(AtomicBool::new(false)),
+            connected_state: Arc::new(AtomicU8::new(ConnectedState::default() as u8)),
+            connection_events: Arc::new(Mutex::new(Vec::new())),
+            dry_run: Arc::new(AtomicBool::new(false)),
+            dry_run_publishes: Arc::new(Mutex::new(Vec::new())),
+            dup_filter: Arc::new(Mutex::new(DupFilter::default())),
+            eventloop_task: Arc::new(Mutex::new(None)),
+            gave_up: Arc::new(AtomicBool::new(false)),
+            history: Arc::new(History::with_clock(clock)),
+            initialization: Arc::new(Initialization::default()),
+            last_received: Arc::new(AtomicU64::new(0)),
             last_will_retain,
             last_will_topic,
-            subscribed: Arc::new(RwLock::new(HashSet::new())),
-            watchers: Arc::new(RwLock::new(Vec::new())),
+            link: Arc::new(watch::channel(Link::Connecting).0),
+            loopback_local_publishes: Arc::new(AtomicBool::new(false)),
+            manual_ack_watchers: Arc::new(Mutex::new(Vec::new())),
+            manual_acks,
+            max_reconnect_attempts: Arc::new(Mutex::new(None)),
+            metrics: Arc::new(Metrics::default()),
+            numeric: Arc::new(RwLock::new(NumericTracker::default())),
+            numeric_tracking: Arc::new(AtomicBool::new(false)),
+            max_payload_bytes: Arc::new(Mutex::new(None)),
+            offline_buffer: Arc::new(Mutex::new(None)),
+            prioritized: Arc::new(Mutex::new(Vec::new())),
+            rate_limit: Arc::new(Mutex::new(None)),
+            record_own_publishes: Arc::new(AtomicBool::new(true)),
+            raw_events: Arc::new(Mutex::new(Vec::new())),
+            scheduled: Arc::new(Mutex::new(HashMap::new())),
+            subscribe_acks: Arc::new(SubscribeAcks::default()),
+            subscribed: Arc::new(RwLock::new(Subscriptions::default())),
+            taps: Arc::new(Mutex::new(Vec::new())),
+            tasmota_prefixes: Arc::new(Mutex::new(TasmotaPrefixes::default())),
+            topic_stats: Arc::new(RwLock::new(TopicStatsCollector::default())),
+            topic_stats_enabled: Arc::new(AtomicBool::new(false)),
+            watchers: Arc::new(RwLock::new(Watchers::default())),
         };
+        (smarthome, eventloop)
+    }
 
-        task::spawn({
-            let smarthome = smarthome.clone();
-            async move {
-                handle_eventloop(&smarthome, eventloop).await;
-            }
-        });
-
+    /// Instance without a running eventloop. Requests to the client are queued but never sent.
+    #[cfg(test)]
+    pub(crate) fn new_for_tests() -> Self {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, eventloop) = Self::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        core::mem::forget(eventloop);
         smarthome
     }
 
     /// Disconnect from the MQTT broker.
+    ///
+    /// The eventloop ends once the disconnect is sent. Use [`close`](Self::close) to also wait for that.
     #[allow(clippy::missing_errors_doc)]
-    pub async fn disconnect(&self) -> Result<(), rumqttc::ClientError> {
+    pub async fn disconnect(&self) -> Result<(), protocol::ClientError> {
         self.client.disconnect().await
     }
 
+    /// The underlying rumqttc client for things this crate does not cover.
+    ///
+    /// Publishes made directly on it are not added to the history.
+    /// Subscriptions made directly on it are not deduplicated, are not restored on reconnect and mix up which acknowledgement belongs to which [subscription](Self::subscribe_confirmed).
+    #[must_use]
+    pub const fn client(&self) -> &AsyncClient {
+        &self.client
+    }
+
     /// Combines [`subscribe`](crate::MqttSmarthome::subscribe) and [`watch`](crate::MqttSmarthome::watch).
     pub async fn subscribe_and_watch(
         &self,
@@ -84,17 +337,241 @@ impl MqttSmarthome {
         self.watch(topic, allow_retained).await
     }
 
-    /// Subscribe to a MQTT `topic`.
+    /// Same as [`subscribe_and_watch`](crate::MqttSmarthome::subscribe_and_watch) but debounced per topic.
+    ///
+    /// Messages arriving within `window` of the last delivered message of the same topic are held back.
+    /// Once the `window` expires the latest held back message is delivered.
+    pub async fn subscribe_channel_debounced(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+        window: Duration,
+    ) -> Receiver<watcher::ChannelPayload> {
+        let receiver = self.subscribe_and_watch(topic, allow_retained).await;
+        debounce::debounce(receiver, window)
+    }
+
+    /// Same as [`subscribe_and_watch`](crate::MqttSmarthome::subscribe_and_watch) but skips messages with the same payload as the previous one of the same topic.
+    ///
+    /// Retained messages count as the previous payload even when `allow_retained` is false.
+    /// A message dropped because the channel was full does not count, so its payload is delivered again.
+    /// The remembered payloads are dropped together with the channel.
+    pub async fn subscribe_channel_distinct(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<watcher::ChannelPayload> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new(topic, allow_retained);
+        self.watchers.write().await.push(watcher.distinct());
+        receiver
+    }
+
+    /// Same as [`subscribe_and_watch`](crate::MqttSmarthome::subscribe_and_watch) but starts with the latest known payload of every matching topic in the history.
+    ///
+    /// When the `topic` is already subscribed the broker does not send the retained messages again.
+    /// Replaying the history makes the channel behave like one created before the messages arrived.
+    /// The replayed payloads are delivered even when `allow_retained` is false and always arrive before newer messages of the same topic.
+    /// They are replayed in the order they were received, topics of [high priority](Priority::High) filters first.
+    ///
+    /// With the [history disabled](Self::set_history_enabled) there is nothing to replay and this behaves like [`subscribe_and_watch`](Self::subscribe_and_watch).
+    pub async fn subscribe_channel_replay(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<watcher::ChannelPayload> {
+        if !self.history.is_enabled() {
+            logging::warning!(topic = topic; "MQTT replay of {topic} requested with the history disabled, nothing is replayed");
+        }
+        self.subscribe(topic).await;
+        // Messages are inserted into the history before the watchers are read, holding the watchers keeps newer messages behind the replay
+        let prioritized = self.prioritized();
+        let mut watchers = self.watchers.write().await;
+        let mut known = self.history.filter_map(|known, entry| {
+            topic_filter::matches(known, topic).then(|| {
+                (
+                    !priority::is_prioritized(&prioritized, known),
+                    entry.time(),
+                    known.clone(),
+                    entry.payload().into_owned(),
+                    entry.source(),
+                )
+            })
+        });
+        known.sort_unstable_by(
+            |(a_normal, a_time, a_topic, ..), (b_normal, b_time, b_topic, ..)| {
+                a_normal
+                    .cmp(b_normal)
+                    .then_with(|| a_time.cmp(b_time))
+                    .then_with(|| a_topic.cmp(b_topic))
+            },
+        );
+        let (watcher, receiver) =
+            Watcher::new_with_capacity(topic, allow_retained, 25 + known.len());
+        for (_, _, known, payload, source) in known {
+            _ = watcher.replay(&known, &payload, source);
+        }
+        watchers.push(watcher);
+        drop(watchers);
+        receiver
+    }
+
+    /// Same as [`subscribe_and_watch`](crate::MqttSmarthome::subscribe_and_watch) but topic and payload are shared instead of cloned.
+    ///
+    /// Every channel of this kind getting a message shares the same allocation, which helps with many channels on busy topics.
+    pub async fn subscribe_channel_arc(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<watcher::ArcPayload> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new_arc(topic, allow_retained);
+        self.watchers.write().await.push(watcher);
+        receiver
+    }
+
+    /// Same as [`subscribe_and_watch`](crate::MqttSmarthome::subscribe_and_watch) but with the [`EntrySource`] of the message.
+    ///
+    /// Only messages [looped back](Self::set_loopback_local_publishes) are [`EntrySource::Published`].
+    pub async fn subscribe_channel_sourced(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<watcher::SourcedPayload> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new_sourced(topic, allow_retained);
+        self.watchers.write().await.push(watcher);
+        receiver
+    }
+
+    /// Same as [`subscribe_and_watch`](crate::MqttSmarthome::subscribe_and_watch) but with the payload as it was received.
+    ///
+    /// The other channels replace invalid UTF-8 sequences with `�`.
+    pub async fn subscribe_bytes_channel(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<watcher::BytesPayload> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new_bytes(topic, allow_retained);
+        self.watchers.write().await.push(watcher);
+        receiver
+    }
+
+    /// Subscribe to the `topic` and only get messages with a payload differing from the previous one in the history.
+    ///
+    /// The third element is the previous payload of the topic.
+    /// As the history is shared this also skips messages repeating a payload that was published by this client or redelivered as retained message after a reconnect.
+    pub async fn subscribe_changes(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<watcher::ChangePayload> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new_changes(topic, allow_retained);
+        self.watchers.write().await.push(watcher);
+        receiver
+    }
+
+    /// Subscribe to the `topic` and get notified when its boolean state changes.
+    ///
+    /// The state is detected with [`payload::is_true`] and compared with the previous payload in the history.
+    /// The first message of a topic produces no edge unless there was an earlier (for example retained) message establishing the state.
+    pub async fn subscribe_edges(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<(String, Edge)> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new_edges(topic, allow_retained);
+        self.watchers.write().await.push(watcher);
+        receiver
+    }
+
+    /// Subscribe to the `topic` and get the numeric value together with the difference to the previous value in the history.
+    ///
+    /// Messages are skipped when the new or the previous payload is not a number.
+    /// Retained messages are not delivered but establish the previous value.
+    pub async fn subscribe_deltas(&self, topic: &str) -> Receiver<watcher::DeltaPayload> {
+        self.subscribe(topic).await;
+        let (watcher, receiver) = Watcher::new_deltas(topic, false);
+        self.watchers.write().await.push(watcher);
+        receiver
+    }
+
+    /// Subscribe to a MQTT `topic` with `QoS` 1.
+    ///
+    /// Shared subscriptions (`$share/{group}/{filter}`) are kept separately from the plain `filter` as the broker handles them differently.
+    /// When the broker rejects the subscription a warning is logged and it is forgotten, so subscribing again retries it.
+    /// Use [`subscribe_confirmed`](Self::subscribe_confirmed) to wait for the outcome.
+    ///
+    /// Subscribed filters covered by the `topic` (like `foo/bar` by `foo/+`) are unsubscribed once the broker acknowledged the `topic`.
     /// # Panics
-    /// Panics when the MQTT eventloop is gone.
+    /// Panics when the MQTT eventloop is gone unless the client [gave up](Self::has_given_up).
     pub async fn subscribe(&self, topic: &str) {
-        let is_new = self.subscribed.write().await.insert(topic.to_owned());
-        if is_new {
-            self.client
-                .subscribe(topic, QoS::AtLeastOnce)
+        self.subscribe_qos(topic, QoS::AtLeastOnce).await;
+    }
+
+    /// Subscribe to a MQTT `topic` with the given `qos`. See [`subscribe`](Self::subscribe).
+    ///
+    /// Subscribing again with a higher `qos` subscribes again on the broker, a lower `qos` keeps the higher one.
+    /// Covered filters are only unsubscribed when the `qos` is at least theirs so no delivery guarantee is lowered.
+    /// # Panics
+    /// Panics when the MQTT eventloop is gone unless the client [gave up](Self::has_given_up).
+    pub async fn subscribe_qos(&self, topic: &str, qos: QoS) {
+        let Added::New { superseded } = self.subscribed.write().await.add(topic, qos) else {
+            return;
+        };
+        if superseded.is_empty() {
+            if let Err(error) = self
+                .send_subscribe(vec![(topic.to_owned(), qos)], None)
                 .await
-                .expect("failed to subscribe to MQTT");
+            {
+                self.request_failed("subscribe", &error);
+            }
+            return;
+        }
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        if let Err(error) = self
+            .send_subscribe(vec![(topic.to_owned(), qos)], Some(sender))
+            .await
+        {
+            self.request_failed("subscribe", &error);
+            return;
         }
+        let smarthome = self.clone();
+        task::spawn(async move {
+            let acknowledged = receiver
+                .await
+                .unwrap_or(Err(SubscribeError::ConnectionLost));
+            smarthome
+                .unsubscribe_superseded(superseded, &acknowledged)
+                .await;
+        });
+    }
+
+    /// Return all subscribed topic filters sorted.
+    pub async fn subscriptions(&self) -> Vec<String> {
+        let mut subscriptions = self
+            .subscribed
+            .read()
+            .await
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        subscriptions.sort_unstable();
+        subscriptions
+    }
+
+    /// Check whether any subscribed topic filter matches the concrete `topic`.
+    ///
+    /// Shared subscriptions (`$share/{group}/{filter}`) match with their `filter`.
+    pub async fn is_subscribed(&self, topic: &str) -> bool {
+        self.subscribed
+            .read()
+            .await
+            .iter()
+            .any(|filter| topic_filter::matches(topic, filter))
     }
 
     /// Watch for new messages on the `topic`.
@@ -110,119 +587,1794 @@ impl MqttSmarthome {
         receiver
     }
 
+    /// Watch for new messages on the `topic` but only subscribe to it once the [`PreparedSubscription`] is activated.
+    ///
+    /// No messages are delivered before the activation.
+    /// When another subscription already covers the `topic`, the messages received in between are replayed from the history on activation.
+    /// Dropping the [`PreparedSubscription`] without activating it removes the watcher again.
+    pub async fn prepare_channel(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> (Receiver<watcher::ChannelPayload>, PreparedSubscription) {
+        let (watcher, receiver) = Watcher::new(topic, allow_retained);
+        let (watcher, active) = watcher.deactivated();
+        let mut watchers = self.watchers.write().await;
+        // Prepared watchers dropped while the lock was busy are only deactivated
+        watchers.retain(|watcher| !watcher.is_closed());
+        watchers.push(watcher);
+        drop(watchers);
+        let prepared = PreparedSubscription::new(
+            self.clone(),
+            topic.to_owned(),
+            allow_retained,
+            active,
+            self.now(),
+        );
+        (receiver, prepared)
+    }
+
+    /// Wait for the next message on the `topic`.
+    ///
+    /// Retained messages are ignored as they represent an already known state.
+    /// Returns `None` when no message arrived within `max_wait`.
+    ///
+    /// Requires the topic to be subscribed to notice them.
+    /// Start waiting before doing whatever should result in the message (like publishing a command) to not miss it.
+    pub async fn wait_for_message(
+        &self,
+        topic: &str,
+        max_wait: Duration,
+    ) -> Option<watcher::ChannelPayload> {
+        let (watcher, mut receiver) = Watcher::new(topic, false);
+        let _remove = RemoveWatcherOnDrop {
+            watchers: self.watchers.clone(),
+            active: watcher.activation(),
+        };
+        self.watchers.write().await.push(watcher);
+        timeout(max_wait, receiver.recv()).await.ok().flatten()
+    }
+
+    /// Whether messages are kept in the history. Enabled by default.
+    ///
+    /// Disabling removes every entry and saves the memory and allocations of it when only channels are used.
+    /// Everything based on the history then behaves like nothing was received yet:
+    /// [`last`](Self::last) and its variants return `None`, [`publish_if_changed`](Self::publish_if_changed) always publishes,
+    /// [`subscribe_channel_replay`](Self::subscribe_channel_replay) replays nothing and change based watchers see every message as a change.
+    pub fn set_history_enabled(&self, enabled: bool) {
+        self.history.set_enabled(enabled);
+    }
+
+    /// Limit the history to `max_entries` topics and entries younger than `max_age`.
+    ///
+    /// When there are more topics than `max_entries` the ones with the oldest entries are removed first.
+    /// Entries older than `max_age` are treated as absent.
+    /// `None` means no limit, which is the default.
+    #[allow(clippy::unused_async)]
+    pub async fn set_history_limits(&self, max_entries: Option<usize>, max_age: Option<Duration>) {
+        self.history.set_limits(max_entries, max_age);
+    }
+
+    /// Keep the given amount of `HistoryEntry` per topic. Defaults to only the last one.
+    #[allow(clippy::unused_async)]
+    pub async fn set_history_size(&self, entries_per_topic: usize) {
+        self.history.set_entries_per_topic(entries_per_topic);
+    }
+
+    /// Keep the given amount of `HistoryEntry` for topics starting with the `topic_prefix`.
+    ///
+    /// When multiple prefixes match a topic the longest one is used.
+    #[allow(clippy::unused_async)]
+    pub async fn set_history_size_for(&self, topic_prefix: &str, entries_per_topic: usize) {
+        self.history
+            .set_entries_per_topic_prefix(topic_prefix, entries_per_topic);
+    }
+
+    /// Return the known `HistoryEntry` of the given `topic` from oldest to newest.
+    ///
+    /// Only contains the last one unless the history size is increased via [`set_history_size`](Self::set_history_size).
+    #[allow(clippy::unused_async)]
+    pub async fn history_of(&self, topic: &str) -> Vec<HistoryEntry> {
+        self.history.history_of(topic)
+    }
+
+    /// Return up to the two last `HistoryEntry` of the given `topic`, the newest first.
+    ///
+    /// Only returns the previous entry when the history keeps it, see [`set_history_size`](Self::set_history_size).
+    pub async fn last_two(&self, topic: &str) -> Vec<HistoryEntry> {
+        let mut entries = self.history_of(topic).await;
+        entries.reverse();
+        entries.truncate(2);
+        entries
+    }
+
     /// Return the last `HistoryEntry` of the given `topic`.
+    #[allow(clippy::unused_async)]
     pub async fn last(&self, topic: &str) -> Option<HistoryEntry> {
-        self.history.read().await.get(topic).cloned()
+        self.history.get(topic)
+    }
+
+    /// Return a copy of the last `HistoryEntry` of every topic.
+    ///
+    /// With the `serde` feature the snapshot can be serialized, for example as JSON for a status page.
+    #[allow(clippy::unused_async)]
+    pub async fn history_snapshot(&self) -> HashMap<String, HistoryEntry> {
+        self.history.snapshot()
+    }
+
+    /// Return all topics known in the history sorted.
+    #[allow(clippy::unused_async)]
+    pub async fn topics(&self) -> Vec<String> {
+        let mut topics = self.history.topics();
+        topics.sort_unstable();
+        topics
+    }
+
+    /// Return all topics known in the history matching the MQTT topic `filter` sorted.
+    ///
+    /// An invalid `filter` matches nothing.
+    #[allow(clippy::unused_async)]
+    pub async fn topics_matching(&self, filter: &str) -> Vec<String> {
+        if !topic_filter::is_valid(filter) {
+            return Vec::new();
+        }
+        let mut topics = self
+            .history
+            .filter_map(|topic, _| topic_filter::matches(topic, filter).then(|| topic.clone()));
+        topics.sort_unstable();
+        topics
+    }
+
+    /// Return the last `HistoryEntry` of every topic matching the MQTT topic `filter` sorted by topic.
+    ///
+    /// An invalid `filter` matches nothing.
+    #[allow(clippy::unused_async)]
+    pub async fn last_matching(&self, filter: &str) -> Vec<(String, HistoryEntry)> {
+        if !topic_filter::is_valid(filter) {
+            return Vec::new();
+        }
+        let mut matching = self.history.filter_map(|topic, entry| {
+            topic_filter::matches(topic, filter).then(|| (topic.clone(), entry.clone()))
+        });
+        matching.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        matching
+    }
+
+    /// Time since the last message was received on any topic.
+    #[allow(clippy::unused_async)]
+    pub async fn since_last_received(&self) -> Option<Duration> {
+        self.last_received_ago()
+    }
+
+    fn last_received_ago(&self) -> Option<Duration> {
+        let millis = self.last_received.load(Ordering::Relaxed);
+        let last_received =
+            (millis > 0).then(|| SystemTime::UNIX_EPOCH + Duration::from_millis(millis))?;
+        Some(self.now().duration_since(last_received).unwrap_or_default())
+    }
+
+    /// Time since the last `HistoryEntry` of the `topic`.
+    ///
+    /// As the history also contains own publishes this includes them too.
+    #[allow(clippy::unused_async)]
+    pub async fn since_last_received_on(&self, topic: &str) -> Option<Duration> {
+        let now = self.now();
+        self.history.get(topic).map(|entry| entry.age_at(now))
+    }
+
+    /// Time since the most recent `HistoryEntry` of all topics matching the MQTT topic `filter`.
+    ///
+    /// An invalid `filter` matches nothing.
+    #[allow(clippy::unused_async)]
+    pub async fn since_last_received_matching(&self, filter: &str) -> Option<Duration> {
+        if !topic_filter::is_valid(filter) {
+            return None;
+        }
+        let now = self.now();
+        self.history
+            .filter_map(|topic, entry| {
+                topic_filter::matches(topic, filter).then(|| entry.age_at(now))
+            })
+            .into_iter()
+            .min()
+    }
+
+    /// Words used by [`last_is_true`](crate::MqttSmarthome::last_is_true) and [`last_as_bool_strict`](crate::MqttSmarthome::last_as_bool_strict).
+    ///
+    /// The payloads are interpreted when they are read, so the vocabulary also applies to payloads received before setting it.
+    pub async fn set_bool_vocabulary(&self, vocabulary: BoolVocabulary) {
+        *self.bool_vocabulary.write().await = vocabulary;
     }
 
-    /// Shortcut for `.last(topic).await.is_some_and(|o| o.as_boolean())`
+    /// Whether the last payload of the `topic` is `true` according to the [vocabulary](crate::MqttSmarthome::set_bool_vocabulary).
+    ///
+    /// Unknown payloads are assumed to be `true`. Is `false` when there is no payload.
     pub async fn last_is_true(&self, topic: &str) -> bool {
+        let vocabulary = self.bool_vocabulary.read().await;
         self.history
-            .read()
-            .await
             .get(topic)
-            .is_some_and(HistoryEntry::as_boolean)
+            .is_some_and(|entry| payload::is_true_with(&entry.payload(), &vocabulary))
+    }
+
+    /// State of the last payload of the `topic` according to the [vocabulary](crate::MqttSmarthome::set_bool_vocabulary).
+    ///
+    /// Unknown payloads result in `None`.
+    pub async fn last_as_bool_strict(&self, topic: &str) -> Option<bool> {
+        let vocabulary = self.bool_vocabulary.read().await;
+        self.history.get_and_then(topic, |entry| {
+            payload::as_bool_with(&entry.payload(), &vocabulary)
+        })
     }
 
     /// Shortcut for `.last(topic).await.and_then(|o| o.as_float())`
+    #[allow(clippy::unused_async)]
     pub async fn last_float(&self, topic: &str) -> Option<f32> {
-        self.history
-            .read()
-            .await
-            .get(topic)
-            .and_then(HistoryEntry::as_float)
+        self.history.get_and_then(topic, HistoryEntry::as_float)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_double())`
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_double(&self, topic: &str) -> Option<f64> {
+        self.history.get_and_then(topic, HistoryEntry::as_double)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_celsius())`
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_celsius(&self, topic: &str) -> Option<f32> {
+        self.history.get_and_then(topic, HistoryEntry::as_celsius)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_percent())`
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_percent(&self, topic: &str) -> Option<f32> {
+        self.history.get_and_then(topic, HistoryEntry::as_percent)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_duration())`
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_duration(&self, topic: &str) -> Option<Duration> {
+        self.history.get_and_then(topic, HistoryEntry::as_duration)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_timestamp())`
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_timestamp(&self, topic: &str) -> Option<SystemTime> {
+        self.history.get_and_then(topic, HistoryEntry::as_timestamp)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.parse())` without cloning the entry
+    #[allow(clippy::unused_async)]
+    pub async fn last_parsed<T>(&self, topic: &str) -> Option<T>
+    where
+        T: core::str::FromStr,
+    {
+        self.history.get_and_then(topic, HistoryEntry::parse)
+    }
+
+    /// Shortcut for `.last(topic).await.and_then(|o| o.as_int())`
+    #[allow(clippy::unused_async)]
+    pub async fn last_as_int(&self, topic: &str) -> Option<i64> {
+        self.history.get_and_then(topic, HistoryEntry::as_int)
     }
 
     /// Publish a `payload` to a MQTT `topic`.
-    /// # Panics
-    /// Panics when the MQTT eventloop is gone.
-    pub async fn publish<P>(&self, topic: &str, payload: P, retain: bool)
+    ///
+    /// When the [offline buffer](crate::MqttSmarthome::set_offline_buffer) is enabled and the broker is not connected the message is buffered instead.
+    ///
+    /// # Errors
+    /// Returns [`PublishError::InvalidTopic`] when the topic is empty, contains wildcards or starts with `$`.
+    /// Returns [`PublishError::Client`] when the MQTT eventloop is gone.
+    pub async fn publish<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+    ) -> Result<(), PublishError>
     where
-        P: ToString + Send,
+        P: IntoPayload,
     {
-        let payload = payload.to_string();
-        self.client
-            .publish(topic, QoS::AtLeastOnce, retain, payload.clone())
+        if let Err(error) = PublishError::check_topic(topic) {
+            Metrics::increase(&self.metrics.publish_errors);
+            return Err(error);
+        }
+        let payload = payload.into_payload();
+        if self.dry_run_publish(topic, &payload, retain).await {
+            return Ok(());
+        }
+        if !self.connected.load(Ordering::Relaxed)
+            && self.buffer_offline(topic, payload.clone(), retain)
+        {
+            self.insert_history(topic, &payload, retain).await;
+            return Ok(());
+        }
+        self.publish_now(topic, payload, retain, QoS::AtLeastOnce)
             .await
-            .expect("failed to publish to MQTT");
+    }
 
-        self.history
-            .write()
+    /// Same as [`publish`](Self::publish) with another [`QoS`] than [`QoS::AtLeastOnce`].
+    ///
+    /// Use [`QoS::ExactlyOnce`] for commands which must not be executed twice.
+    /// Messages are never put into the offline buffer.
+    ///
+    /// # Errors
+    /// Returns [`PublishError::InvalidTopic`] when the topic is empty, contains wildcards or starts with `$`.
+    /// Returns [`PublishError::Client`] when the MQTT eventloop is gone.
+    pub async fn publish_qos<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        qos: QoS,
+    ) -> Result<(), PublishError>
+    where
+        P: IntoPayload,
+    {
+        if let Err(error) = PublishError::check_topic(topic) {
+            Metrics::increase(&self.metrics.publish_errors);
+            return Err(error);
+        }
+        self.publish_now(topic, payload.into_payload(), retain, qos)
             .await
-            .insert(topic.to_owned(), HistoryEntry::new(payload));
     }
-}
 
-async fn handle_eventloop(smarthome: &MqttSmarthome, mut eventloop: EventLoop) {
-    loop {
-        match eventloop.poll().await {
-            Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(packet))) => {
-                println!("MQTT connected {packet:?}");
+    /// Put the message into the offline buffer. Returns false when the buffer is disabled or the client [gave up](Self::has_given_up).
+    fn buffer_offline(&self, topic: &str, payload: Bytes, retain: bool) -> bool {
+        if self.has_given_up() {
+            return false;
+        }
+        self.offline_buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .map(|buffer| buffer.push(topic.to_owned(), payload, retain))
+            .is_some()
+    }
 
-                let smarthome = smarthome.clone();
-                task::spawn(async move {
-                    let topics = smarthome.subscribed.read().await.clone();
-                    #[allow(clippy::iter_over_hash_type)]
-                    for topic in topics {
-                        smarthome
-                            .client
-                            .subscribe(topic, QoS::AtLeastOnce)
-                            .await
-                            .expect("failed to subscribe after reconnect");
-                    }
+    async fn publish_now(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        retain: bool,
+        qos: QoS,
+    ) -> Result<(), PublishError> {
+        if self.dry_run_publish(topic, &payload, retain).await {
+            return Ok(());
+        }
+        self.throttle().await;
+        let result = self
+            .client
+            .publish_bytes(topic, qos, retain, payload.clone())
+            .await;
+        if let Err(error) = result {
+            Metrics::increase(&self.metrics.publish_errors);
+            return Err(error.into());
+        }
+        self.record_published(topic, &payload, retain).await;
+        Ok(())
+    }
 
-                    smarthome
-                        .client
-                        .publish(
-                            &smarthome.last_will_topic,
-                            QoS::AtLeastOnce,
-                            smarthome.last_will_retain,
-                            "2",
-                        )
-                        .await
-                        .expect("failed to publish connected");
-                    println!("MQTT connection fully initialized");
-                });
-            }
-            Ok(rumqttc::Event::Incoming(rumqttc::Incoming::Publish(publish))) if !publish.dup => {
-                if let Ok(payload) = String::from_utf8(publish.payload.into()) {
-                    smarthome
-                        .history
-                        .write()
-                        .await
-                        .insert(publish.topic.clone(), HistoryEntry::new(payload.clone()));
-
-                    let senders = smarthome
-                        .watchers
-                        .read()
-                        .await
-                        .iter()
-                        .filter_map(|watcher| {
-                            watcher.matching_sender(&publish.topic, publish.retain)
-                        })
-                        .collect::<Vec<_>>();
-                    for sender in senders {
-                        match sender.try_send((publish.topic.clone(), payload.clone())) {
-                            Ok(()) => {}
-                            Err(TrySendError::Closed((topic, _))) => {
-                                panic!("MQTT watcher receiver closed. Topic: {topic}");
-                            }
-                            Err(TrySendError::Full((topic, _))) => {
-                                eprintln!("MQTT watcher receiver buffer is full. Topic: {topic}");
-                            }
-                        }
+    /// Same as [`publish`](Self::publish) with MQTT 5 properties like user properties.
+    ///
+    /// Messages are never put into the offline buffer.
+    ///
+    /// # Errors
+    /// Returns [`PublishError::InvalidTopic`] when the topic is empty, contains wildcards or starts with `$`.
+    /// Returns [`PublishError::Client`] when the MQTT eventloop is gone.
+    #[cfg(feature = "v5")]
+    pub async fn publish_with_properties<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        properties: protocol::PublishProperties,
+    ) -> Result<(), PublishError>
+    where
+        P: IntoPayload,
+    {
+        if let Err(error) = PublishError::check_topic(topic) {
+            Metrics::increase(&self.metrics.publish_errors);
+            return Err(error);
+        }
+        let payload = payload.into_payload();
+        if self.dry_run_publish(topic, &payload, retain).await {
+            return Ok(());
+        }
+        self.throttle().await;
+        let result = self
+            .client
+            .publish_bytes_with_properties(
+                topic,
+                QoS::AtLeastOnce,
+                retain,
+                payload.clone(),
+                properties,
+            )
+            .await;
+        if let Err(error) = result {
+            Metrics::increase(&self.metrics.publish_errors);
+            return Err(error.into());
+        }
+        self.record_published(topic, &payload, retain).await;
+        Ok(())
+    }
+
+    /// Same as [`publish`](Self::publish) but the broker drops the message when it could not be delivered within `expiry`.
+    ///
+    /// Useful for commands which are harmful when delivered late.
+    /// The expiry is rounded up to full seconds.
+    ///
+    /// # Errors
+    /// Returns [`PublishError::InvalidTopic`] when the topic is empty, contains wildcards or starts with `$`.
+    /// Returns [`PublishError::Client`] when the MQTT eventloop is gone.
+    #[cfg(feature = "v5")]
+    pub async fn publish_with_expiry<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        expiry: Duration,
+    ) -> Result<(), PublishError>
+    where
+        P: IntoPayload,
+    {
+        let secs = expiry.as_secs() + u64::from(expiry.subsec_nanos() > 0);
+        let properties = protocol::PublishProperties {
+            message_expiry_interval: Some(u32::try_from(secs).unwrap_or(u32::MAX)),
+            ..protocol::PublishProperties::default()
+        };
+        self.publish_with_properties(topic, payload, retain, properties)
+            .await
+    }
+
+    /// Buffer up to `capacity` messages published via [`publish`](crate::MqttSmarthome::publish) while the broker is not connected.
+    /// `None` disables the buffer, which is the default.
+    ///
+    /// When the buffer is full the oldest message is dropped.
+    /// Retained messages replace the buffered retained message of the same topic.
+    /// The buffer is published in order once connected again before resubscribing.
+    /// Disabling the buffer drops the buffered messages.
+    pub fn set_offline_buffer(&self, capacity: Option<usize>) {
+        *self
+            .offline_buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = capacity.map(OfflineBuffer::new);
+    }
+
+    /// Amount of messages currently waiting in the offline buffer.
+    #[must_use]
+    pub fn offline_buffered(&self) -> usize {
+        self.offline_buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map_or(0, OfflineBuffer::len)
+    }
+
+    /// Publish the messages in the offline buffer in order, the ones to [high priority](Priority::High) topics first.
+    async fn flush_offline_buffer(&self) {
+        let messages = self
+            .offline_buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .map(OfflineBuffer::take)
+            .unwrap_or_default();
+        let prioritized = self.prioritized();
+        let (high, normal) = messages
+            .into_iter()
+            .partition::<Vec<_>, _>(|(topic, ..)| priority::is_prioritized(&prioritized, topic));
+        let mut messages = high.into_iter().chain(normal).collect::<VecDeque<_>>();
+        while let Some((topic, payload, retain)) = messages.pop_front() {
+            self.throttle().await;
+            let result = self
+                .client
+                .publish_bytes(topic.clone(), QoS::AtLeastOnce, retain, payload.clone())
+                .await;
+            if let Err(error) = result {
+                Metrics::increase(&self.metrics.publish_errors);
+                logging::status_warning!(client_id = self.client_id.as_str(), topic = topic.as_str(); "MQTT failed to publish buffered message to {topic}: {error}");
+                messages.push_front((topic, payload, retain));
+                if self.has_given_up() {
+                    return;
+                }
+                if let Some(buffer) = self
+                    .offline_buffer
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_mut()
+                {
+                    buffer.restore(messages);
+                }
+                return;
+            }
+            Metrics::increase(&self.metrics.published);
+        }
+    }
+
+    /// Limit the amount of publishes per second. `None` disables the limit, which is the default.
+    ///
+    /// Publishing waits when the limit is reached instead of dropping messages.
+    /// Short bursts up to the amount per second are allowed.
+    ///
+    /// # Panics
+    /// Panics when `max_publishes_per_second` is not positive.
+    pub fn set_publish_rate_limit(&self, max_publishes_per_second: Option<f64>) {
+        let bucket = max_publishes_per_second.map(|per_second| {
+            assert!(per_second > 0.0, "rate limit has to be positive");
+            TokenBucket::new(per_second)
+        });
+        *self
+            .rate_limit
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = bucket;
+    }
+
+    /// Wait until the rate limit allows the next publish.
+    async fn throttle(&self) {
+        let wait = self
+            .rate_limit
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| {
+                bucket.acquire(tokio::time::Instant::now())
+            });
+        if !wait.is_zero() {
+            let micros = u64::try_from(wait.as_micros()).unwrap_or(u64::MAX);
+            self.metrics
+                .throttled_micros
+                .fetch_add(micros, Ordering::Relaxed);
+            sleep(wait).await;
+        }
+    }
+
+    /// Update the history and metrics after successfully publishing.
+    async fn record_published(&self, topic: &str, payload: &[u8], retain: bool) {
+        Metrics::increase(&self.metrics.published);
+        self.insert_history(topic, payload, retain).await;
+    }
+
+    async fn insert_history(&self, topic: &str, payload: &[u8], retain: bool) {
+        let previous = self.insert_published(topic, payload, retain);
+        self.loop_back(topic, payload, previous.as_ref()).await;
+    }
+
+    /// Insert the published message into the history unless [disabled](Self::set_record_own_publishes).
+    ///
+    /// Returns the previous entry of the topic which is the current one when not recorded.
+    fn insert_published(&self, topic: &str, payload: &[u8], retain: bool) -> Option<HistoryEntry> {
+        if !self.history.is_enabled() {
+            return None;
+        }
+        if !self.record_own_publishes.load(Ordering::Relaxed) {
+            return self.history.get(topic);
+        }
+        let entry = HistoryEntry::from_bytes_at(payload, self.now())
+            .with_retained(retain)
+            .with_source(EntrySource::Published);
+        self.history.insert(topic.to_owned(), entry)
+    }
+
+    /// Whether messages published by this client are inserted into the history. Enabled by default.
+    ///
+    /// When disabled the history only contains what was received from the broker.
+    /// For example a command to a `set` topic then does not make [`last`](Self::last) of it return a value the device never applied.
+    /// [`publish_if_changed`](Self::publish_if_changed) then compares with the received payloads only.
+    pub fn set_record_own_publishes(&self, enabled: bool) {
+        self.record_own_publishes.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Publish a `payload` to a MQTT `topic` and retry when the request queue of the client is full.
+    ///
+    /// The wait before each retry starts with `backoff` and doubles every time.
+    /// The history is only updated when publishing succeeded.
+    ///
+    /// # Errors
+    /// Returns the last error when all `attempts` failed.
+    /// Invalid topics fail immediately without retrying.
+    pub async fn publish_with_retry<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        attempts: usize,
+        backoff: Duration,
+    ) -> Result<(), PublishError>
+    where
+        P: IntoPayload,
+    {
+        if let Err(error) = PublishError::check_topic(topic) {
+            Metrics::increase(&self.metrics.publish_errors);
+            return Err(error);
+        }
+        let payload = payload.into_payload();
+        if self.dry_run_publish(topic, &payload, retain).await {
+            return Ok(());
+        }
+        let mut wait = backoff;
+        let mut attempt = 1;
+        self.throttle().await;
+        loop {
+            match self
+                .client
+                .try_publish(topic, QoS::AtLeastOnce, retain, payload.to_vec())
+            {
+                Ok(()) => {
+                    self.record_published(topic, &payload, retain).await;
+                    return Ok(());
+                }
+                Err(error) => {
+                    Metrics::increase(&self.metrics.publish_errors);
+                    if attempt >= attempts {
+                        return Err(error.into());
+                    }
+                }
+            }
+            sleep(wait).await;
+            wait *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// Publish multiple messages of `(topic, payload, retain)` in the given order.
+    ///
+    /// The history is updated once afterwards for all published messages.
+    ///
+    /// # Errors
+    /// Stops at the first message failing to be published.
+    /// The error contains its index so the remaining messages can be retried.
+    pub async fn publish_many(
+        &self,
+        messages: Vec<(String, String, bool)>,
+    ) -> Result<(), PublishManyError> {
+        let mut published = Vec::with_capacity(messages.len());
+        let mut result = Ok(());
+        for (index, (topic, payload, retain)) in messages.into_iter().enumerate() {
+            let publish = async {
+                PublishError::check_topic(&topic)?;
+                if self.announce_dry_run(&topic, payload.as_bytes(), retain) {
+                    return Ok(false);
+                }
+                self.throttle().await;
+                self.client
+                    .publish(&topic, QoS::AtLeastOnce, retain, payload.clone())
+                    .await?;
+                Ok(true)
+            };
+            match publish.await {
+                Ok(sent) => {
+                    if sent {
+                        Metrics::increase(&self.metrics.published);
                     }
                 }
+                Err(error) => {
+                    Metrics::increase(&self.metrics.publish_errors);
+                    result = Err(PublishManyError { index, error });
+                    break;
+                }
+            }
+            published.push((topic, payload, retain));
+        }
+
+        let published = published
+            .into_iter()
+            .map(|(topic, payload, retain)| {
+                let previous = self.insert_published(&topic, payload.as_bytes(), retain);
+                (topic, payload, previous)
+            })
+            .collect::<Vec<_>>();
+        for (topic, payload, previous) in published {
+            self.loop_back(&topic, payload.as_bytes(), previous.as_ref())
+                .await;
+        }
+        result
+    }
+
+    /// Publish the `payload` only when it differs from the last one in the history of the `topic`.
+    ///
+    /// With `max_age` the payload is also published when the last entry is older, which keeps retained topics fresh after broker restarts.
+    /// Returns whether it was published.
+    ///
+    /// # Errors
+    /// See [`publish`](crate::MqttSmarthome::publish).
+    pub async fn publish_if_changed<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        max_age: Option<Duration>,
+    ) -> Result<bool, PublishError>
+    where
+        P: IntoPayload,
+    {
+        let payload = payload.into_payload();
+        let is_unchanged = self.history.get(topic).is_some_and(|last| {
+            last.payload_bytes() == payload
+                && max_age.is_none_or(|max_age| last.age_at(self.now()) <= max_age)
+        });
+        if is_unchanged {
+            return Ok(false);
+        }
+        self.publish(topic, payload, retain).await?;
+        Ok(true)
+    }
+
+    /// Clear the retained message of the `topic` by publishing an empty retained payload.
+    ///
+    /// The topic is removed from the history.
+    ///
+    /// # Errors
+    /// See [`publish`](crate::MqttSmarthome::publish).
+    pub async fn clear_retained(&self, topic: &str) -> Result<(), PublishError> {
+        let publish = async {
+            PublishError::check_topic(topic)?;
+            if self.announce_dry_run(topic, &[], true) {
+                return Ok(false);
+            }
+            self.throttle().await;
+            self.client
+                .publish(topic, QoS::AtLeastOnce, true, Vec::new())
+                .await?;
+            Ok(true)
+        };
+        match publish.await {
+            Ok(sent) => {
+                if sent {
+                    Metrics::increase(&self.metrics.published);
+                }
+            }
+            Err(error) => {
+                Metrics::increase(&self.metrics.publish_errors);
+                return Err(error);
+            }
+        }
+        self.history.remove(topic);
+        Ok(())
+    }
+
+    /// Clear the retained messages of all topics in the history matching the `filter`.
+    ///
+    /// Only topics known from the history are cleared, so subscribe to the `filter` first and wait for the retained messages to arrive.
+    /// Returns the cleared topics.
+    ///
+    /// # Errors
+    /// Stops at the first topic failing to be cleared, see [`publish`](crate::MqttSmarthome::publish).
+    pub async fn clear_retained_matching(&self, filter: &str) -> Result<Vec<String>, PublishError> {
+        let topics = self.topics_matching(filter).await;
+        for topic in &topics {
+            self.clear_retained(topic).await?;
+        }
+        Ok(topics)
+    }
+}
+
+async fn handle_eventloop(smarthome: &MqttSmarthome, mut eventloop: EventLoop) {
+    let mut failed_attempts: u32 = 0;
+    loop {
+        let polled = eventloop.poll().await;
+        if let Ok(event) = &polled {
+            smarthome.forward_raw_event(event);
+        }
+        match polled {
+            Ok(protocol::Event::Incoming(protocol::Packet::ConnAck(packet))) => {
+                logging::status!(client_id = smarthome.client_id.as_str(); "MQTT connected {packet:?}");
+                smarthome.connected.store(true, Ordering::Relaxed);
+                smarthome.link.send_replace(Link::Connected);
+                Metrics::increase(&smarthome.metrics.connections);
+                failed_attempts = 0;
+                // Messages of a resumed session follow the ConnAck and are handled by the next polls.
+                // Forward the event before that so receivers see it first.
+                let session_present = packet.session_present;
+                smarthome.forward_connection_event(ConnectionEvent::Connected { session_present });
+                smarthome.spawn_initialization(session_present);
+            }
+            Ok(protocol::Event::Incoming(protocol::Packet::Publish(publish))) => {
+                handle_publish(smarthome, publish).await;
+            }
+            Ok(protocol::Event::Incoming(
+                packet @ (protocol::Packet::PubAck(_)
+                | protocol::Packet::PubRec(_)
+                | protocol::Packet::PubComp(_)),
+            )) => {
+                // The client completes the QoS 1 and 2 flows itself, only MQTT 5 reports failures
+                if let Some(reason) = protocol::publish_failure(&packet) {
+                    logging::status_warning!(client_id = smarthome.client_id.as_str(); "MQTT broker did not accept publish: {reason}");
+                    Metrics::increase(&smarthome.metrics.publish_errors);
+                }
+            }
+            Ok(protocol::Event::Outgoing(rumqttc::Outgoing::Subscribe(pkid))) => {
+                smarthome.subscribe_sent(pkid);
+            }
+            Ok(protocol::Event::Incoming(protocol::Packet::SubAck(suback))) => {
+                smarthome.handle_suback(&suback).await;
             }
-            Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) => {
-                println!("MQTT Disconnect happening...");
+            Ok(protocol::Event::Outgoing(rumqttc::Outgoing::Disconnect)) => {
+                logging::status!(client_id = smarthome.client_id.as_str(); "MQTT Disconnect happening...");
+                if smarthome.connected.swap(false, Ordering::Relaxed) {
+                    smarthome.link.send_replace(Link::Connecting);
+                    smarthome.forward_connection_event(ConnectionEvent::Disconnected);
+                }
                 break;
             }
             Ok(_) => {}
             Err(err) => {
-                println!("MQTT Connection Error: {err}");
+                failed_attempts = failed_attempts.saturating_add(1);
+                if !smarthome.connection_failed(&err, failed_attempts).await {
+                    break;
+                }
                 sleep(Duration::from_secs(1)).await;
             }
+        }
+    }
+}
+
+#[cfg(test)]
+async fn handle_incoming<P>(smarthome: &MqttSmarthome, topic: String, raw: P, retain: bool)
+where
+    P: Into<Bytes>,
+{
+    handle_incoming_publish(smarthome, topic, raw.into(), retain, Vec::new()).await;
+}
+
+async fn handle_publish(smarthome: &MqttSmarthome, publish: protocol::Publish) {
+    // Redeliveries of unacknowledged messages are duplicates but the manual acknowledgement channels need them
+    smarthome.dispatch_manual_ack(&publish);
+    if !smarthome.deliver_publish(&publish) {
+        return;
+    }
+    let topic = protocol::publish_topic(&publish);
+    logging::trace!(
+        client_id = smarthome.client_id.as_str(),
+        topic = topic.as_str(),
+        payload_length = publish.payload.len(),
+        retain = publish.retain;
+        "MQTT received {topic}"
+    );
+    let user_properties = protocol::user_properties(&publish);
+    handle_incoming_publish(
+        smarthome,
+        topic,
+        publish.payload,
+        publish.retain,
+        user_properties,
+    )
+    .await;
+}
+
+async fn handle_incoming_publish(
+    smarthome: &MqttSmarthome,
+    topic: String,
+    raw: Bytes,
+    retain: bool,
+    user_properties: Vec<(String, String)>,
+) {
+    smarthome.forward_to_taps(&topic, &raw, retain, user_properties);
+    let now = smarthome.now();
+    let millis = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    smarthome
+        .last_received
+        .store(u64::try_from(millis).unwrap_or(u64::MAX), Ordering::Relaxed);
+    Metrics::increase(&smarthome.metrics.received);
+    if smarthome.topic_stats_enabled.load(Ordering::Relaxed) {
+        smarthome.topic_stats.write().await.record(&topic);
+    }
+    if let Some(oversized) = smarthome.oversized(raw.len()) {
+        smarthome
+            .handle_oversized(&topic, &raw, retain, now, oversized)
+            .await;
+        return;
+    }
+    let payload = String::from_utf8_lossy(&raw);
+    if smarthome.numeric_tracking.load(Ordering::Relaxed) {
+        smarthome
+            .numeric
+            .write()
+            .await
+            .record(&topic, &payload, now);
+    }
+
+    // Compare with the previous entry while replacing it so no other message can interfere
+    let previous = if smarthome.history.is_enabled() {
+        smarthome.history.insert(
+            topic.clone(),
+            HistoryEntry::from_bytes_at(raw.to_vec(), now).with_retained(retain),
+        )
+    } else {
+        None
+    };
+    let previous = previous.as_ref().map(HistoryEntry::payload);
+    dispatch_to_watchers(
+        smarthome,
+        &topic,
+        &raw,
+        previous.as_deref(),
+        retain,
+        EntrySource::Incoming,
+    )
+    .await;
+}
+
+/// Deliver the message to every matching watcher. `previous` is the payload of the topic in the history before this message.
+async fn dispatch_to_watchers(
+    smarthome: &MqttSmarthome,
+    topic: &str,
+    raw: &[u8],
+    previous: Option<&str>,
+    retain: bool,
+    source: EntrySource,
+) {
+    let payload = String::from_utf8_lossy(raw);
+    let derived = Derived::new(previous);
+    // Only clone the senders while the watchers are locked, so handlers can register new channels while this message is sent
+    let senders = smarthome
+        .watchers
+        .read()
+        .await
+        .matching(topic)
+        .filter_map(|watcher| watcher.matching_sender(topic, &payload, retain, &derived))
+        .collect::<Vec<_>>();
+    send_to_watchers(smarthome, topic, &payload, raw, source, derived, senders).await;
+}
+
+async fn send_to_watchers(
+    smarthome: &MqttSmarthome,
+    topic: &str,
+    payload: &str,
+    raw: &[u8],
+    source: EntrySource,
+    derived: Derived<'_>,
+    senders: Vec<MatchedSender>,
+) {
+    let mut any_closed = false;
+    for sender in senders {
+        match sender.try_send(topic, payload, raw, source, &derived) {
+            Ok(()) => {}
+            Err(TrySendError::Closed(())) => any_closed = true,
+            Err(TrySendError::Full(())) => {
+                Metrics::increase(&smarthome.metrics.dropped);
+                logging::warning!(topic = topic; "MQTT watcher receiver buffer is full. Topic: {topic}");
+            }
+        }
+    }
+    if any_closed {
+        smarthome
+            .watchers
+            .write()
+            .await
+            .retain(|watcher| !watcher.is_closed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::error::TryRecvError;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_message_removes_watcher_on_timeout() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let _other = smarthome.watch("#", true).await;
+        let message = smarthome
+            .wait_for_message("foo", Duration::from_secs(5))
+            .await;
+        assert_eq!(message, None);
+        assert_eq!(smarthome.watchers.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn handler_registers_channel_while_dispatching() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut first = smarthome.watch("a", true).await;
+        let handler = task::spawn({
+            let smarthome = smarthome.clone();
+            async move {
+                first.recv().await.unwrap();
+                smarthome.watch("b", true).await
+            }
+        });
+        // Keeps dispatching to the first channel until it is full while the handler registers
+        let mut second = timeout(Duration::from_secs(5), async {
+            loop {
+                handle_incoming(&smarthome, "a".to_owned(), "1", false).await;
+                if handler.is_finished() {
+                    break handler.await.unwrap();
+                }
+                task::yield_now().await;
+            }
+        })
+        .await
+        .expect("registering from a handler should not block");
+        handle_incoming(&smarthome, "b".to_owned(), "2", false).await;
+        assert_eq!(second.try_recv(), Ok(("b".to_owned(), "2".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn wait_for_message_ignores_retained() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let waiting = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move {
+                smarthome
+                    .wait_for_message("foo", Duration::from_secs(5))
+                    .await
+            }
+        });
+        while smarthome.watchers.read().await.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), true).await;
+        handle_incoming(&smarthome, "foo".to_owned(), "2".to_owned(), false).await;
+        let message = waiting.await.unwrap();
+        assert_eq!(message, Some(("foo".to_owned(), "2".to_owned())));
+        assert!(smarthome.watchers.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn last_matching_is_sorted() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        for topic in [
+            "b/status/temp",
+            "a/status/temp",
+            "a/status/hum",
+            "c/set/temp",
+        ] {
+            handle_incoming(&smarthome, topic.to_owned(), "42".to_owned(), true).await;
+        }
+        let topics = smarthome
+            .last_matching("+/status/temp")
+            .await
+            .into_iter()
+            .map(|(topic, _)| topic)
+            .collect::<Vec<_>>();
+        assert_eq!(topics, ["a/status/temp", "b/status/temp"]);
+    }
+
+    #[tokio::test]
+    async fn last_matching_invalid_filter_is_empty() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "foo".to_owned(), "42".to_owned(), true).await;
+        assert!(smarthome.last_matching("#/foo").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn last_matching_shared_filter() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "foo".to_owned(), "42".to_owned(), true).await;
+        assert_eq!(smarthome.last_matching("$share/group/+").await.len(), 1);
+        assert!(smarthome
+            .since_last_received_matching("$share/group/foo")
+            .await
+            .is_some());
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_sorted_and_match() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.subscribe("b/#").await;
+        smarthome.subscribe("a/+/temp").await;
+        smarthome.subscribe("b/#").await;
+        assert_eq!(smarthome.subscriptions().await, ["a/+/temp", "b/#"]);
+        assert!(smarthome.is_subscribed("a/status/temp").await);
+        assert!(smarthome.is_subscribed("b/foo/bar").await);
+        assert!(!smarthome.is_subscribed("a/status/hum").await);
+    }
+
+    #[tokio::test]
+    async fn arc_channels_share_the_message() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut first = smarthome.subscribe_channel_arc("foo/#", false).await;
+        let mut second = smarthome.subscribe_channel_arc("#", false).await;
+        handle_incoming(&smarthome, "foo/bar".to_owned(), "1", false).await;
+        let (first_topic, first_payload) = first.try_recv().unwrap();
+        let (second_topic, second_payload) = second.try_recv().unwrap();
+        assert_eq!(&*first_topic, "foo/bar");
+        assert_eq!(&*first_payload, "1");
+        assert!(Arc::ptr_eq(&first_topic, &second_topic));
+        assert!(Arc::ptr_eq(&first_payload, &second_payload));
+    }
+
+    #[tokio::test]
+    async fn replay_delivers_known_before_live() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let _early = smarthome.subscribe_and_watch("foo/+", true).await;
+        handle_incoming(&smarthome, "foo/a".to_owned(), "1", true).await;
+        handle_incoming(&smarthome, "foo/b".to_owned(), "2", false).await;
+        handle_incoming(&smarthome, "other".to_owned(), "3", true).await;
+
+        let mut late = smarthome.subscribe_and_watch("foo/+", true).await;
+        let mut replayed = smarthome.subscribe_channel_replay("foo/+", false).await;
+        handle_incoming(&smarthome, "foo/a".to_owned(), "4", false).await;
+
+        assert_eq!(late.try_recv(), Ok(("foo/a".to_owned(), "4".to_owned())));
+        assert_eq!(late.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(
+            replayed.try_recv(),
+            Ok(("foo/a".to_owned(), "1".to_owned()))
+        );
+        assert_eq!(
+            replayed.try_recv(),
+            Ok(("foo/b".to_owned(), "2".to_owned()))
+        );
+        assert_eq!(
+            replayed.try_recv(),
+            Ok(("foo/a".to_owned(), "4".to_owned()))
+        );
+        assert_eq!(replayed.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn replay_is_not_limited_by_the_channel_size() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        for index in 0..30 {
+            handle_incoming(&smarthome, format!("foo/{index}"), "1", true).await;
+        }
+        let mut replayed = smarthome.subscribe_channel_replay("foo/#", true).await;
+        for _ in 0..30 {
+            assert!(replayed.try_recv().is_ok());
+        }
+        assert_eq!(replayed.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn shared_subscription_is_separate() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.set_birth_message(None);
+        smarthome.subscribe("$share/workers/base/set/#").await;
+        smarthome.subscribe("base/set/#").await;
+        smarthome.subscribe("$share/workers/base/set/#").await;
+        assert_eq!(
+            smarthome.subscriptions().await,
+            ["$share/workers/base/set/#", "base/set/#"]
+        );
+        assert!(smarthome.is_subscribed("base/set/lamp").await);
+
+        smarthome.initialize_connection(false, 0).await;
+        eventloop.clean();
+        let filters = eventloop
+            .pending
+            .iter()
+            .filter_map(|request| match request {
+                protocol::Request::Subscribe(subscribe) => Some(
+                    subscribe
+                        .filters
+                        .iter()
+                        .map(|filter| filter.path.as_str())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            filters,
+            [
+                vec!["$share/workers/base/set/#"],
+                vec!["base/set/#"],
+                vec!["$share/workers/base/set/#", "base/set/#"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn topics_are_sorted() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        for topic in ["b/status/temp", "a/status/temp", "c/set/temp"] {
+            handle_incoming(&smarthome, topic.to_owned(), "42".to_owned(), true).await;
+        }
+        smarthome.publish("a/set/temp", 42, false).await.unwrap();
+        assert_eq!(
+            smarthome.topics().await,
+            ["a/set/temp", "a/status/temp", "b/status/temp", "c/set/temp"]
+        );
+        assert_eq!(
+            smarthome.topics_matching("+/set/#").await,
+            ["a/set/temp", "c/set/temp"]
+        );
+    }
+
+    #[tokio::test]
+    async fn history_disabled_still_feeds_channels() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        smarthome.set_history_enabled(false);
+        assert!(smarthome.last("foo").await.is_none());
+
+        let mut receiver = smarthome.subscribe_channel_replay("foo", false).await;
+        handle_incoming(&smarthome, "foo".to_owned(), "2".to_owned(), false).await;
+        smarthome.publish("bar", "3", false).await.unwrap();
+        assert_eq!(
+            receiver.recv().await,
+            Some(("foo".to_owned(), "2".to_owned()))
+        );
+        assert!(smarthome.last("foo").await.is_none());
+        assert!(smarthome.last("bar").await.is_none());
+        assert!(smarthome.history_snapshot().await.is_empty());
+    }
+
+    #[test]
+    fn detached_is_created_without_runtime() {
+        let (smarthome, eventloop) = MqttSmarthome::new_detached(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            MqttOptions::new("test", "localhost", 1),
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        smarthome.set_max_reconnect_attempts(Some(1));
+        runtime.block_on(async {
+            let mut events = smarthome.connection_events();
+            task::spawn(eventloop).await.unwrap();
+            assert_eq!(events.recv().await, Some(ConnectionEvent::GaveUp));
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn history_stress_concurrent_readers_and_writer() {
+        const TOPICS: usize = 64;
+        const ROUNDS: usize = 200;
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_history_size(3).await;
+
+        let writer = {
+            let smarthome = smarthome.clone();
+            task::spawn(async move {
+                for round in 1..=ROUNDS {
+                    for topic in 0..TOPICS {
+                        let topic = format!("stress/{topic}");
+                        handle_incoming(&smarthome, topic, round.to_string(), false).await;
+                    }
+                }
+            })
+        };
+        let readers = (0..4)
+            .map(|_| {
+                let smarthome = smarthome.clone();
+                task::spawn(async move {
+                    let mut seen = vec![0; TOPICS];
+                    while seen.iter().any(|round| *round < ROUNDS) {
+                        for (topic, seen) in seen.iter_mut().enumerate() {
+                            let topic = format!("stress/{topic}");
+                            if let Some(round) = smarthome.last_parsed::<usize>(&topic).await {
+                                assert!(round >= *seen, "{topic} went back to {round}");
+                                *seen = round;
+                            }
+                            let history = smarthome.history_of(&topic).await;
+                            assert!(history.len() <= 3);
+                        }
+                        assert!(smarthome.last_matching("stress/+").await.len() <= TOPICS);
+                        task::yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        writer.await.unwrap();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        assert_eq!(smarthome.topics().await.len(), TOPICS);
+        assert_eq!(
+            smarthome.last_parsed::<usize>("stress/0").await,
+            Some(ROUNDS)
+        );
+        assert_eq!(
+            smarthome.metrics().messages_received,
+            (TOPICS * ROUNDS) as u64
+        );
+        assert!(smarthome.since_last_received().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn history_snapshot_contains_last_entries() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), true).await;
+        handle_incoming(&smarthome, "foo".to_owned(), "2".to_owned(), false).await;
+        smarthome.publish("bar", 3, false).await.unwrap();
+        let snapshot = smarthome.history_snapshot().await;
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(snapshot["foo"].payload(), "2");
+        assert_eq!(snapshot["bar"].payload(), "3");
+    }
+
+    #[tokio::test]
+    async fn since_last_received_on_unknown_is_none() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        assert_eq!(smarthome.since_last_received_on("foo").await, None);
+        assert_eq!(smarthome.since_last_received_matching("#").await, None);
+    }
+
+    #[tokio::test]
+    async fn since_last_received_matching_takes_most_recent() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let old = std::time::SystemTime::now() - Duration::from_mins(10);
+        smarthome
+            .history
+            .insert("a/temp".to_owned(), HistoryEntry::new_at("1", old));
+        handle_incoming(&smarthome, "b/temp".to_owned(), "2".to_owned(), false).await;
+        handle_incoming(&smarthome, "c/hum".to_owned(), "3".to_owned(), false).await;
+
+        let a = smarthome.since_last_received_on("a/temp").await.unwrap();
+        assert!(a >= Duration::from_mins(10));
+        let matching = smarthome
+            .since_last_received_matching("+/temp")
+            .await
+            .unwrap();
+        assert!(matching < Duration::from_secs(5));
+        assert_eq!(smarthome.since_last_received_matching("+/none").await, None);
+    }
+
+    #[tokio::test]
+    async fn clock_drives_entry_times_and_ages() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_hours(500_000);
+        let clock = Arc::new(clock::MockClock::new(start));
+        smarthome.set_clock(clock.clone());
+        handle_incoming(&smarthome, "a/temp".to_owned(), "1".to_owned(), false).await;
+        clock.advance(Duration::from_secs(30));
+        smarthome.publish("b/temp", "2", false).await.unwrap();
+        clock.advance(Duration::from_mins(1));
+
+        assert_eq!(smarthome.last("a/temp").await.unwrap().time(), start);
+        assert_eq!(
+            smarthome.since_last_received().await,
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            smarthome.since_last_received_on("a/temp").await,
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            smarthome.since_last_received_matching("+/temp").await,
+            Some(Duration::from_mins(1))
+        );
+    }
+
+    #[tokio::test]
+    async fn clock_drives_history_expiry() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let clock = Arc::new(clock::MockClock::new(SystemTime::now()));
+        smarthome.set_clock(clock.clone());
+        smarthome
+            .set_history_limits(None, Some(Duration::from_mins(1)))
+            .await;
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        clock.advance(Duration::from_mins(1));
+        assert!(smarthome.last("foo").await.is_some());
+        clock.advance(Duration::from_secs(1));
+        assert!(smarthome.last("foo").await.is_none());
+        assert!(smarthome.topics().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn edges_use_retained_as_baseline() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_edges("door", false).await;
+        handle_incoming(&smarthome, "door".to_owned(), "false".to_owned(), true).await;
+        handle_incoming(&smarthome, "door".to_owned(), "true".to_owned(), false).await;
+        handle_incoming(&smarthome, "door".to_owned(), "true".to_owned(), false).await;
+        handle_incoming(&smarthome, "door".to_owned(), "false".to_owned(), false).await;
+        assert_eq!(receiver.try_recv(), Ok(("door".to_owned(), Edge::Rising)));
+        assert_eq!(receiver.try_recv(), Ok(("door".to_owned(), Edge::Falling)));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn edges_cold_start_without_baseline() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_edges("door", false).await;
+        handle_incoming(&smarthome, "door".to_owned(), "true".to_owned(), false).await;
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn deltas_skip_unparsable_previous() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_deltas("power").await;
+        handle_incoming(&smarthome, "power".to_owned(), "unknown".to_owned(), true).await;
+        handle_incoming(&smarthome, "power".to_owned(), "40 W".to_owned(), false).await;
+        handle_incoming(&smarthome, "power".to_owned(), "540 W".to_owned(), false).await;
+        assert_eq!(receiver.try_recv(), Ok(("power".to_owned(), 540.0, 500.0)));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn last_two_newest_first() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_history_size(3).await;
+        for payload in ["1", "2", "3"] {
+            handle_incoming(&smarthome, "foo".to_owned(), payload.to_owned(), false).await;
+        }
+        let payloads = smarthome
+            .last_two("foo")
+            .await
+            .iter()
+            .map(|entry| entry.payload().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(payloads, ["3", "2"]);
+    }
+
+    #[tokio::test]
+    async fn publish_if_changed_skips_same() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        assert!(smarthome
+            .publish_if_changed("foo", 1, true, None)
+            .await
+            .unwrap());
+        assert!(!smarthome
+            .publish_if_changed("foo", 1, true, None)
+            .await
+            .unwrap());
+        assert!(smarthome
+            .publish_if_changed("foo", 2, true, None)
+            .await
+            .unwrap());
+        assert_eq!(smarthome.metrics().messages_published, 2);
+    }
+
+    #[tokio::test]
+    async fn publish_if_changed_republishes_old() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let old = SystemTime::now() - Duration::from_mins(10);
+        smarthome
+            .history
+            .insert("foo".to_owned(), HistoryEntry::new_at("1", old));
+        let max_age = Some(Duration::from_mins(5));
+        assert!(smarthome
+            .publish_if_changed("foo", 1, true, max_age)
+            .await
+            .unwrap());
+        assert!(!smarthome
+            .publish_if_changed("foo", 1, true, max_age)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn publish_many_updates_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .publish_many(vec![
+                ("foo/config".to_owned(), "{}".to_owned(), true),
+                ("foo/state".to_owned(), "1".to_owned(), true),
+                ("foo/state".to_owned(), "2".to_owned(), true),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(smarthome.metrics().messages_published, 3);
+        assert_eq!(smarthome.last("foo/config").await.unwrap().payload(), "{}");
+        assert_eq!(smarthome.last("foo/state").await.unwrap().payload(), "2");
+    }
+
+    #[tokio::test]
+    async fn publish_many_reports_failed_index() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let result = smarthome
+            .publish_many(vec![
+                ("foo".to_owned(), "1".to_owned(), false),
+                ("foo/#".to_owned(), "2".to_owned(), false),
+                ("bar".to_owned(), "3".to_owned(), false),
+            ])
+            .await;
+        assert_eq!(result.unwrap_err().index, 1);
+        assert_eq!(smarthome.topics().await, ["foo"]);
+    }
+
+    #[tokio::test]
+    async fn last_bool_uses_vocabulary() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "cover".to_owned(), "zu".to_owned(), false).await;
+        assert_eq!(smarthome.last_as_bool_strict("cover").await, None);
+        smarthome
+            .set_bool_vocabulary(BoolVocabulary::new(["auf"], ["zu"]))
+            .await;
+        assert_eq!(smarthome.last_as_bool_strict("cover").await, Some(false));
+        assert!(!smarthome.last_is_true("cover").await);
+    }
+
+    #[tokio::test]
+    async fn history_knows_retain_and_source() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "a".to_owned(), "1".to_owned(), true).await;
+        handle_incoming(&smarthome, "b".to_owned(), "1".to_owned(), false).await;
+        smarthome.publish("c", 1, true).await.unwrap();
+
+        let a = smarthome.last("a").await.unwrap();
+        assert!(a.retained());
+        assert_eq!(a.source(), EntrySource::Incoming);
+        let b = smarthome.last("b").await.unwrap();
+        assert!(!b.retained());
+        assert_eq!(b.source(), EntrySource::Incoming);
+        let c = smarthome.last("c").await.unwrap();
+        assert!(c.retained());
+        assert_eq!(c.source(), EntrySource::Published);
+    }
+
+    #[tokio::test]
+    async fn own_publishes_can_be_excluded_from_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_record_own_publishes(false);
+        handle_incoming(&smarthome, "thermostat/set".to_owned(), "20", false).await;
+        smarthome
+            .publish("thermostat/set", 22, false)
+            .await
+            .unwrap();
+        smarthome
+            .publish_many(vec![("other".to_owned(), "1".to_owned(), false)])
+            .await
+            .unwrap();
+
+        let last = smarthome.last("thermostat/set").await.unwrap();
+        assert_eq!(last.payload(), "20");
+        assert_eq!(last.source(), EntrySource::Incoming);
+        assert!(smarthome.last("other").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn last_parsed_trims() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "port".to_owned(), " 1883 ", false).await;
+        assert_eq!(smarthome.last_parsed::<u16>("port").await, Some(1883));
+        assert_eq!(
+            smarthome.last_parsed::<std::net::Ipv4Addr>("port").await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_binary_keeps_bytes_in_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .publish("foo", vec![0xff, 0xfe], false)
+            .await
+            .unwrap();
+        let last = smarthome.last("foo").await.unwrap();
+        assert_eq!(last.payload_bytes(), [0xff, 0xfe]);
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_is_delivered_lossy() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut strings = smarthome.subscribe_and_watch("sensor", false).await;
+        let mut bytes = smarthome.subscribe_bytes_channel("sensor", false).await;
+        let raw = b"21.5 \xb0C".as_slice();
+        handle_incoming(&smarthome, "sensor".to_owned(), raw, false).await;
+
+        assert_eq!(
+            strings.try_recv().unwrap(),
+            ("sensor".to_owned(), "21.5 \u{fffd}C".to_owned())
+        );
+        assert_eq!(
+            bytes.try_recv().unwrap(),
+            ("sensor".to_owned(), raw.to_vec())
+        );
+        assert!(smarthome.since_last_received().await.is_some());
+        assert_eq!(smarthome.last("sensor").await.unwrap().payload_bytes(), raw);
+    }
+
+    #[tokio::test]
+    async fn publish_invalid_topic_keeps_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let result = smarthome.publish("light/+/set", "on", false).await;
+        assert!(
+            matches!(result, Err(PublishError::InvalidTopic { topic }) if topic == "light/+/set")
+        );
+        assert!(smarthome.topics().await.is_empty());
+        assert_eq!(smarthome.metrics().publish_errors, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publish_with_retry_invalid_topic_fails_immediately() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let start = tokio::time::Instant::now();
+        let result = smarthome
+            .publish_with_retry("foo/+", 1, false, 5, Duration::from_secs(1))
+            .await;
+        assert!(matches!(result, Err(PublishError::InvalidTopic { .. })));
+        assert_eq!(start.elapsed(), Duration::ZERO);
+        assert_eq!(smarthome.metrics().publish_errors, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publish_with_retry_gives_up_when_queue_stays_full() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        for _ in 0..100 {
+            smarthome.publish("fill", 1, false).await.unwrap();
+        }
+        let start = tokio::time::Instant::now();
+        let result = smarthome
+            .publish_with_retry("foo", 1, false, 3, Duration::from_secs(1))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+        assert_eq!(smarthome.metrics().publish_errors, 3);
+        assert!(smarthome.last("foo").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn publish_with_retry_updates_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .publish_with_retry("foo", 1, false, 3, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "1");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publish_is_throttled() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_publish_rate_limit(Some(2.0));
+        let start = tokio::time::Instant::now();
+        for index in 0..5 {
+            smarthome.publish("foo", index, false).await.unwrap();
+        }
+        assert_eq!(start.elapsed(), Duration::from_millis(1500));
+        assert_eq!(smarthome.metrics().messages_published, 5);
+        assert!(smarthome.metrics().time_throttled >= Duration::from_millis(1500));
+    }
+
+    #[tokio::test]
+    async fn offline_buffer_holds_back_while_disconnected() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_offline_buffer(Some(2));
+        for index in 0..3 {
+            smarthome.publish("foo", index, false).await.unwrap();
+        }
+        assert_eq!(smarthome.offline_buffered(), 2);
+        assert_eq!(smarthome.metrics().messages_published, 0);
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "2");
+
+        smarthome.connected.store(true, Ordering::Relaxed);
+        smarthome.flush_offline_buffer().await;
+        assert_eq!(smarthome.offline_buffered(), 0);
+        assert_eq!(smarthome.metrics().messages_published, 2);
+    }
+
+    #[tokio::test]
+    async fn offline_buffer_keeps_messages_failing_to_flush() {
+        let (smarthome, eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            MqttOptions::new("test", "localhost", 1883),
+        );
+        smarthome.set_offline_buffer(Some(10));
+        for index in 0..3 {
+            smarthome.publish("foo", index, false).await.unwrap();
+        }
+        drop(eventloop);
+        smarthome.flush_offline_buffer().await;
+        assert_eq!(smarthome.offline_buffered(), 3);
+        assert_eq!(smarthome.metrics().publish_errors, 1);
+    }
+
+    #[tokio::test]
+    async fn offline_buffer_disabled_publishes_directly() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.publish("foo", 1, false).await.unwrap();
+        assert_eq!(smarthome.offline_buffered(), 0);
+        assert_eq!(smarthome.metrics().messages_published, 1);
+    }
+
+    #[tokio::test]
+    async fn clear_retained_removes_history() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.publish("foo", 1, true).await.unwrap();
+        smarthome.clear_retained("foo").await.unwrap();
+        assert!(smarthome.last("foo").await.is_none());
+        assert_eq!(smarthome.metrics().messages_published, 2);
+    }
+
+    #[tokio::test]
+    async fn clear_retained_matching_clears_subtree() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        for topic in ["device/a", "device/b/c", "other"] {
+            handle_incoming(&smarthome, topic.to_owned(), "1".to_owned(), true).await;
+        }
+        let cleared = smarthome.clear_retained_matching("device/#").await.unwrap();
+        assert_eq!(cleared, ["device/a", "device/b/c"]);
+        assert_eq!(smarthome.topics().await, ["other"]);
+    }
+
+    #[tokio::test]
+    async fn changes_contain_previous_payload() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_changes("foo", true).await;
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), true).await;
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        handle_incoming(&smarthome, "foo".to_owned(), "2".to_owned(), false).await;
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(("foo".to_owned(), "1".to_owned(), None))
+        );
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(("foo".to_owned(), "2".to_owned(), Some("1".to_owned())))
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[rstest::rstest]
+    #[case::new_session(false, 1)]
+    #[case::resumed_session(true, 0)]
+    #[tokio::test]
+    async fn resubscribe_unless_session_present(
+        #[case] session_present: bool,
+        #[case] expected: usize,
+    ) {
+        let mut mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        protocol::set_persistent_session(&mut mqttoptions, true);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.set_birth_message(None);
+        smarthome.subscribe("light/set/#").await;
+        eventloop.clean();
+        eventloop.pending.clear();
+
+        smarthome.initialize_connection(session_present, 0).await;
+        eventloop.clean();
+        let subscribes = eventloop
+            .pending
+            .iter()
+            .filter(|request| matches!(request, protocol::Request::Subscribe(_)))
+            .count();
+        assert_eq!(subscribes, expected);
+    }
+
+    #[tokio::test]
+    async fn publish_qos_uses_qos() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome
+            .publish_qos("counter/set/increment", "1", false, QoS::ExactlyOnce)
+            .await
+            .unwrap();
+        eventloop.clean();
+        assert_eq!(
+            eventloop.pending.pop_front(),
+            Some(protocol::publish_request(
+                "counter/set/increment",
+                QoS::ExactlyOnce,
+                "1",
+                false
+            ))
+        );
+    }
+
+    #[cfg(feature = "v5")]
+    #[tokio::test]
+    async fn publish_with_expiry_sets_interval() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome
+            .publish_with_expiry("light/set/toggle", true, false, Duration::from_millis(9500))
+            .await
+            .unwrap();
+        assert_eq!(
+            smarthome.last("light/set/toggle").await.unwrap().payload(),
+            "true"
+        );
+        eventloop.clean();
+        let Some(protocol::Request::Publish(publish)) = eventloop.pending.pop_front() else {
+            panic!("expected a publish");
         };
+        assert_eq!(
+            publish.properties.unwrap().message_expiry_interval,
+            Some(10)
+        );
     }
 }
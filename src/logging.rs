@@ -0,0 +1,73 @@
+//! Shim so diagnostics go through the `log` crate with the `log` feature.
+//!
+//! Without the feature warnings stay silent while connection status keeps being printed to stdout.
+
+/// Log a warning with structured fields like `warning!(topic = topic; "message")`.
+macro_rules! warning {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($key = $value),+ ; $($arg)+);
+        #[cfg(not(feature = "log"))]
+        {
+            let _ = ($(&$value),+);
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// Log a connection status at info level. Printed to stdout without the `log` feature.
+macro_rules! status {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::info!($($key = $value),+ ; $($arg)+);
+        #[cfg(not(feature = "log"))]
+        {
+            let _ = ($(&$value),+);
+            println!($($arg)+);
+        }
+    }};
+}
+
+/// Log a connection problem at warn level. Printed to stdout without the `log` feature.
+macro_rules! status_warning {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($key = $value),+ ; $($arg)+);
+        #[cfg(not(feature = "log"))]
+        {
+            let _ = ($(&$value),+);
+            println!($($arg)+);
+        }
+    }};
+}
+
+/// Log at debug level. Silent without the `log` feature.
+#[cfg(feature = "json")]
+macro_rules! debug {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::debug!($($key = $value),+ ; $($arg)+);
+        #[cfg(not(feature = "log"))]
+        {
+            let _ = ($(&$value),+);
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// Log at trace level. Silent without the `log` feature.
+macro_rules! trace {
+    ($($key:ident = $value:expr),+ ; $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::trace!($($key = $value),+ ; $($arg)+);
+        #[cfg(not(feature = "log"))]
+        {
+            let _ = ($(&$value),+);
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+#[cfg(feature = "json")]
+pub(crate) use debug;
+pub(crate) use {status, status_warning, trace, warning};
@@ -0,0 +1,130 @@
+use std::sync::atomic::Ordering;
+
+use crate::{dispatch_to_watchers, EntrySource, HistoryEntry, MqttSmarthome};
+
+impl MqttSmarthome {
+    /// Also deliver messages published by this client to its own watchers. Disabled by default.
+    ///
+    /// Watchers get them like messages received from the broker.
+    /// Like the broker forwards publishes to existing subscriptions they are not marked as retained, even when published retained.
+    /// Use [`subscribe_channel_sourced`](Self::subscribe_channel_sourced) to distinguish them.
+    /// When the broker sends them back as well, they are delivered twice.
+    ///
+    /// Beware of loops: a handler publishing to a topic it watches itself receives its own message again, which might publish again.
+    pub fn set_loopback_local_publishes(&self, enabled: bool) {
+        self.loopback_local_publishes
+            .store(enabled, Ordering::Relaxed);
+    }
+
+    /// Deliver the published message to the watchers when enabled.
+    pub(crate) async fn loop_back(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        previous: Option<&HistoryEntry>,
+    ) {
+        if !self.loopback_local_publishes.load(Ordering::Relaxed) {
+            return;
+        }
+        let previous = previous.map(HistoryEntry::payload);
+        dispatch_to_watchers(
+            self,
+            topic,
+            payload,
+            previous.as_deref(),
+            false,
+            EntrySource::Published,
+        )
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::error::TryRecvError;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn disabled_by_default() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_and_watch("base/status/#", true).await;
+        smarthome
+            .publish("base/status/lamp", "1", false)
+            .await
+            .unwrap();
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn published_is_delivered_to_watchers() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_loopback_local_publishes(true);
+        let mut plain = smarthome.subscribe_and_watch("base/status/#", false).await;
+        let mut sourced = smarthome
+            .subscribe_channel_sourced("base/status/#", true)
+            .await;
+        let mut changes = smarthome.subscribe_changes("base/status/#", false).await;
+        smarthome
+            .publish("base/status/lamp", "1", false)
+            .await
+            .unwrap();
+        smarthome
+            .publish("base/status/lamp", "1", false)
+            .await
+            .unwrap();
+        smarthome
+            .publish("base/status/lamp", "0", true)
+            .await
+            .unwrap();
+        smarthome.publish("other/lamp", "1", false).await.unwrap();
+
+        assert_eq!(
+            plain.try_recv(),
+            Ok(("base/status/lamp".to_owned(), "1".to_owned()))
+        );
+        assert_eq!(
+            plain.try_recv(),
+            Ok(("base/status/lamp".to_owned(), "1".to_owned()))
+        );
+        assert_eq!(
+            plain.try_recv(),
+            Ok(("base/status/lamp".to_owned(), "0".to_owned()))
+        );
+        assert_eq!(plain.try_recv(), Err(TryRecvError::Empty));
+
+        assert_eq!(
+            sourced.try_recv(),
+            Ok((
+                "base/status/lamp".to_owned(),
+                "1".to_owned(),
+                EntrySource::Published
+            ))
+        );
+        assert_eq!(changes.try_recv().unwrap().1, "1");
+        assert_eq!(changes.try_recv().unwrap().1, "0");
+        assert_eq!(changes.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn retained_publish_is_not_delivered_as_retained() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_loopback_local_publishes(true);
+        let mut receiver = smarthome.subscribe_and_watch("foo", false).await;
+        smarthome.publish("foo", "1", true).await.unwrap();
+        assert_eq!(receiver.try_recv(), Ok(("foo".to_owned(), "1".to_owned())));
+        assert!(smarthome.last("foo").await.unwrap().retained());
+    }
+
+    #[tokio::test]
+    async fn incoming_is_marked_as_incoming() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_loopback_local_publishes(true);
+        let mut sourced = smarthome.subscribe_channel_sourced("foo", true).await;
+        crate::handle_incoming(&smarthome, "foo".to_owned(), "1", false).await;
+        assert_eq!(
+            sourced.try_recv(),
+            Ok(("foo".to_owned(), "1".to_owned(), EntrySource::Incoming))
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use core::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+
+use crate::metrics::Metrics;
+use crate::protocol::{self, AsyncClient, ClientError, Publish};
+use crate::{logging, topic_filter, MqttSmarthome, ReceivedMessage};
+
+/// Acknowledges a received message to the broker once, see [`ReceivedMessage::ack`].
+#[derive(Clone)]
+pub struct AckHandle {
+    client: AsyncClient,
+    publish: Arc<Publish>,
+    acked: Arc<AtomicBool>,
+}
+
+impl AckHandle {
+    async fn ack(&self) -> Result<(), ClientError> {
+        if self.acked.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.client.ack(&self.publish).await
+    }
+}
+
+impl fmt::Debug for AckHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckHandle")
+            .field("pkid", &self.publish.pkid)
+            .field("acked", &self.acked.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for AckHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.acked, &other.acked)
+    }
+}
+
+impl Eq for AckHandle {}
+
+pub struct ManualAckWatcher {
+    filter: String,
+    allow_retained: bool,
+    sender: Sender<ReceivedMessage>,
+}
+
+impl ReceivedMessage {
+    /// Acknowledge the message to the broker, see [`subscribe_channel_manual_ack`](MqttSmarthome::subscribe_channel_manual_ack).
+    ///
+    /// Only the first call of all receivers of the message sends the acknowledgement.
+    /// Does nothing for messages which are not acknowledged manually.
+    ///
+    /// # Errors
+    /// Returns an error when the MQTT eventloop is gone.
+    pub async fn ack(&self) -> Result<(), ClientError> {
+        match &self.ack_handle {
+            Some(handle) => handle.ack().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl MqttSmarthome {
+    /// Subscribe to the `topic` and acknowledge `QoS` 1 and 2 messages only once [`ReceivedMessage::ack`] is called.
+    ///
+    /// This requires [`MqttOptions::set_manual_acks`](protocol::MqttOptions::set_manual_acks) on creation of the client.
+    /// Without it the messages are acknowledged on arrival like with every other channel.
+    /// Messages not taken by any manual acknowledgement channel are still acknowledged on arrival.
+    ///
+    /// The broker sends unacknowledged messages again on the next connection with a [persistent session](protocol::set_persistent_session).
+    /// These are marked as duplicate and are delivered to this channel while all other channels and the history skip duplicates.
+    /// Messages are not acknowledged and skipped while the channel is full.
+    ///
+    /// # Panics
+    /// Panics when the `topic` is not a valid filter or the MQTT eventloop is gone.
+    pub async fn subscribe_channel_manual_ack(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+    ) -> Receiver<ReceivedMessage> {
+        assert!(topic_filter::is_valid(topic), "topic filter is not valid");
+        self.subscribe(topic).await;
+        let (sender, receiver) = channel(25);
+        self.manual_ack_watchers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(ManualAckWatcher {
+                filter: topic.to_owned(),
+                allow_retained,
+                sender,
+            });
+        receiver
+    }
+
+    /// Hand the `publish` to the manual acknowledgement channels and acknowledge it when none of them took it.
+    pub(crate) fn dispatch_manual_ack(&self, publish: &Publish) {
+        let topic = protocol::publish_topic(publish);
+        let handle = self.manual_acks.then(|| AckHandle {
+            client: self.client.clone(),
+            publish: Arc::new(publish.clone()),
+            acked: Arc::new(AtomicBool::new(false)),
+        });
+        let mut taken = false;
+        let mut skipped = false;
+        self.manual_ack_watchers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|watcher| {
+                if (publish.retain && !watcher.allow_retained)
+                    || !topic_filter::matches(&topic, &watcher.filter)
+                {
+                    return !watcher.sender.is_closed();
+                }
+                let message = ReceivedMessage {
+                    topic: topic.clone(),
+                    payload: publish.payload.to_vec(),
+                    retained: publish.retain,
+                    user_properties: protocol::user_properties(publish),
+                    ack_handle: handle.clone(),
+                };
+                match watcher.sender.try_send(message) {
+                    Ok(()) => {
+                        taken = true;
+                        true
+                    }
+                    Err(TrySendError::Full(_)) => {
+                        Metrics::increase(&self.metrics.dropped);
+                        skipped = true;
+                        true
+                    }
+                    Err(TrySendError::Closed(_)) => false,
+                }
+            });
+        // A full channel leaves the message unacknowledged so the broker delivers it again
+        if self.manual_acks && !taken && !skipped {
+            // Not awaiting as the eventloop itself frees the space in the request channel
+            if let Err(err) = self.client.try_ack(publish) {
+                logging::status_warning!(client_id = self.client_id.as_str(), topic = topic.as_str(); "MQTT failed to acknowledge {topic}: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{EventLoop, MqttOptions, QoS, Request};
+    use crate::LastWillConfig;
+
+    fn new(manual_acks: bool) -> (MqttSmarthome, EventLoop) {
+        let mut mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        mqttoptions.set_manual_acks(manual_acks);
+        MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        )
+    }
+
+    fn incoming(topic: &str, pkid: u16) -> Publish {
+        let Request::Publish(mut publish) =
+            protocol::publish_request(topic, QoS::AtLeastOnce, "1", false)
+        else {
+            unreachable!();
+        };
+        publish.pkid = pkid;
+        publish
+    }
+
+    fn acked(eventloop: &mut EventLoop) -> Vec<u16> {
+        eventloop.clean();
+        eventloop
+            .pending
+            .drain(..)
+            .filter_map(|request| match request {
+                Request::PubAck(puback) => Some(puback.pkid),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn acked_by_receiver() {
+        let (smarthome, mut eventloop) = new(true);
+        let mut receiver = smarthome.subscribe_channel_manual_ack("set/#", false).await;
+        let mut second = smarthome
+            .subscribe_channel_manual_ack("set/lamp", false)
+            .await;
+        smarthome.dispatch_manual_ack(&incoming("set/lamp", 5));
+        assert!(acked(&mut eventloop).is_empty());
+
+        let message = receiver.try_recv().unwrap();
+        assert_eq!(message.topic, "set/lamp");
+        message.ack().await.unwrap();
+        second.try_recv().unwrap().ack().await.unwrap();
+        message.ack().await.unwrap();
+        assert_eq!(acked(&mut eventloop), [5]);
+    }
+
+    #[tokio::test]
+    async fn others_are_acked_on_arrival() {
+        let (smarthome, mut eventloop) = new(true);
+        let _receiver = smarthome.subscribe_channel_manual_ack("set/#", false).await;
+        smarthome.dispatch_manual_ack(&incoming("status/lamp", 6));
+        let mut retained = incoming("set/lamp", 7);
+        retained.retain = true;
+        smarthome.dispatch_manual_ack(&retained);
+        assert_eq!(acked(&mut eventloop), [6, 7]);
+    }
+
+    #[tokio::test]
+    async fn full_channel_is_not_acked() {
+        let (smarthome, mut eventloop) = new(true);
+        let mut receiver = smarthome.subscribe_channel_manual_ack("set/#", false).await;
+        for pkid in 1..=25 {
+            smarthome.dispatch_manual_ack(&incoming("set/lamp", pkid));
+        }
+        smarthome.dispatch_manual_ack(&incoming("set/lamp", 26));
+        assert!(acked(&mut eventloop).is_empty());
+
+        let mut count = 0;
+        while let Ok(message) = receiver.try_recv() {
+            message.ack().await.unwrap();
+            count += 1;
+        }
+        assert_eq!(count, 25);
+        assert_eq!(acked(&mut eventloop), (1..=25).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn without_manual_acks_nothing_is_acked() {
+        let (smarthome, mut eventloop) = new(false);
+        let mut receiver = smarthome.subscribe_channel_manual_ack("set/#", false).await;
+        smarthome.dispatch_manual_ack(&incoming("set/lamp", 5));
+        smarthome.dispatch_manual_ack(&incoming("status/lamp", 6));
+        receiver.try_recv().unwrap().ack().await.unwrap();
+        assert!(acked(&mut eventloop).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use core::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::MqttSmarthome;
+
+/// Counters of the client. They are only meant for statistics and therefore use relaxed atomics.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub received: AtomicU64,
+    pub published: AtomicU64,
+    pub publish_errors: AtomicU64,
+    pub dropped: AtomicU64,
+    pub oversized: AtomicU64,
+    pub connections: AtomicU64,
+    pub throttled_micros: AtomicU64,
+}
+
+impl Metrics {
+    pub fn increase(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Values of the client counters at the time of [`metrics`](MqttSmarthome::metrics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub messages_received: u64,
+    pub messages_published: u64,
+    pub publish_errors: u64,
+    /// Messages not delivered to a watcher as its channel was full
+    pub messages_dropped: u64,
+    /// Received messages above the [maximum payload size](MqttSmarthome::set_max_payload_bytes)
+    pub messages_oversized: u64,
+    pub reconnects: u64,
+    /// Time publishes waited because of the rate limit
+    pub time_throttled: Duration,
+}
+
+impl MqttSmarthome {
+    #[must_use]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let metrics = &self.metrics;
+        MetricsSnapshot {
+            messages_received: metrics.received.load(Ordering::Relaxed),
+            messages_published: metrics.published.load(Ordering::Relaxed),
+            publish_errors: metrics.publish_errors.load(Ordering::Relaxed),
+            messages_dropped: metrics.dropped.load(Ordering::Relaxed),
+            messages_oversized: metrics.oversized.load(Ordering::Relaxed),
+            reconnects: metrics
+                .connections
+                .load(Ordering::Relaxed)
+                .saturating_sub(1),
+            time_throttled: Duration::from_micros(metrics.throttled_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl MqttSmarthome {
+    /// Encode the metrics in the Prometheus text exposition format.
+    ///
+    /// All metrics are prefixed with `mqtt_smarthome_` and labeled with the base topic.
+    #[allow(clippy::unused_async)]
+    pub async fn encode_prometheus(&self) -> String {
+        use core::fmt::Write as _;
+
+        let metrics = self.metrics();
+        let history_topics = self.history.len();
+        let label = format!(
+            "{{base_topic=\"{}\"}}",
+            escape_label_value(&self.base_topic)
+        );
+
+        let mut result = String::new();
+        for (name, kind, help, value) in [
+            (
+                "messages_received_total",
+                "counter",
+                "Messages received from the broker.",
+                metrics.messages_received,
+            ),
+            (
+                "messages_published_total",
+                "counter",
+                "Messages published to the broker.",
+                metrics.messages_published,
+            ),
+            (
+                "publish_errors_total",
+                "counter",
+                "Messages which failed to be published.",
+                metrics.publish_errors,
+            ),
+            (
+                "messages_dropped_total",
+                "counter",
+                "Messages not delivered to a watcher as its channel was full.",
+                metrics.messages_dropped,
+            ),
+            (
+                "messages_oversized_total",
+                "counter",
+                "Received messages above the maximum payload size.",
+                metrics.messages_oversized,
+            ),
+            (
+                "reconnects_total",
+                "counter",
+                "Reconnects to the broker.",
+                metrics.reconnects,
+            ),
+            (
+                "history_topics",
+                "gauge",
+                "Topics known in the history.",
+                history_topics as u64,
+            ),
+        ] {
+            _ = writeln!(result, "# HELP mqtt_smarthome_{name} {help}");
+            _ = writeln!(result, "# TYPE mqtt_smarthome_{name} {kind}");
+            _ = writeln!(result, "mqtt_smarthome_{name}{label} {value}");
+        }
+        result
+    }
+}
+
+#[cfg(feature = "prometheus")]
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn counters_move() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let _receiver = smarthome.watch("foo", false).await;
+        for index in 0..30 {
+            handle_incoming(&smarthome, "foo".to_owned(), index.to_string(), false).await;
+        }
+        smarthome.publish("bar", 42, false).await.unwrap();
+
+        let metrics = smarthome.metrics();
+        assert_eq!(metrics.messages_received, 30);
+        assert_eq!(metrics.messages_published, 1);
+        assert_eq!(metrics.publish_errors, 0);
+        assert_eq!(metrics.messages_dropped, 5);
+        assert_eq!(metrics.reconnects, 0);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn escape_label_value_works() {
+        assert_eq!(escape_label_value(r#"a\b"c"#), r#"a\\b\"c"#);
+        assert_eq!(escape_label_value("a\nb"), r"a\nb");
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn encode_prometheus_contains_metrics() {
+        let (smarthome, _eventloop) = MqttSmarthome::new_without_eventloop(
+            crate::LastWillConfig::new("home/connected".to_owned(), false),
+            crate::protocol::MqttOptions::new("client", "localhost", 1883),
+        );
+        handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        let encoded = smarthome.encode_prometheus().await;
+        assert!(encoded.contains("# TYPE mqtt_smarthome_messages_received_total counter\n"));
+        assert!(encoded.contains("mqtt_smarthome_messages_received_total{base_topic=\"home\"} 1\n"));
+        assert!(encoded.contains("mqtt_smarthome_history_topics{base_topic=\"home\"} 1\n"));
+    }
+}
@@ -0,0 +1,131 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+
+/// Publishes held back while disconnected from the broker.
+#[derive(Debug)]
+pub struct OfflineBuffer {
+    capacity: usize,
+    /// `(topic, payload, retain)` in publish order
+    messages: VecDeque<(String, Bytes, bool)>,
+}
+
+impl OfflineBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            messages: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Append the message, dropping the oldest one when full.
+    ///
+    /// A retained message replaces the earlier retained message of the same topic as only the latest survives on the broker anyway.
+    pub fn push(&mut self, topic: String, payload: Bytes, retain: bool) {
+        if retain {
+            self.messages
+                .retain(|(existing, _, existing_retain)| !existing_retain || *existing != topic);
+        }
+        while self.messages.len() >= self.capacity.max(1) {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((topic, payload, retain));
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn take(&mut self) -> VecDeque<(String, Bytes, bool)> {
+        core::mem::take(&mut self.messages)
+    }
+
+    /// Put taken `messages` back in front of the ones buffered meanwhile, dropping the oldest when full.
+    ///
+    /// Retained messages are dropped when a newer retained message of the same topic is buffered.
+    pub fn restore<I>(&mut self, messages: I)
+    where
+        I: IntoIterator<Item = (String, Bytes, bool)>,
+        I::IntoIter: DoubleEndedIterator,
+    {
+        for (topic, payload, retain) in messages.into_iter().rev() {
+            if self.messages.len() >= self.capacity.max(1) {
+                break;
+            }
+            let is_replaced = retain
+                && self
+                    .messages
+                    .iter()
+                    .any(|(existing, _, existing_retain)| *existing_retain && *existing == topic);
+            if !is_replaced {
+                self.messages.push_front((topic, payload, retain));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topics(buffer: &mut OfflineBuffer) -> Vec<(String, Bytes)> {
+        buffer
+            .take()
+            .into_iter()
+            .map(|(topic, payload, _)| (topic, payload))
+            .collect()
+    }
+
+    fn message(topic: &str, payload: &'static str) -> (String, Bytes) {
+        (topic.to_owned(), Bytes::from_static(payload.as_bytes()))
+    }
+
+    #[test]
+    fn drops_oldest_when_full() {
+        let mut buffer = OfflineBuffer::new(2);
+        buffer.push("a".to_owned(), Bytes::from_static(b"1"), false);
+        buffer.push("b".to_owned(), Bytes::from_static(b"2"), false);
+        buffer.push("c".to_owned(), Bytes::from_static(b"3"), false);
+        assert_eq!(topics(&mut buffer), [message("b", "2"), message("c", "3")]);
+        assert_eq!(buffer.len(), 0);
+    }
+
+    #[test]
+    fn retained_are_coalesced_per_topic() {
+        let mut buffer = OfflineBuffer::new(10);
+        buffer.push("a".to_owned(), Bytes::from_static(b"1"), true);
+        buffer.push("a".to_owned(), Bytes::from_static(b"2"), false);
+        buffer.push("b".to_owned(), Bytes::from_static(b"3"), true);
+        buffer.push("a".to_owned(), Bytes::from_static(b"4"), true);
+        assert_eq!(
+            topics(&mut buffer),
+            [message("a", "2"), message("b", "3"), message("a", "4")]
+        );
+    }
+
+    #[test]
+    fn restore_in_front_of_newer() {
+        let mut buffer = OfflineBuffer::new(3);
+        buffer.push("c".to_owned(), Bytes::from_static(b"3"), true);
+        buffer.restore([
+            ("a".to_owned(), Bytes::from_static(b"1"), false),
+            ("b".to_owned(), Bytes::from_static(b"2"), false),
+            ("c".to_owned(), Bytes::from_static(b"0"), true),
+        ]);
+        assert_eq!(
+            topics(&mut buffer),
+            [message("a", "1"), message("b", "2"), message("c", "3")]
+        );
+
+        buffer.push("c".to_owned(), Bytes::from_static(b"3"), false);
+        buffer.restore([
+            ("a".to_owned(), Bytes::from_static(b"1"), false),
+            ("b".to_owned(), Bytes::from_static(b"2"), false),
+            ("d".to_owned(), Bytes::from_static(b"4"), false),
+        ]);
+        assert_eq!(
+            topics(&mut buffer),
+            [message("b", "2"), message("d", "4"), message("c", "3")]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::PoisonError;
+use std::time::SystemTime;
+
+use crate::metrics::Metrics;
+use crate::watcher::Derived;
+use crate::{send_to_watchers, EntrySource, HistoryEntry, MqttSmarthome};
+
+/// Handling of the history for received payloads above the [maximum size](MqttSmarthome::set_max_payload_bytes).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedPayload {
+    /// Keep an entry with the payload cut at the maximum size, see [`HistoryEntry::truncated`].
+    ///
+    /// Payloads which are valid UTF-8 are cut before an incomplete character.
+    /// The typed accessors like [`HistoryEntry::as_float`] and the `last_*` methods read the cut payload, check [`HistoryEntry::truncated`] before relying on them.
+    Truncate,
+    /// Leave the history of the topic unchanged.
+    #[default]
+    Skip,
+}
+
+impl MqttSmarthome {
+    /// Limit the size of received payloads which are handled like usual. Unlimited by default.
+    ///
+    /// Larger payloads still count as received and reach the [taps](Self::tap), but are only delivered to [bytes channels](Self::subscribe_bytes_channel).
+    /// The history handles them according to the `policy`.
+    /// They are counted in [`messages_oversized`](crate::MetricsSnapshot::messages_oversized).
+    pub fn set_max_payload_bytes(
+        &self,
+        max_payload_bytes: Option<usize>,
+        policy: OversizedPayload,
+    ) {
+        *self
+            .max_payload_bytes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = max_payload_bytes.map(|max| (max, policy));
+    }
+
+    /// The maximum size and policy when the `len` is above the maximum payload size.
+    pub(crate) fn oversized(&self, len: usize) -> Option<(usize, OversizedPayload)> {
+        self.max_payload_bytes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .filter(|(max, _)| len > *max)
+    }
+
+    pub(crate) async fn handle_oversized(
+        &self,
+        topic: &str,
+        raw: &[u8],
+        retain: bool,
+        now: SystemTime,
+        (max, policy): (usize, OversizedPayload),
+    ) {
+        Metrics::increase(&self.metrics.oversized);
+        if policy == OversizedPayload::Truncate && self.history.is_enabled() {
+            let entry = HistoryEntry::from_bytes_at(truncate(raw, max), now)
+                .with_retained(retain)
+                .with_truncated(true);
+            self.history.insert(topic.to_owned(), entry);
+        }
+        let senders = self
+            .watchers
+            .read()
+            .await
+            .matching(topic)
+            .filter_map(|watcher| watcher.matching_bytes_sender(topic, retain))
+            .collect::<Vec<_>>();
+        send_to_watchers(
+            self,
+            topic,
+            "",
+            raw,
+            EntrySource::Incoming,
+            Derived::default(),
+            senders,
+        )
+        .await;
+    }
+}
+
+/// The first `max` bytes of the `raw` payload without a character cut in half at the end.
+fn truncate(raw: &[u8], max: usize) -> &[u8] {
+    let cut = &raw[..max];
+    match core::str::from_utf8(cut) {
+        Err(error) if error.error_len().is_none() => &cut[..error.valid_up_to()],
+        _ => cut,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn oversized_only_reaches_bytes_channels() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_max_payload_bytes(Some(4), OversizedPayload::Truncate);
+        let mut strings = smarthome.watch("camera/#", false).await;
+        let mut bytes = smarthome.subscribe_bytes_channel("camera/#", false).await;
+        handle_incoming(
+            &smarthome,
+            "camera/a".to_owned(),
+            "123456".to_owned(),
+            false,
+        )
+        .await;
+        handle_incoming(&smarthome, "camera/a".to_owned(), "1234".to_owned(), false).await;
+
+        assert_eq!(
+            bytes.recv().await,
+            Some(("camera/a".to_owned(), b"123456".to_vec()))
+        );
+        assert_eq!(
+            strings.recv().await,
+            Some(("camera/a".to_owned(), "1234".to_owned()))
+        );
+        assert_eq!(smarthome.metrics().messages_received, 2);
+        assert_eq!(smarthome.metrics().messages_oversized, 1);
+    }
+
+    #[tokio::test]
+    async fn oversized_history_policy() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_max_payload_bytes(Some(4), OversizedPayload::Truncate);
+        handle_incoming(&smarthome, "a".to_owned(), "123456".to_owned(), true).await;
+        let entry = smarthome.last("a").await.unwrap();
+        assert_eq!(entry.payload(), "1234");
+        assert!(entry.truncated());
+        assert!(entry.retained());
+
+        smarthome.set_max_payload_bytes(Some(4), OversizedPayload::Skip);
+        handle_incoming(&smarthome, "b".to_owned(), "1".to_owned(), false).await;
+        handle_incoming(&smarthome, "b".to_owned(), "123456".to_owned(), false).await;
+        let entry = smarthome.last("b").await.unwrap();
+        assert_eq!(entry.payload(), "1");
+        assert!(!entry.truncated());
+        assert!(smarthome.since_last_received().await.is_some());
+
+        smarthome.set_max_payload_bytes(None, OversizedPayload::Skip);
+        handle_incoming(&smarthome, "b".to_owned(), "123456".to_owned(), false).await;
+        assert_eq!(smarthome.last("b").await.unwrap().payload(), "123456");
+    }
+
+    #[rstest::rstest]
+    #[case::ascii(b"123456", b"1234")]
+    #[case::split_character("123°C".as_bytes(), b"123")]
+    #[case::whole_character("12°C".as_bytes(), "12°".as_bytes())]
+    #[case::binary(&[0xff, 0xfe, 0xfd, 0xfc, 0xfb], &[0xff, 0xfe, 0xfd, 0xfc])]
+    fn truncate_keeps_characters(#[case] raw: &[u8], #[case] expected: &[u8]) {
+        assert_eq!(truncate(raw, 4), expected);
+    }
+}
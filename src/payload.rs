@@ -1,27 +1,863 @@
+use core::time::Duration;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+
+use crate::logging::warning;
+
+/// Values which can be published as MQTT payload.
+///
+/// Numbers and booleans are published in their textual form.
+pub trait IntoPayload {
+    fn into_payload(self) -> Bytes;
+}
+
+impl IntoPayload for Bytes {
+    fn into_payload(self) -> Bytes {
+        self
+    }
+}
+
+impl IntoPayload for Vec<u8> {
+    fn into_payload(self) -> Bytes {
+        Bytes::from(self)
+    }
+}
+
+impl IntoPayload for &[u8] {
+    fn into_payload(self) -> Bytes {
+        Bytes::copy_from_slice(self)
+    }
+}
+
+impl IntoPayload for String {
+    fn into_payload(self) -> Bytes {
+        Bytes::from(self)
+    }
+}
+
+impl IntoPayload for &String {
+    fn into_payload(self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl IntoPayload for &str {
+    fn into_payload(self) -> Bytes {
+        Bytes::copy_from_slice(self.as_bytes())
+    }
+}
+
+impl IntoPayload for bool {
+    fn into_payload(self) -> Bytes {
+        Bytes::from_static(if self { b"true" } else { b"false" })
+    }
+}
+
+macro_rules! into_payload_display {
+    ($($type:ty),+) => {
+        $(
+            impl IntoPayload for $type {
+                fn into_payload(self) -> Bytes {
+                    Bytes::from(self.to_string())
+                }
+            }
+        )+
+    };
+}
+
+into_payload_display!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize, f32, f64);
+
+const TRUTHY: &[&str] = &[
+    "true", "True", "TRUE", "on", "On", "ON", "online", "Online", "ONLINE", "1", "2", "yes", "Yes",
+    "YES", "open", "Open", "OPEN", "locked", "Locked", "LOCKED", "home", "Home", "HOME", "active",
+    "Active", "ACTIVE", "heat", "Heat", "HEAT",
+];
+const FALSY: &[&str] = &[
+    "false", "False", "FALSE", "off", "Off", "OFF", "offline", "Offline", "OFFLINE", "0", "no",
+    "No", "NO", "closed", "Closed", "CLOSED", "unlocked", "Unlocked", "UNLOCKED", "away", "Away",
+    "AWAY", "not_home", "Not_home", "NOT_HOME", "inactive", "Inactive", "INACTIVE",
+];
+
+/// Words describing `true` and `false` states.
+///
+/// The [`Default`] contains the words known by [`as_bool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoolVocabulary {
+    truthy: Vec<String>,
+    falsy: Vec<String>,
+    case_insensitive: bool,
+}
+
+impl Default for BoolVocabulary {
+    fn default() -> Self {
+        Self::new(TRUTHY.iter().copied(), FALSY.iter().copied())
+    }
+}
+
+impl BoolVocabulary {
+    #[must_use]
+    pub fn new<T, F>(truthy: T, falsy: F) -> Self
+    where
+        T: IntoIterator,
+        T::Item: Into<String>,
+        F: IntoIterator,
+        F::Item: Into<String>,
+    {
+        Self {
+            truthy: truthy.into_iter().map(Into::into).collect(),
+            falsy: falsy.into_iter().map(Into::into).collect(),
+            case_insensitive: false,
+        }
+    }
+
+    /// Ignore the ASCII case when comparing payloads with the words.
+    #[must_use]
+    pub const fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    fn contains(&self, words: &[String], payload: &str) -> bool {
+        words.iter().any(|word| {
+            if self.case_insensitive {
+                word.eq_ignore_ascii_case(payload)
+            } else {
+                word == payload
+            }
+        })
+    }
+}
+
 /// Detect common `true` / `false` states in a string payload.
+///
+/// See [`as_bool`] for the known states.
+/// Unknown payloads are assumed to be `true`. Use [`as_bool`] to handle them explicitly.
 #[must_use]
 pub fn is_true(payload: &str) -> bool {
-    match payload {
-        "true" | "True" | "TRUE" | "on" | "On" | "ON" | "online" | "Online" | "ONLINE" | "1"
-        | "2" => true,
-        "false" | "False" | "FALSE" | "off" | "Off" | "OFF" | "offline" | "Offline" | "OFFLINE"
-        | "0" => false,
+    as_bool(payload).unwrap_or_else(|| {
+        warning!(payload = payload; "is_true unclear, assumes true: {payload:?}");
+        true
+    })
+}
+
+/// Detect common `true` / `false` states in a string payload.
+///
+/// Each state is known in lowercase, capitalized and uppercase:
+///
+/// | `true`   | `false`              |
+/// |----------|----------------------|
+/// | `true`   | `false`              |
+/// | `on`     | `off`                |
+/// | `online` | `offline`            |
+/// | `1`, `2` | `0`                  |
+/// | `yes`    | `no`                 |
+/// | `open`   | `closed`             |
+/// | `locked` | `unlocked`           |
+/// | `home`   | `away`, `not_home`   |
+/// | `active` | `inactive`           |
+/// | `heat`   | `off`                |
+///
+/// `open` is `true` as in a door or window being open.
+/// For a valve this might be the other way around, use a [`BoolVocabulary`] then.
+///
+/// Unknown payloads result in `None`.
+#[must_use]
+pub fn as_bool(payload: &str) -> Option<bool> {
+    if TRUTHY.contains(&payload) {
+        Some(true)
+    } else if FALSY.contains(&payload) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Same as [`is_true`] but with the words of the `vocabulary`.
+#[must_use]
+pub fn is_true_with(payload: &str, vocabulary: &BoolVocabulary) -> bool {
+    as_bool_with(payload, vocabulary).unwrap_or_else(|| {
+        warning!(payload = payload; "is_true unclear, assumes true: {payload:?}");
+        true
+    })
+}
+
+/// Same as [`as_bool`] but with the words of the `vocabulary`.
+#[must_use]
+pub fn as_bool_with(payload: &str, vocabulary: &BoolVocabulary) -> Option<bool> {
+    if vocabulary.contains(&vocabulary.truthy, payload) {
+        Some(true)
+    } else if vocabulary.contains(&vocabulary.falsy, payload) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Parse the number at the start of the payload. Units separated by whitespace are ignored.
+///
+/// Prefer [`as_f64`] for values which need more than about 7 significant digits.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn as_f32(payload: &str) -> Option<f32> {
+    as_f64(payload)
+        .map(|value| value as f32)
+        .filter(|value| value.is_finite())
+}
+
+/// Parse the number at the start of the payload. Units are ignored, even without whitespace in between like `12.3°C`.
+///
+/// A single comma is accepted as decimal separator when there is no dot.
+/// Thousands separators are not guessed, so `1,234` is `1.234` and `1,234,5` is `None`.
+/// Multiple separators like `12.3.4` are ambiguous and result in `None`.
+/// Infinite and NaN values result in `None`.
+#[must_use]
+pub fn as_f64(payload: &str) -> Option<f64> {
+    let token = first_token(payload)?;
+    token
+        .parse::<f64>()
+        .ok()
+        .or_else(|| leading_number(token))
+        .filter(|value| value.is_finite())
+}
+
+/// Parse the number at the start of the token consisting of an optional sign, digits and one decimal separator.
+fn leading_number(token: &str) -> Option<f64> {
+    let bytes = token.as_bytes();
+    let digits_from = |from: usize| {
+        bytes
+            .iter()
+            .skip(from)
+            .take_while(|byte| byte.is_ascii_digit())
+            .count()
+    };
+    let mut end = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let integer = digits_from(end);
+    end += integer;
+    let mut fraction = 0;
+    if matches!(bytes.get(end), Some(b'.' | b',')) {
+        fraction = digits_from(end + 1);
+        if fraction > 0 {
+            end += 1 + fraction;
+        }
+    }
+    if integer + fraction == 0 || matches!(bytes.get(end), Some(b'.' | b',')) {
+        return None;
+    }
+    token[..end].replacen(',', ".", 1).parse().ok()
+}
+
+/// Parse a temperature and convert it to degree Celsius.
+///
+/// Known units are `°C`, `C`, `°F`, `F` and `K`, attached to the number or separated by whitespace.
+/// Without a unit the temperature is assumed to be in Celsius already.
+/// Other units result in `None`.
+#[must_use]
+pub fn as_celsius(payload: &str) -> Option<f32> {
+    let value = as_f32(payload)?;
+    let unit = payload
+        .trim_start()
+        .trim_start_matches(|char: char| {
+            char.is_ascii_digit() || matches!(char, '+' | '-' | '.' | ',')
+        })
+        .trim();
+    match unit {
+        "" | "°C" | "C" | "℃" => Some(value),
+        "°F" | "F" | "℉" => Some((value - 32.0) * 5.0 / 9.0),
+        "K" => Some(value - 273.15),
+        _ => None,
+    }
+}
+
+/// Parse a percentage like `55`, `55%` or `55 %`.
+///
+/// Values outside of 0 to 100 are clamped.
+#[must_use]
+pub fn as_percent(payload: &str) -> Option<f32> {
+    as_f32(payload).map(|value| value.clamp(0.0, 100.0))
+}
+
+/// Parse a raw byte value of 0 to 255 as percentage, which is common for zigbee brightness.
+///
+/// Values outside of 0 to 255 are clamped.
+#[must_use]
+pub fn as_percent_from_255(payload: &str) -> Option<f32> {
+    as_f32(payload).map(|value| (value / 255.0 * 100.0).clamp(0.0, 100.0))
+}
+
+/// Longest duration accepted by [`as_duration`], about 10 years
+const MAX_DURATION_SECS: f64 = 10.0 * 365.0 * 24.0 * 60.0 * 60.0;
+
+/// Parse a duration.
+///
+/// | Format | Example |
+/// |--------|---------|
+/// | Seconds | `30`, `1.5` |
+/// | Number with unit `s`, `sec`, `m`, `min`, `h` or `d` | `30s`, `5 min`, `1.5h` |
+/// | Combined units | `1h30m`, `1d 12h` |
+/// | `HH:MM:SS` | `00:05:00` |
+///
+/// Negative durations and durations longer than about 10 years result in `None`.
+#[must_use]
+pub fn as_duration(payload: &str) -> Option<Duration> {
+    let payload = payload.trim();
+    let seconds = if payload.contains(':') {
+        clock_seconds(payload)?
+    } else if let Ok(seconds) = payload.parse::<f64>() {
+        seconds
+    } else {
+        unit_seconds(payload)?
+    };
+    (seconds.is_finite() && (0.0..=MAX_DURATION_SECS).contains(&seconds))
+        .then(|| Duration::from_secs_f64(seconds))
+}
+
+/// Seconds of `HH:MM:SS`
+#[allow(clippy::cast_precision_loss)]
+fn clock_seconds(payload: &str) -> Option<f64> {
+    let mut parts = payload.split(':').map(|part| {
+        if part.is_empty() || !part.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        part.parse::<u64>().ok()
+    });
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    if parts.next().is_some() || minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    Some((hours as f64).mul_add(3600.0, (minutes * 60 + seconds) as f64))
+}
+
+/// Seconds of numbers with units like `1h 30min`. Every number needs a unit.
+fn unit_seconds(payload: &str) -> Option<f64> {
+    let mut rest = payload;
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|char: char| !char.is_ascii_digit() && char != '.')
+            .unwrap_or(rest.len());
+        let number = rest[..number_end].parse::<f64>().ok()?;
+        rest = rest[number_end..].trim_start();
+        let unit_end = rest
+            .find(|char: char| !char.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let factor = match &rest[..unit_end] {
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" => 60.0 * 60.0,
+            "d" => 24.0 * 60.0 * 60.0,
+            _ => return None,
+        };
+        total += number * factor;
+        rest = rest[unit_end..].trim_start();
+    }
+    (!payload.is_empty()).then_some(total)
+}
+
+/// Unix timestamps from this value on are assumed to be milliseconds.
+/// In seconds this would be in the year 5138, in milliseconds it is in 1973.
+const UNIX_MILLIS_FROM: i64 = 100_000_000_000;
+
+/// Parse a timestamp as unix seconds, unix milliseconds or ISO 8601.
+///
+/// Unix seconds and milliseconds are distinguished by their magnitude.
+/// ISO 8601 needs a full date and time like `2024-05-03T18:22:00+02:00` with optional fractional seconds.
+/// Without an offset or `Z` the time is assumed to be UTC.
+#[must_use]
+pub fn as_timestamp(payload: &str) -> Option<SystemTime> {
+    let payload = payload.trim();
+    if let Ok(number) = payload.parse::<i64>() {
+        return if number.unsigned_abs() >= UNIX_MILLIS_FROM.unsigned_abs() {
+            unix_time(number.div_euclid(1000), number.rem_euclid(1000) * 1_000_000)
+        } else {
+            unix_time(number, 0)
+        };
+    }
+    iso_8601(payload)
+}
+
+fn unix_time(seconds: i64, nanos: i64) -> Option<SystemTime> {
+    let nanos = Duration::from_nanos(u64::try_from(nanos).ok()?);
+    let time = if seconds >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(seconds.unsigned_abs()))?
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))?
+    };
+    time.checked_add(nanos)
+}
+
+/// Parse `YYYY-MM-DDTHH:MM:SS[.fraction][Z|±HH:MM|±HHMM]`
+fn iso_8601(payload: &str) -> Option<SystemTime> {
+    fn number(digits: &str) -> Option<i64> {
+        if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    }
+
+    let date = payload.get(..10)?;
+    let separator = payload.get(10..11)?;
+    let time = payload.get(11..19)?;
+    let mut rest = payload.get(19..)?;
+    if !matches!(separator, "T" | "t" | " ")
+        || date.as_bytes()[4] != b'-'
+        || date.as_bytes()[7] != b'-'
+        || time.as_bytes()[2] != b':'
+        || time.as_bytes()[5] != b':'
+    {
+        return None;
+    }
+    let year = number(&date[..4])?;
+    let month = number(&date[5..7])?;
+    let day = number(&date[8..])?;
+    let hour = number(&time[..2])?;
+    let minute = number(&time[3..5])?;
+    let second = number(&time[6..])?;
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    let mut nanos = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction
+            .find(|char: char| !char.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        if digits == 0 {
+            return None;
+        }
+        let significant = &fraction[..digits.min(9)];
+        let scale = 10_i64.pow(9 - u32::try_from(significant.len()).ok()?);
+        nanos = number(significant)? * scale;
+        rest = &fraction[digits..];
+    }
+
+    let offset_seconds = match rest {
+        "" | "Z" | "z" => 0,
         _ => {
-            eprintln!("WARNING is_true unclear, assumes true: {payload:?}");
-            true
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset = &rest[1..];
+            let (hours, minutes) = match offset.len() {
+                5 if offset.as_bytes()[2] == b':' => (offset.get(..2)?, offset.get(3..)?),
+                4 => (offset.get(..2)?, offset.get(2..)?),
+                _ => return None,
+            };
+            let (hours, minutes) = (number(hours)?, number(minutes)?);
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
         }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86_400 + hour * 3600 + minute * 60 + second - offset_seconds;
+    unix_time(seconds, nanos)
+}
+
+const fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+const fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
     }
 }
 
+/// Days since 1970-01-01 of the proleptic Gregorian date.
+///
+/// See <https://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Parse the integer at the start of the payload. Units separated by whitespace are ignored.
+///
+/// Floats are rejected instead of truncated so a change of the reported format is noticed.
+/// Numbers not fitting into `i64` result in `None`.
+#[must_use]
+pub fn as_i64(payload: &str) -> Option<i64> {
+    first_token(payload)?.parse::<i64>().ok()
+}
+
+/// Value at the RFC 6901 `pointer` like `/color/x` in the JSON payload.
+///
+/// Payloads which are not JSON and missing pointers result in `None`.
+#[cfg(feature = "json")]
+#[must_use]
+pub fn json_pointer(payload: &str, pointer: &str) -> Option<serde_json::Value> {
+    let mut value = serde_json::from_str::<serde_json::Value>(payload).ok()?;
+    value.pointer_mut(pointer).map(serde_json::Value::take)
+}
+
+/// Number at the `pointer` in the JSON payload. See [`json_pointer`].
+#[cfg(feature = "json")]
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn json_f32(payload: &str, pointer: &str) -> Option<f32> {
+    json_pointer(payload, pointer)?
+        .as_f64()
+        .map(|value| value as f32)
+        .filter(|value| value.is_finite())
+}
+
+/// Boolean at the `pointer` in the JSON payload. See [`json_pointer`].
+#[cfg(feature = "json")]
+#[must_use]
+pub fn json_bool(payload: &str, pointer: &str) -> Option<bool> {
+    json_pointer(payload, pointer)?.as_bool()
+}
+
+fn first_token(payload: &str) -> Option<&str> {
+    payload
+        .split(char::is_whitespace)
+        .find(|str| !str.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
+    use core::time::Duration;
+
+    use super::IntoPayload;
+
+    #[test]
+    fn into_payload() {
+        assert_eq!(true.into_payload(), "true");
+        assert_eq!(false.into_payload(), "false");
+        assert_eq!(42.into_payload(), "42");
+        assert_eq!(1.5_f32.into_payload(), "1.5");
+        assert_eq!("foo".into_payload(), "foo");
+        assert_eq!(vec![0xff, 0].into_payload(), [0xff, 0].as_slice());
+    }
+
     #[rstest::rstest]
-    fn is_true(#[values("on", "1", "true")] payload: &str) {
+    #[case::empty("", None)]
+    #[case::text("test", None)]
+    #[case::number("42", Some(42.0))]
+    #[case::unit("12.3 °C", Some(12.3))]
+    #[case::indent(" 2.4 °C", Some(2.4))]
+    #[case::precise("123456.789 kWh", Some(123_456.789))]
+    #[case::comma("21,5", Some(21.5))]
+    #[case::comma_unit("21,5 °C", Some(21.5))]
+    #[case::comma_negative("-3,2", Some(-3.2))]
+    #[case::comma_is_not_thousands("1,234", Some(1.234))]
+    #[case::multiple_commas("1,234,5", None)]
+    #[case::comma_and_dot("1,234.5", None)]
+    #[case::glued_celsius("12.3°C", Some(12.3))]
+    #[case::glued_percent("55%", Some(55.0))]
+    #[case::glued_volt("230V", Some(230.0))]
+    #[case::glued_comma("21,5°C", Some(21.5))]
+    #[case::glued_negative("-4.5dB", Some(-4.5))]
+    #[case::exponent("1e3", Some(1000.0))]
+    #[case::multiple_dots("12.3.4", None)]
+    #[case::trailing_dot("12.", Some(12.0))]
+    #[case::trailing_dot_unit("12.V", None)]
+    #[case::sign_only("-", None)]
+    #[case::dot_only(".", None)]
+    #[case::sign_unit("-V", None)]
+    #[case::nan("NaN", None)]
+    #[case::infinite("inf", None)]
+    fn as_f64(#[case] input: &str, #[case] expected: Option<f64>) {
+        match (super::as_f64(input), expected) {
+            (None, None) => {}
+            (Some(actual), Some(expected)) => {
+                float_eq::assert_float_eq!(actual, expected, abs <= 0.000_001);
+            }
+            (actual, expected) => panic!("Assertion failed:\n{actual:?} should be\n{expected:?}"),
+        }
+    }
+
+    #[test]
+    fn as_f32_out_of_range() {
+        assert_eq!(super::as_f32("1e300"), None);
+    }
+
+    #[rstest::rstest]
+    #[case::seconds("30", Some(30_000))]
+    #[case::fraction_seconds("1.5", Some(1500))]
+    #[case::zero("0", Some(0))]
+    #[case::s("30s", Some(30_000))]
+    #[case::sec("30 sec", Some(30_000))]
+    #[case::m("5m", Some(300_000))]
+    #[case::min("5 min", Some(300_000))]
+    #[case::h("2h", Some(7_200_000))]
+    #[case::fraction_hours("1.5h", Some(5_400_000))]
+    #[case::d("1d", Some(86_400_000))]
+    #[case::combined("1h30m", Some(5_400_000))]
+    #[case::combined_spaced("1h 30m 15s", Some(5_415_000))]
+    #[case::padded(" 10s ", Some(10_000))]
+    #[case::clock("00:05:00", Some(300_000))]
+    #[case::clock_long("100:00:01", Some(360_001_000))]
+    #[case::clock_minutes_overflow("00:60:00", None)]
+    #[case::clock_two_parts("05:00", None)]
+    #[case::clock_four_parts("00:00:05:00", None)]
+    #[case::clock_negative("-01:00:00", None)]
+    #[case::clock_absurd("99999999999999999:00:00", None)]
+    #[case::empty("", None)]
+    #[case::text("soon", None)]
+    #[case::unknown_unit("5 weeks", None)]
+    #[case::missing_unit("1h30", None)]
+    #[case::unit_only("min", None)]
+    #[case::negative("-5", None)]
+    #[case::negative_unit("-5s", None)]
+    #[case::nan("NaN", None)]
+    #[case::absurd("4000d", None)]
+    #[case::absurd_seconds("1e12", None)]
+    fn as_duration(#[case] input: &str, #[case] expected_millis: Option<u64>) {
+        assert_eq!(
+            super::as_duration(input),
+            expected_millis.map(Duration::from_millis)
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::unix_seconds("1714753320", Some(1_714_753_320_000))]
+    #[case::unix_millis("1714753320123", Some(1_714_753_320_123))]
+    #[case::unix_epoch("0", Some(0))]
+    #[case::iso_offset("2024-05-03T18:22:00+02:00", Some(1_714_753_320_000))]
+    #[case::iso_offset_compact("2024-05-03T18:22:00+0200", Some(1_714_753_320_000))]
+    #[case::iso_utc("2024-05-03T16:22:00Z", Some(1_714_753_320_000))]
+    #[case::iso_without_offset("2024-05-03T16:22:00", Some(1_714_753_320_000))]
+    #[case::iso_negative_offset("2024-05-03T11:22:00-05:00", Some(1_714_753_320_000))]
+    #[case::iso_space("2024-05-03 16:22:00Z", Some(1_714_753_320_000))]
+    #[case::iso_millis("2024-05-03T16:22:00.123Z", Some(1_714_753_320_123))]
+    #[case::iso_leap_day("2024-02-29T00:00:00Z", Some(1_709_164_800_000))]
+    #[case::iso_no_leap_day("2023-02-29T00:00:00Z", None)]
+    #[case::iso_month("2024-13-01T00:00:00Z", None)]
+    #[case::iso_hour("2024-05-03T24:00:00Z", None)]
+    #[case::iso_date_only("2024-05-03", None)]
+    #[case::iso_garbage_offset("2024-05-03T16:22:00+2", None)]
+    #[case::iso_empty_fraction("2024-05-03T16:22:00.Z", None)]
+    #[case::iso_unicode("2024-05-03T16:22:00ü", None)]
+    #[case::empty("", None)]
+    #[case::text("yesterday", None)]
+    fn as_timestamp(#[case] input: &str, #[case] expected_millis: Option<u64>) {
+        assert_eq!(
+            super::as_timestamp(input),
+            expected_millis.map(|millis| std::time::UNIX_EPOCH + Duration::from_millis(millis))
+        );
+    }
+
+    #[test]
+    fn as_timestamp_nanos() {
+        assert_eq!(
+            super::as_timestamp("2024-05-03T16:22:00.123456789123Z"),
+            Some(std::time::UNIX_EPOCH + Duration::new(1_714_753_320, 123_456_789))
+        );
+    }
+
+    #[test]
+    fn as_timestamp_i64_min() {
+        assert_eq!(
+            super::as_timestamp("-9223372036854775808"),
+            std::time::UNIX_EPOCH.checked_sub(Duration::from_millis(9_223_372_036_854_775_808))
+        );
+    }
+
+    #[test]
+    fn as_timestamp_before_epoch() {
+        assert_eq!(
+            super::as_timestamp("1969-12-31T23:59:59Z"),
+            Some(std::time::UNIX_EPOCH - Duration::from_secs(1))
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::bare("22.1", Some(22.1))]
+    #[case::celsius("22.1 °C", Some(22.1))]
+    #[case::celsius_glued("22.1°C", Some(22.1))]
+    #[case::celsius_letter("22.1 C", Some(22.1))]
+    #[case::celsius_symbol("22.1℃", Some(22.1))]
+    #[case::fahrenheit("72.5 °F", Some(22.5))]
+    #[case::fahrenheit_letter("32F", Some(0.0))]
+    #[case::fahrenheit_negative("-40 °F", Some(-40.0))]
+    #[case::kelvin("295.15 K", Some(22.0))]
+    #[case::kelvin_zero("0K", Some(-273.15))]
+    #[case::comma("21,5 °C", Some(21.5))]
+    #[case::other_unit("55 %", None)]
+    #[case::text("warm", None)]
+    fn as_celsius(#[case] input: &str, #[case] expected: Option<f32>) {
+        match (super::as_celsius(input), expected) {
+            (None, None) => {}
+            (Some(actual), Some(expected)) => {
+                float_eq::assert_float_eq!(actual, expected, abs <= 0.001);
+            }
+            (actual, expected) => panic!("Assertion failed:\n{actual:?} should be\n{expected:?}"),
+        }
+    }
+
+    #[rstest::rstest]
+    #[case::plain("55", Some(55.0))]
+    #[case::glued("55%", Some(55.0))]
+    #[case::spaced("55 %", Some(55.0))]
+    #[case::fraction("12,5 %", Some(12.5))]
+    #[case::above("120", Some(100.0))]
+    #[case::below("-5%", Some(0.0))]
+    #[case::text("full", None)]
+    #[case::percent_only("%", None)]
+    fn as_percent(#[case] input: &str, #[case] expected: Option<f32>) {
+        assert_eq!(super::as_percent(input), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::zero("0", Some(0.0))]
+    #[case::full("255", Some(100.0))]
+    #[case::half("127.5", Some(50.0))]
+    #[case::above("300", Some(100.0))]
+    #[case::text("full", None)]
+    fn as_percent_from_255(#[case] input: &str, #[case] expected: Option<f32>) {
+        assert_eq!(super::as_percent_from_255(input), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::empty("", None)]
+    #[case::text("test", None)]
+    #[case::number("42", Some(42))]
+    #[case::positive("+42", Some(42))]
+    #[case::negative("-42", Some(-42))]
+    #[case::unit("1234567890123 Ws", Some(1_234_567_890_123))]
+    #[case::indent(" 12 W", Some(12))]
+    #[case::float("12.3", None)]
+    #[case::max("9223372036854775807", Some(i64::MAX))]
+    #[case::overflow("9223372036854775808", None)]
+    #[case::many_digits(&"9".repeat(1000), None)]
+    fn as_i64(#[case] input: &str, #[case] expected: Option<i64>) {
+        assert_eq!(super::as_i64(input), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::on("on", Some(true))]
+    #[case::online("ONLINE", Some(true))]
+    #[case::off("off", Some(false))]
+    #[case::zero("0", Some(false))]
+    #[case::unknown("unknown", None)]
+    #[case::empty("", None)]
+    #[case::unavailable("unavailable", None)]
+    #[case::yes("yes", Some(true))]
+    #[case::no("No", Some(false))]
+    #[case::open("OPEN", Some(true))]
+    #[case::closed("closed", Some(false))]
+    #[case::locked("Locked", Some(true))]
+    #[case::unlocked("unlocked", Some(false))]
+    #[case::home("home", Some(true))]
+    #[case::away("away", Some(false))]
+    #[case::not_home("not_home", Some(false))]
+    #[case::active("ACTIVE", Some(true))]
+    #[case::inactive("inactive", Some(false))]
+    #[case::heat("heat", Some(true))]
+    #[case::mixed_case("oPeN", None)]
+    #[case::padded(" on", None)]
+    fn as_bool(#[case] input: &str, #[case] expected: Option<bool>) {
+        assert_eq!(super::as_bool(input), expected);
+    }
+
+    #[rstest::rstest]
+    fn default_vocabulary_matches_as_bool(
+        #[values("on", "ONLINE", "2", "off", "False", "0", "unknown", "tRUE")] payload: &str,
+    ) {
+        let vocabulary = super::BoolVocabulary::default();
+        assert_eq!(
+            super::as_bool_with(payload, &vocabulary),
+            super::as_bool(payload)
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::open("open", Some(true))]
+    #[case::closed("closed", Some(false))]
+    #[case::upper("OPEN", None)]
+    #[case::on("on", None)]
+    fn custom_vocabulary(#[case] input: &str, #[case] expected: Option<bool>) {
+        let vocabulary = super::BoolVocabulary::new(["open"], ["closed"]);
+        assert_eq!(super::as_bool_with(input, &vocabulary), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::lower("locked", Some(true))]
+    #[case::upper("UNLOCKED", Some(false))]
+    #[case::mixed("Locked", Some(true))]
+    fn case_insensitive_vocabulary(#[case] input: &str, #[case] expected: Option<bool>) {
+        let vocabulary =
+            super::BoolVocabulary::new(["LOCKED"], ["UNLOCKED"]).case_insensitive(true);
+        assert_eq!(super::as_bool_with(input, &vocabulary), expected);
+    }
+
+    #[rstest::rstest]
+    fn is_true_assumes_true_for_unknown(#[values("unknown", "", "maybe")] payload: &str) {
         assert!(super::is_true(payload));
     }
 
     #[rstest::rstest]
-    fn is_false(#[values("off", "0", "false")] payload: &str) {
+    fn is_true(
+        #[values("on", "1", "true", "yes", "open", "locked", "home", "active", "heat")]
+        payload: &str,
+    ) {
+        assert!(super::is_true(payload));
+    }
+
+    #[rstest::rstest]
+    fn is_false(
+        #[values(
+            "off", "0", "false", "no", "closed", "unlocked", "away", "not_home", "inactive"
+        )]
+        payload: &str,
+    ) {
         assert!(!super::is_true(payload));
     }
+
+    #[cfg(feature = "json")]
+    #[rstest::rstest]
+    #[case::nested("/color/x", Some(0.7))]
+    #[case::top_level("/temperature", Some(21.5))]
+    #[case::array("/values/1", Some(2.0))]
+    #[case::text("/state", None)]
+    #[case::missing("/humidity", None)]
+    fn json_f32(#[case] pointer: &str, #[case] expected: Option<f32>) {
+        let payload = r#"{"temperature":21.5,"state":"ON","color":{"x":0.7},"values":[1,2]}"#;
+        assert_eq!(super::json_f32(payload, pointer), expected);
+    }
+
+    #[cfg(feature = "json")]
+    #[rstest::rstest]
+    #[case::whole("", Some(r#"{"a/b":{"c~d":[1,2]}}"#))]
+    #[case::escaped("/a~1b/c~0d/1", Some("2"))]
+    #[case::not_escaped("/a/b", None)]
+    #[case::index_out_of_range("/a~1b/c~0d/2", None)]
+    #[case::no_leading_slash("a~1b", None)]
+    fn json_pointer(#[case] pointer: &str, #[case] expected: Option<&str>) {
+        let payload = r#"{"a/b":{"c~d":[1,2]}}"#;
+        let expected = expected.map(|expected| serde_json::from_str(expected).unwrap());
+        assert_eq!(super::json_pointer(payload, pointer), expected);
+    }
+
+    #[cfg(feature = "json")]
+    #[rstest::rstest]
+    #[case::bool(r#"{"occupancy":true}"#, Some(true))]
+    #[case::text(r#"{"occupancy":"true"}"#, None)]
+    #[case::not_json("true", None)]
+    #[case::invalid(r#"{"occupancy":true"#, None)]
+    fn json_bool(#[case] payload: &str, #[case] expected: Option<bool>) {
+        assert_eq!(super::json_bool(payload, "/occupancy"), expected);
+    }
 }
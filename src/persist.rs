@@ -0,0 +1,227 @@
+//! Store the history as JSON lines of `[topic, unix_millis, payload_hex, retained, source, truncated]`.
+//!
+//! The payload is hex encoded as it does not need to be valid UTF-8.
+
+use core::fmt::Write as _;
+use core::time::Duration;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::json::{escape_json_string, parse_json_string};
+use crate::{logging, EntrySource, HistoryEntry, MqttSmarthome};
+
+impl MqttSmarthome {
+    /// Save the last `HistoryEntry` of every topic to the file at `path`.
+    ///
+    /// Can be loaded again with [`restore_history`](Self::restore_history).
+    /// The file is written next to the `path` first and renamed afterwards so an interrupted save keeps the previous file.
+    ///
+    /// # Errors
+    /// Returns an error when the file could not be written.
+    pub async fn save_history(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.history.snapshot().into_iter().collect::<Vec<_>>();
+        entries.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        let mut content = String::new();
+        for (topic, entry) in entries {
+            content += &format_line(&topic, &entry);
+            content.push('\n');
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        tokio::fs::write(&temporary, content).await?;
+        tokio::fs::rename(&temporary, path).await
+    }
+
+    /// Load the history saved with [`save_history`](Self::save_history) from the file at `path`.
+    ///
+    /// Entries older than `max_age` are skipped.
+    /// Topics already having a newer entry keep it.
+    /// Lines which are not valid are skipped with a warning.
+    /// Call this right after creating the client in order to know the previous state before anything happens.
+    /// Returns the amount of restored topics.
+    ///
+    /// # Errors
+    /// Returns an error when the file could not be read.
+    pub async fn restore_history(
+        &self,
+        path: &Path,
+        max_age: Option<Duration>,
+    ) -> io::Result<usize> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let now = self.now();
+        let mut restored = 0;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let Some((topic, entry)) = parse_line(line) else {
+                logging::warning!(client_id = self.client_id.as_str(); "skipping invalid history line: {line}");
+                continue;
+            };
+            let is_too_old = max_age.is_some_and(|max_age| {
+                now.duration_since(entry.time())
+                    .is_ok_and(|age| age > max_age)
+            });
+            if !is_too_old && self.history.restore(topic, entry) {
+                restored += 1;
+            }
+        }
+        Ok(restored)
+    }
+}
+
+fn format_line(topic: &str, entry: &HistoryEntry) -> String {
+    let millis = entry
+        .time()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let mut payload = String::with_capacity(entry.payload_bytes().len() * 2);
+    for byte in entry.payload_bytes() {
+        _ = write!(payload, "{byte:02x}");
+    }
+    let source = match entry.source() {
+        EntrySource::Incoming => "incoming",
+        EntrySource::Published => "published",
+    };
+    format!(
+        "[{},{millis},\"{payload}\",{},\"{source}\",{}]",
+        escape_json_string(topic),
+        entry.retained(),
+        entry.truncated()
+    )
+}
+
+fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_bool(input: &str) -> Option<(bool, &str)> {
+    input
+        .strip_prefix("true")
+        .map(|rest| (true, rest))
+        .or_else(|| input.strip_prefix("false").map(|rest| (false, rest)))
+}
+
+fn parse_line(line: &str) -> Option<(String, HistoryEntry)> {
+    let rest = line.trim().strip_prefix('[')?;
+    let (topic, rest) = parse_json_string(rest)?;
+    let rest = rest.strip_prefix(',')?;
+    let digits = rest.find(|char: char| !char.is_ascii_digit())?;
+    let millis = rest[..digits].parse::<u64>().ok()?;
+    let rest = rest[digits..].strip_prefix(',')?;
+    let (payload, rest) = parse_json_string(rest)?;
+    let (retained, rest) = parse_bool(rest.strip_prefix(',')?)?;
+    let (source, rest) = parse_json_string(rest.strip_prefix(',')?)?;
+    let (truncated, rest) = parse_bool(rest.strip_prefix(',')?)?;
+    if rest != "]" {
+        return None;
+    }
+    let source = match source.as_str() {
+        "incoming" => EntrySource::Incoming,
+        "published" => EntrySource::Published,
+        _ => return None,
+    };
+    let time = SystemTime::UNIX_EPOCH.checked_add(Duration::from_millis(millis))?;
+    let entry = HistoryEntry::from_bytes_at(parse_hex(&payload)?, time)
+        .with_retained(retained)
+        .with_source(source)
+        .with_truncated(truncated);
+    Some((topic, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_roundtrip() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_714_750_000_123);
+        let entry = HistoryEntry::new_at("21,5°", time).with_retained(true);
+        let line = format_line("foo/\"bar\"", &entry);
+        assert_eq!(
+            line,
+            r#"["foo/\"bar\"",1714750000123,"32312c35c2b0",true,"incoming",false]"#
+        );
+        let (topic, parsed) = parse_line(&line).unwrap();
+        assert_eq!(topic, "foo/\"bar\"");
+        assert_eq!(parsed.payload(), entry.payload());
+        assert_eq!(parsed.time(), time);
+        assert!(parsed.retained());
+        assert_eq!(parsed.source(), EntrySource::Incoming);
+        assert!(!parsed.truncated());
+    }
+
+    #[test]
+    fn line_roundtrip_binary() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_714_750_000_123);
+        let entry = HistoryEntry::from_bytes_at([0xff, 0x00, 0xc3], time)
+            .with_source(EntrySource::Published)
+            .with_truncated(true);
+        let (_, parsed) = parse_line(&format_line("foo", &entry)).unwrap();
+        assert_eq!(parsed.payload_bytes(), [0xff, 0x00, 0xc3]);
+        assert!(!parsed.retained());
+        assert_eq!(parsed.source(), EntrySource::Published);
+        assert!(parsed.truncated());
+    }
+
+    #[rstest::rstest]
+    #[case::empty("")]
+    #[case::missing_millis(r#"["foo","00"]"#)]
+    #[case::missing_flags(r#"["foo",1,"00"]"#)]
+    #[case::trailing(r#"["foo",1,"00",false,"incoming",false]x"#)]
+    #[case::negative(r#"["foo",-1,"00",false,"incoming",false]"#)]
+    #[case::odd_hex(r#"["foo",1,"0",false,"incoming",false]"#)]
+    #[case::invalid_hex(r#"["foo",1,"zz",false,"incoming",false]"#)]
+    #[case::unknown_source(r#"["foo",1,"00",false,"elsewhere",false]"#)]
+    fn line_invalid(#[case] line: &str) {
+        assert!(parse_line(line).is_none());
+    }
+
+    #[tokio::test]
+    async fn save_and_restore() {
+        let path = std::env::temp_dir().join(format!(
+            "mqtt-smarthome-history-{}.jsonl",
+            std::process::id()
+        ));
+        let old = SystemTime::now() - Duration::from_hours(24 * 7);
+
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.publish("foo", "new", false).await.unwrap();
+        smarthome
+            .history
+            .insert("old".to_owned(), HistoryEntry::new_at("old", old));
+        smarthome.save_history(&path).await.unwrap();
+        assert!(!path.with_extension("jsonl.tmp").exists());
+
+        let restored = MqttSmarthome::new_for_tests();
+        let amount = restored
+            .restore_history(&path, Some(Duration::from_hours(24)))
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(amount, 1);
+        assert_eq!(restored.last("foo").await.unwrap().payload(), "new");
+        assert!(restored.last("old").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn restore_skips_invalid_lines() {
+        let path = std::env::temp_dir().join(format!(
+            "mqtt-smarthome-history-invalid-{}.jsonl",
+            std::process::id()
+        ));
+        let valid = format_line("foo", &HistoryEntry::new("42"));
+        std::fs::write(&path, format!("[\"broken\"\n{valid}\n")).unwrap();
+
+        let smarthome = MqttSmarthome::new_for_tests();
+        let amount = smarthome.restore_history(&path, None).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(amount, 1);
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "42");
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::{topic_filter, MqttSmarthome};
+
+/// Watcher which is registered but not yet subscribed to.
+///
+/// Created by [`prepare_channel`](crate::MqttSmarthome::prepare_channel).
+/// Dropping it without calling [`activate`](Self::activate) removes the watcher again.
+#[must_use = "dropping removes the prepared watcher"]
+pub struct PreparedSubscription {
+    active: Arc<AtomicBool>,
+    allow_retained: bool,
+    prepared_at: SystemTime,
+    smarthome: MqttSmarthome,
+    topic: String,
+}
+
+impl PreparedSubscription {
+    pub(crate) const fn new(
+        smarthome: MqttSmarthome,
+        topic: String,
+        allow_retained: bool,
+        active: Arc<AtomicBool>,
+        prepared_at: SystemTime,
+    ) -> Self {
+        Self {
+            active,
+            allow_retained,
+            prepared_at,
+            smarthome,
+            topic,
+        }
+    }
+
+    /// Start delivering messages to the channel and subscribe to the topic.
+    ///
+    /// The latest payloads of the topics in the history which arrived after preparing are delivered first.
+    /// Activating multiple times has no further effect.
+    pub async fn activate(&self) {
+        // Messages are inserted into the history before the watchers are read, holding the watchers keeps newer messages behind the replay
+        let watchers = self.smarthome.watchers.write().await;
+        if self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut arrived = self.smarthome.history.filter_map(|topic, entry| {
+            (topic_filter::matches(topic, &self.topic)
+                && entry.time() >= self.prepared_at
+                && (self.allow_retained || !entry.retained()))
+            .then(|| {
+                (
+                    entry.time(),
+                    topic.clone(),
+                    entry.payload().into_owned(),
+                    entry.source(),
+                )
+            })
+        });
+        arrived.sort_unstable_by(|(a_time, a_topic, ..), (b_time, b_topic, ..)| {
+            a_time.cmp(b_time).then_with(|| a_topic.cmp(b_topic))
+        });
+        if let Some(watcher) = watchers.with_activation(&self.active) {
+            for (_, topic, payload, source) in arrived {
+                _ = watcher.replay(&topic, &payload, source);
+            }
+        }
+        self.active.store(true, Ordering::Relaxed);
+        drop(watchers);
+        self.smarthome.subscribe(&self.topic).await;
+    }
+}
+
+impl Drop for PreparedSubscription {
+    fn drop(&mut self) {
+        if self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        // Without the lock the watcher stays deactivated and is removed as closed with the next cleanup
+        if let Ok(mut watchers) = self.smarthome.watchers.try_write() {
+            watchers.retain(|watcher| !watcher.has_activation(&self.active));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use tokio::sync::mpsc::error::TryRecvError;
+
+    use crate::clock::MockClock;
+    use crate::{handle_incoming, MqttSmarthome};
+
+    async fn incoming(smarthome: &MqttSmarthome, topic: &str, payload: &str) {
+        handle_incoming(smarthome, topic.to_owned(), payload.to_owned(), false).await;
+    }
+
+    #[tokio::test]
+    async fn no_messages_before_activation() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let (mut receiver, prepared) = smarthome.prepare_channel("foo/#", false).await;
+        incoming(&smarthome, "foo/bar", "1").await;
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+        assert!(!smarthome.subscribed.read().await.contains("foo/#"));
+
+        prepared.activate().await;
+        assert!(smarthome.subscribed.read().await.contains("foo/#"));
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(("foo/bar".to_owned(), "1".to_owned())),
+            "replayed from the history"
+        );
+        incoming(&smarthome, "foo/bar", "2").await;
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(("foo/bar".to_owned(), "2".to_owned()))
+        );
+    }
+
+    #[tokio::test]
+    async fn activation_replays_messages_since_preparing() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        smarthome.set_clock(clock.clone());
+        let _other = smarthome.subscribe_and_watch("foo/#", true).await;
+        incoming(&smarthome, "foo/old", "0").await;
+        clock.advance(Duration::from_secs(1));
+        let (mut receiver, prepared) = smarthome.prepare_channel("foo/#", false).await;
+        clock.advance(Duration::from_secs(1));
+        incoming(&smarthome, "foo/bar", "1").await;
+        handle_incoming(&smarthome, "foo/retained".to_owned(), "2".to_owned(), true).await;
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+
+        prepared.activate().await;
+        incoming(&smarthome, "foo/bar", "3").await;
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(("foo/bar".to_owned(), "1".to_owned()))
+        );
+        assert_eq!(
+            receiver.try_recv(),
+            Ok(("foo/bar".to_owned(), "3".to_owned()))
+        );
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn activation_is_idempotent() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let (mut receiver, prepared) = smarthome.prepare_channel("foo", false).await;
+        prepared.activate().await;
+        prepared.activate().await;
+        assert_eq!(smarthome.subscribed.read().await.len(), 1);
+        assert_eq!(smarthome.watchers.read().await.len(), 1);
+        incoming(&smarthome, "foo", "1").await;
+        assert_eq!(receiver.try_recv(), Ok(("foo".to_owned(), "1".to_owned())));
+        assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[tokio::test]
+    async fn drop_without_activation_removes_watcher() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let (_receiver, prepared) = smarthome.prepare_channel("foo", false).await;
+        assert_eq!(smarthome.watchers.read().await.len(), 1);
+        drop(prepared);
+        assert!(smarthome.watchers.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_while_locked_removes_watcher_later() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let (_receiver, prepared) = smarthome.prepare_channel("foo", false).await;
+        let watchers = smarthome.watchers.read().await;
+        drop(prepared);
+        drop(watchers);
+        assert_eq!(smarthome.watchers.read().await.len(), 1);
+
+        let (_receiver, _prepared) = smarthome.prepare_channel("bar", false).await;
+        assert_eq!(smarthome.watchers.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_after_activation_keeps_watcher() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let (_receiver, prepared) = smarthome.prepare_channel("foo", false).await;
+        prepared.activate().await;
+        drop(prepared);
+        assert_eq!(smarthome.watchers.read().await.len(), 1);
+    }
+}
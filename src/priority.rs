@@ -0,0 +1,157 @@
+use std::sync::PoisonError;
+
+use tokio::sync::mpsc::Receiver;
+
+use crate::watcher::ChannelPayload;
+use crate::{topic_filter, MqttSmarthome};
+
+/// Priority of a channel, see [`subscribe_channel_priority`](MqttSmarthome::subscribe_channel_priority).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    #[default]
+    Normal,
+    High,
+}
+
+impl MqttSmarthome {
+    /// Same as [`subscribe_and_watch`](Self::subscribe_and_watch) but a high priority `topic` filter is handled before the others where this client chooses the order:
+    ///
+    /// - After reconnecting without a resumed session the high priority filters are subscribed with their own request first, so the broker sends their retained messages before the others.
+    /// - Messages of the [offline buffer](Self::set_offline_buffer) for topics matching a high priority filter are published first. The order within both groups stays the same.
+    /// - [`subscribe_channel_replay`](Self::subscribe_channel_replay) replays topics matching a high priority filter first.
+    ///
+    /// It does not apply to messages which are already received: each one is delivered to every matching channel at once and channels are not emptied in any particular order.
+    /// The order in which the broker sends other messages is not affected either.
+    /// The priority stays until the `topic` is [unsubscribed](Self::unsubscribe), even when the channel is dropped.
+    pub async fn subscribe_channel_priority(
+        &self,
+        topic: &str,
+        allow_retained: bool,
+        priority: Priority,
+    ) -> Receiver<ChannelPayload> {
+        if priority == Priority::High {
+            let mut prioritized = self
+                .prioritized
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if !prioritized.iter().any(|existing| existing == topic) {
+                prioritized.push(topic.to_owned());
+            }
+        }
+        self.subscribe_and_watch(topic, allow_retained).await
+    }
+
+    /// Filters subscribed with [`Priority::High`].
+    pub(crate) fn prioritized(&self) -> Vec<String> {
+        self.prioritized
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn forget_priority(&self, topic: &str) {
+        self.prioritized
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|existing| existing != topic);
+    }
+}
+
+/// Whether the `topic` matches any of the `prioritized` filters.
+pub fn is_prioritized(prioritized: &[String], topic: &str) -> bool {
+    prioritized
+        .iter()
+        .any(|filter| topic_filter::matches(topic, filter))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{EventLoop, MqttOptions, Request};
+    use crate::{handle_incoming, LastWillConfig};
+
+    fn new() -> (MqttSmarthome, EventLoop) {
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            MqttOptions::new("test", "localhost", 1883),
+        );
+        smarthome.set_birth_message(None);
+        eventloop.clean();
+        eventloop.pending.clear();
+        (smarthome, eventloop)
+    }
+
+    fn requests(eventloop: &mut EventLoop) -> Vec<Request> {
+        eventloop.clean();
+        eventloop.pending.drain(..).collect()
+    }
+
+    #[tokio::test]
+    async fn high_priority_is_resubscribed_first() {
+        let (smarthome, mut eventloop) = new();
+        let _status = smarthome.subscribe_and_watch("status/#", true).await;
+        let _set = smarthome
+            .subscribe_channel_priority("set/#", false, Priority::High)
+            .await;
+        requests(&mut eventloop);
+
+        smarthome.initialize_connection(false, 0).await;
+        let filters = requests(&mut eventloop)
+            .into_iter()
+            .map(|request| {
+                let Request::Subscribe(subscribe) = request else {
+                    panic!("expected only subscribes: {request:?}");
+                };
+                subscribe
+                    .filters
+                    .into_iter()
+                    .map(|filter| filter.path)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(filters, [vec!["set/#"], vec!["status/#"]]);
+
+        smarthome.unsubscribe("set/#").await;
+        assert!(smarthome.prioritized().is_empty());
+    }
+
+    #[tokio::test]
+    async fn high_priority_is_flushed_first() {
+        let (smarthome, mut eventloop) = new();
+        let _set = smarthome
+            .subscribe_channel_priority("set/#", false, Priority::High)
+            .await;
+        smarthome.set_offline_buffer(Some(10));
+        for topic in ["status/a", "set/a", "status/b", "set/b"] {
+            smarthome.publish(topic, "1", false).await.unwrap();
+        }
+        requests(&mut eventloop);
+
+        smarthome.flush_offline_buffer().await;
+        let topics = requests(&mut eventloop)
+            .into_iter()
+            .filter_map(|request| match request {
+                Request::Publish(publish) => Some(publish.topic),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(topics, ["set/a", "set/b", "status/a", "status/b"]);
+    }
+
+    #[tokio::test]
+    async fn high_priority_is_replayed_first() {
+        let (smarthome, _eventloop) = new();
+        let _set = smarthome
+            .subscribe_channel_priority("set/#", false, Priority::High)
+            .await;
+        for topic in ["status/a", "set/a", "status/b"] {
+            handle_incoming(&smarthome, topic.to_owned(), "1".to_owned(), true).await;
+        }
+        let mut replay = smarthome.subscribe_channel_replay("#", false).await;
+        let mut topics = Vec::new();
+        while let Ok((topic, _)) = replay.try_recv() {
+            topics.push(topic);
+        }
+        assert_eq!(topics, ["set/a", "status/a", "status/b"]);
+    }
+}
@@ -0,0 +1,201 @@
+//! Types of the MQTT protocol version in use.
+//!
+//! MQTT 5 is used with the `v5` feature, MQTT 3.1.1 otherwise.
+//! Everything else of this crate is independent of the protocol version.
+//! Reason codes of acknowledgements are part of the [raw events](crate::MqttSmarthome::raw_events).
+
+#[cfg(feature = "v5")]
+pub(crate) use rumqttc::v5::mqttbytes::v5::Filter as SubscribeFilter;
+#[cfg(feature = "v5")]
+pub use rumqttc::v5::mqttbytes::v5::{
+    LastWill, Packet, Publish, PublishProperties, SubAck, SubscribeReasonCode,
+};
+#[cfg(feature = "v5")]
+pub use rumqttc::v5::mqttbytes::QoS;
+#[cfg(feature = "v5")]
+pub use rumqttc::v5::{
+    AsyncClient, ClientError, ConnectionError, Event, EventLoop, MqttOptions, Request,
+};
+#[cfg(not(feature = "v5"))]
+pub(crate) use rumqttc::SubscribeFilter;
+#[cfg(not(feature = "v5"))]
+pub use rumqttc::{
+    AsyncClient, ClientError, ConnectionError, Event, EventLoop, LastWill, MqttOptions, Packet,
+    Publish, QoS, Request, SubAck, SubscribeReasonCode,
+};
+
+use crate::ConnectionFailure;
+
+pub(crate) fn last_will(topic: &str, payload: &str, qos: QoS, retain: bool) -> LastWill {
+    #[cfg(not(feature = "v5"))]
+    return LastWill::new(topic, payload, qos, retain);
+    #[cfg(feature = "v5")]
+    return LastWill::new(topic, payload, qos, retain, None);
+}
+
+/// Keep the session on the broker while the client is disconnected.
+///
+/// The broker then queues `QoS` 1 and 2 messages of the subscriptions until the client reconnects and no messages are missed during a restart.
+/// When the broker resumed the session the subscriptions are not sent again, see [`ConnectionEvent::Connected`](crate::ConnectionEvent::Connected).
+///
+/// The broker identifies the session by the client id so it has to stay the same across restarts.
+/// Brokers limit how many messages they queue per session and might drop sessions on their restart unless they persist them (like `persistence true` on Mosquitto).
+/// MQTT 3.1.1 sessions never expire by the protocol, Mosquitto removes them after `persistent_client_expiration`.
+/// With MQTT 5 the session is requested to never expire, the broker might limit this with its maximum session expiry interval.
+///
+/// # Panics
+/// Panics with MQTT 3.1.1 when `persistent` is true and the client id is empty.
+pub fn set_persistent_session(mqttoptions: &mut MqttOptions, persistent: bool) {
+    #[cfg(not(feature = "v5"))]
+    mqttoptions.set_clean_session(!persistent);
+    #[cfg(feature = "v5")]
+    {
+        mqttoptions.set_clean_start(!persistent);
+        let mut properties = mqttoptions.connect_properties().unwrap_or_default();
+        properties.session_expiry_interval = persistent.then_some(u32::MAX);
+        mqttoptions.set_connect_properties(properties);
+    }
+}
+
+/// Classify the error of a connection attempt.
+pub(crate) const fn connection_failure(error: &ConnectionError) -> ConnectionFailure {
+    #[cfg(feature = "v5")]
+    use rumqttc::v5::mqttbytes::v5::ConnectReturnCode;
+    #[cfg(not(feature = "v5"))]
+    use rumqttc::ConnectReturnCode;
+
+    match error {
+        #[cfg(not(feature = "v5"))]
+        ConnectionError::ConnectionRefused(
+            ConnectReturnCode::BadUserNamePassword | ConnectReturnCode::NotAuthorized,
+        ) => ConnectionFailure::AuthenticationFailed,
+        #[cfg(feature = "v5")]
+        ConnectionError::ConnectionRefused(
+            ConnectReturnCode::BadUserNamePassword
+            | ConnectReturnCode::NotAuthorized
+            | ConnectReturnCode::Banned
+            | ConnectReturnCode::BadAuthenticationMethod,
+        ) => ConnectionFailure::AuthenticationFailed,
+        ConnectionError::ConnectionRefused(_) => ConnectionFailure::Refused,
+        #[cfg(not(feature = "v5"))]
+        ConnectionError::NetworkTimeout | ConnectionError::FlushTimeout => {
+            ConnectionFailure::Network
+        }
+        #[cfg(feature = "v5")]
+        ConnectionError::Timeout(_) => ConnectionFailure::Network,
+        #[cfg(feature = "tls")]
+        ConnectionError::Tls(_) => ConnectionFailure::Network,
+        ConnectionError::Io(_) => ConnectionFailure::Network,
+        _ => ConnectionFailure::Other,
+    }
+}
+
+/// Topic of an incoming publish. MQTT 5 allows topics which are not valid UTF-8.
+pub(crate) fn publish_topic(publish: &Publish) -> String {
+    #[cfg(not(feature = "v5"))]
+    return publish.topic.clone();
+    #[cfg(feature = "v5")]
+    return String::from_utf8_lossy(&publish.topic).into_owned();
+}
+
+/// User properties of an incoming publish. Always empty for MQTT 3.1.1.
+#[cfg_attr(not(feature = "v5"), allow(clippy::missing_const_for_fn))]
+pub(crate) fn user_properties(publish: &Publish) -> Vec<(String, String)> {
+    #[cfg(not(feature = "v5"))]
+    {
+        _ = publish;
+        Vec::new()
+    }
+    #[cfg(feature = "v5")]
+    publish
+        .properties
+        .as_ref()
+        .map(|properties| properties.user_properties.clone())
+        .unwrap_or_default()
+}
+
+/// Reason of the broker not accepting an own publish. MQTT 3.1.1 has no such reasons.
+#[cfg_attr(not(feature = "v5"), allow(clippy::missing_const_for_fn))]
+pub(crate) fn publish_failure(acknowledgement: &Packet) -> Option<String> {
+    #[cfg(not(feature = "v5"))]
+    {
+        _ = acknowledgement;
+        None
+    }
+    #[cfg(feature = "v5")]
+    {
+        use rumqttc::v5::mqttbytes::v5::{PubAckReason, PubCompReason, PubRecReason};
+        let (pkid, reason) = match acknowledgement {
+            Packet::PubAck(puback)
+                if !matches!(
+                    puback.reason,
+                    PubAckReason::Success | PubAckReason::NoMatchingSubscribers
+                ) =>
+            {
+                (puback.pkid, format!("{:?}", puback.reason))
+            }
+            Packet::PubRec(pubrec)
+                if !matches!(
+                    pubrec.reason,
+                    PubRecReason::Success | PubRecReason::NoMatchingSubscribers
+                ) =>
+            {
+                (pubrec.pkid, format!("{:?}", pubrec.reason))
+            }
+            Packet::PubComp(pubcomp) if pubcomp.reason != PubCompReason::Success => {
+                (pubcomp.pkid, format!("{:?}", pubcomp.reason))
+            }
+            _ => return None,
+        };
+        Some(format!("{reason} (packet id {pkid})"))
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn publish(topic: &str, qos: QoS, payload: &[u8], retain: bool) -> Publish {
+    #[cfg(not(feature = "v5"))]
+    let mut publish = Publish::new(topic, qos, payload.to_vec());
+    #[cfg(feature = "v5")]
+    let mut publish = Publish::new(topic, qos, payload.to_vec(), None);
+    publish.retain = retain;
+    publish
+}
+
+#[cfg(test)]
+pub(crate) fn publish_request(
+    topic: &str,
+    qos: QoS,
+    payload: &'static str,
+    retain: bool,
+) -> Request {
+    Request::Publish(publish(topic, qos, payload.as_bytes(), retain))
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "v5", allow(clippy::missing_const_for_fn))]
+pub(crate) fn suback(pkid: u16, return_codes: Vec<SubscribeReasonCode>) -> SubAck {
+    #[cfg(not(feature = "v5"))]
+    return SubAck::new(pkid, return_codes);
+    #[cfg(feature = "v5")]
+    return SubAck {
+        pkid,
+        return_codes,
+        properties: None,
+    };
+}
+
+#[cfg(test)]
+pub(crate) fn configured_last_will(eventloop: &EventLoop) -> Option<LastWill> {
+    #[cfg(not(feature = "v5"))]
+    return eventloop.mqtt_options.last_will();
+    #[cfg(feature = "v5")]
+    return eventloop.options.last_will();
+}
+
+#[cfg(test)]
+pub(crate) const fn disconnect_request() -> Request {
+    #[cfg(not(feature = "v5"))]
+    return Request::Disconnect(rumqttc::Disconnect);
+    #[cfg(feature = "v5")]
+    return Request::Disconnect;
+}
@@ -0,0 +1,74 @@
+use core::time::Duration;
+
+use tokio::time::Instant;
+
+/// Token bucket allowing bursts up to the amount of messages per second.
+#[derive(Debug)]
+pub struct TokenBucket {
+    per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(per_second: f64) -> Self {
+        Self {
+            per_second,
+            tokens: per_second.max(1.0),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Take a token and return how long to wait until it is available.
+    ///
+    /// Tokens are reserved even when they are not yet available so concurrent callers are served in order.
+    pub fn acquire(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.last_refill = now;
+        self.tokens = elapsed
+            .as_secs_f64()
+            .mul_add(self.per_second, self.tokens)
+            .min(self.per_second.max(1.0));
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_wait() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2.0);
+        assert_eq!(bucket.acquire(now), Duration::ZERO);
+        assert_eq!(bucket.acquire(now), Duration::ZERO);
+        assert_eq!(bucket.acquire(now), Duration::from_millis(500));
+        assert_eq!(bucket.acquire(now), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2.0);
+        bucket.acquire(now);
+        bucket.acquire(now);
+        let later = now + Duration::from_secs(10);
+        assert_eq!(bucket.acquire(later), Duration::ZERO);
+        assert_eq!(bucket.acquire(later), Duration::ZERO);
+        assert_eq!(bucket.acquire(later), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn slower_than_one_per_second() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(0.5);
+        assert_eq!(bucket.acquire(now), Duration::ZERO);
+        assert_eq!(bucket.acquire(now), Duration::from_secs(2));
+    }
+}
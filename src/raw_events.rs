@@ -0,0 +1,85 @@
+use std::sync::PoisonError;
+
+use crate::protocol::Event;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::MqttSmarthome;
+
+/// Capacity of the channels returned by [`raw_events`](crate::MqttSmarthome::raw_events)
+pub const RAW_EVENTS_CAPACITY: usize = 100;
+
+impl MqttSmarthome {
+    /// Every event of the underlying MQTT eventloop, for example to debug protocol issues.
+    ///
+    /// The channel holds up to [`RAW_EVENTS_CAPACITY`] events.
+    /// Events are skipped while it is full so a slow receiver does not stall the eventloop.
+    #[must_use]
+    pub fn raw_events(&self) -> Receiver<Event> {
+        let (sender, receiver) = channel(RAW_EVENTS_CAPACITY);
+        self.raw_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    /// Hand the event to the raw event receivers. Does nothing when there are none.
+    pub(crate) fn forward_raw_event(&self, event: &Event) {
+        let mut senders = self
+            .raw_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if senders.is_empty() {
+            return;
+        }
+        senders.retain(|sender| {
+            !matches!(sender.try_send(event.clone()), Err(TrySendError::Closed(_)))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rumqttc::Outgoing;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn events_are_forwarded() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.raw_events();
+        smarthome.forward_raw_event(&Event::Outgoing(Outgoing::PingResp));
+        smarthome.forward_raw_event(&Event::Outgoing(Outgoing::PingReq));
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::Outgoing(Outgoing::PingResp))
+        ));
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(Event::Outgoing(Outgoing::PingReq))
+        ));
+    }
+
+    #[tokio::test]
+    async fn full_receiver_skips_events() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.raw_events();
+        for _ in 0..=RAW_EVENTS_CAPACITY {
+            smarthome.forward_raw_event(&Event::Outgoing(Outgoing::PingResp));
+        }
+        let mut count = 0;
+        while receiver.try_recv().is_ok() {
+            count += 1;
+        }
+        assert_eq!(count, RAW_EVENTS_CAPACITY);
+    }
+
+    #[tokio::test]
+    async fn dropped_receiver_is_removed() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        drop(smarthome.raw_events());
+        smarthome.forward_raw_event(&Event::Outgoing(Outgoing::PingResp));
+        assert!(smarthome.raw_events.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::atomic::Ordering;
+use std::sync::PoisonError;
+
+use crate::connection_events::Link;
+use crate::protocol::{self, ConnectionError};
+use crate::{logging, ConnectionEvent, ConnectionFailure, MqttSmarthome};
+
+impl MqttSmarthome {
+    /// Stop reconnecting after `max_attempts` failed connection attempts in a row. `None` retries forever, which is the default.
+    ///
+    /// Giving up is final: the eventloop stops, every channel of this client is closed after a [`ConnectionEvent::GaveUp`] and publishing or subscribing fails from then on.
+    pub fn set_max_reconnect_attempts(&self, max_attempts: Option<u32>) {
+        *self
+            .max_reconnect_attempts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = max_attempts;
+    }
+
+    /// Whether the client stopped reconnecting, see [`set_max_reconnect_attempts`](Self::set_max_reconnect_attempts).
+    #[must_use]
+    pub fn has_given_up(&self) -> bool {
+        self.gave_up.load(Ordering::Relaxed)
+    }
+
+    /// Log the failed `request` once the client [gave up](Self::has_given_up).
+    ///
+    /// # Panics
+    /// Panics when the client did not give up as the eventloop is gone unexpectedly.
+    pub(crate) fn request_failed(&self, request: &str, error: &dyn core::fmt::Display) {
+        assert!(self.has_given_up(), "failed to {request}: {error}");
+        logging::warning!(client_id = self.client_id.as_str(); "MQTT gave up, failed to {request}: {error}");
+    }
+
+    /// Handle the failed connection attempt. Returns whether to keep reconnecting.
+    ///
+    /// Authentication failures are not retried as they only repeat and might get the client banned by the broker.
+    pub(crate) async fn connection_failed(
+        &self,
+        error: &ConnectionError,
+        failed_attempts: u32,
+    ) -> bool {
+        logging::status_warning!(client_id = self.client_id.as_str(); "MQTT Connection Error: {error}");
+        self.subscribes_lost();
+        if self.connected.swap(false, Ordering::Relaxed) {
+            self.link.send_replace(Link::Connecting);
+            self.forward_connection_event(ConnectionEvent::Disconnected);
+        }
+        let failure = protocol::connection_failure(error);
+        if failure == ConnectionFailure::AuthenticationFailed {
+            logging::status_warning!(client_id = self.client_id.as_str(); "MQTT giving up as the authentication failed");
+            self.give_up(ConnectionEvent::AuthenticationFailed, failure)
+                .await;
+            return false;
+        }
+        if self.reconnect_attempts_exhausted(failed_attempts) {
+            logging::status_warning!(client_id = self.client_id.as_str(); "MQTT giving up after {failed_attempts} failed connection attempts");
+            self.give_up(ConnectionEvent::GaveUp, failure).await;
+            return false;
+        }
+        true
+    }
+
+    fn reconnect_attempts_exhausted(&self, failed_attempts: u32) -> bool {
+        self.max_reconnect_attempts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some_and(|max_attempts| failed_attempts >= max_attempts)
+    }
+
+    /// Mark the client as permanently failed and close every channel.
+    ///
+    /// Requests fail once the eventloop is dropped.
+    async fn give_up(&self, event: ConnectionEvent, failure: ConnectionFailure) {
+        self.gave_up.store(true, Ordering::Relaxed);
+        self.link.send_replace(Link::Failed(failure));
+        self.forward_connection_event(event);
+        self.close_channels().await;
+    }
+
+    /// Drop the senders of every channel handed out so their receivers end.
+    pub(crate) async fn close_channels(&self) {
+        self.watchers.write().await.clear();
+        self.manual_ack_watchers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.taps
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.raw_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.connection_events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.dry_run_publishes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::protocol::{MqttOptions, QoS};
+
+    use super::*;
+    use crate::{LastWillConfig, PublishError};
+
+    #[cfg(feature = "v5")]
+    use rumqttc::v5::mqttbytes::v5::ConnectReturnCode;
+    #[cfg(not(feature = "v5"))]
+    use rumqttc::ConnectReturnCode;
+
+    fn network_error() -> ConnectionError {
+        ConnectionError::Io(io::Error::from(io::ErrorKind::ConnectionRefused))
+    }
+
+    #[tokio::test]
+    async fn retries_forever_by_default() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        assert!(
+            smarthome
+                .connection_failed(&network_error(), u32::MAX)
+                .await
+        );
+        smarthome.set_max_reconnect_attempts(Some(3));
+        assert!(smarthome.connection_failed(&network_error(), 2).await);
+        assert!(!smarthome.has_given_up());
+        assert!(!smarthome.connection_failed(&network_error(), 3).await);
+        assert!(smarthome.has_given_up());
+        assert_eq!(
+            smarthome.await_connected().await,
+            Err(ConnectionFailure::Network)
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::bad_credentials(
+        ConnectReturnCode::BadUserNamePassword,
+        ConnectionFailure::AuthenticationFailed
+    )]
+    #[case::not_authorized(
+        ConnectReturnCode::NotAuthorized,
+        ConnectionFailure::AuthenticationFailed
+    )]
+    #[case::refused(ConnectReturnCode::ServiceUnavailable, ConnectionFailure::Refused)]
+    fn refused_is_classified(#[case] code: ConnectReturnCode, #[case] expected: ConnectionFailure) {
+        let error = ConnectionError::ConnectionRefused(code);
+        assert_eq!(protocol::connection_failure(&error), expected);
+    }
+
+    #[tokio::test]
+    async fn authentication_failure_is_final() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut events = smarthome.connection_events();
+        let error = ConnectionError::ConnectionRefused(ConnectReturnCode::BadUserNamePassword);
+        assert!(!smarthome.connection_failed(&error, 1).await);
+        assert_eq!(
+            smarthome.await_connected().await,
+            Err(ConnectionFailure::AuthenticationFailed)
+        );
+        assert_eq!(
+            events.recv().await,
+            Some(ConnectionEvent::AuthenticationFailed)
+        );
+        assert_eq!(events.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn giving_up_closes_everything() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.set_offline_buffer(Some(10));
+        smarthome.set_max_reconnect_attempts(Some(1));
+        let mut watcher = smarthome.watch("#", true).await;
+        let mut tap = smarthome.tap();
+        let mut events = smarthome.connection_events();
+
+        assert!(!smarthome.connection_failed(&network_error(), 1).await);
+        drop(eventloop);
+
+        assert_eq!(watcher.recv().await, None);
+        assert_eq!(tap.recv().await, None);
+        assert_eq!(events.recv().await, Some(ConnectionEvent::GaveUp));
+        assert_eq!(events.recv().await, None);
+        assert!(matches!(
+            smarthome.publish("foo", "1", false).await,
+            Err(PublishError::Client(_))
+        ));
+        assert_eq!(smarthome.offline_buffered(), 0);
+        smarthome.subscribe("foo").await;
+        smarthome.subscribe_qos("bar/+", QoS::ExactlyOnce).await;
+        smarthome.unsubscribe("foo").await;
+        smarthome.spawn_initialization(false).await.unwrap();
+    }
+}
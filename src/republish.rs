@@ -0,0 +1,159 @@
+use core::time::Duration;
+
+use crate::protocol::QoS;
+use tokio::task::{self, AbortHandle};
+use tokio::time::sleep;
+
+use crate::MqttSmarthome;
+
+/// Handle of a topic being republished periodically.
+///
+/// Created by [`republish_periodically`](crate::MqttSmarthome::republish_periodically).
+/// Dropping the handle does not stop republishing.
+#[derive(Debug)]
+pub struct Republishing {
+    task: AbortHandle,
+}
+
+impl Republishing {
+    /// Stop republishing the topic.
+    pub fn cancel(&self) {
+        self.task.abort();
+    }
+
+    /// Whether the topic is still republished.
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+}
+
+impl MqttSmarthome {
+    /// Publish the last payload of the `topic` from the history retained every `interval`.
+    ///
+    /// The interval starts again whenever the topic is updated in the history, so this pairs nicely with [`publish_if_changed`](crate::MqttSmarthome::publish_if_changed).
+    /// Nothing is published while the topic has no entry in the history.
+    /// Republishing stops when cancelled, when the client [gave up](crate::MqttSmarthome::has_given_up) or when the MQTT eventloop is gone, for example after [`close`](crate::MqttSmarthome::close).
+    #[must_use]
+    pub fn republish_periodically(&self, topic: &str, interval: Duration) -> Republishing {
+        let smarthome = self.clone();
+        let topic = topic.to_owned();
+        let task = task::spawn(async move {
+            let mut wait = interval;
+            loop {
+                tokio::select! {
+                    () = sleep(wait) => {}
+                    () = smarthome.wait_given_up() => break,
+                }
+                wait = interval;
+                let Some(last) = smarthome.last(&topic).await else {
+                    continue;
+                };
+                if let Some(remaining) = interval.checked_sub(last.age_at(smarthome.now())) {
+                    if !remaining.is_zero() {
+                        wait = remaining;
+                        continue;
+                    }
+                }
+                let payload = last.payload_bytes().to_vec();
+                if smarthome.dry_run_publish(&topic, &payload, true).await {
+                    continue;
+                }
+                smarthome.throttle().await;
+                if smarthome
+                    .client
+                    .publish(&topic, QoS::AtLeastOnce, true, payload.clone())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                smarthome.record_published(&topic, &payload, true).await;
+            }
+        });
+        Republishing {
+            task: task.abort_handle(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use tokio::time::advance;
+
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_mins(5);
+
+    async fn settle() {
+        for _ in 0..10 {
+            task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn republishes_old_entry() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .history
+            .insert_at("foo", "1", SystemTime::now() - Duration::from_hours(1));
+        let republishing = smarthome.republish_periodically("foo", INTERVAL);
+        settle().await;
+        advance(INTERVAL).await;
+        settle().await;
+        assert_eq!(smarthome.metrics().messages_published, 1);
+        let last = smarthome.last("foo").await.unwrap();
+        assert_eq!(last.payload(), "1");
+        assert!(last.ago() < INTERVAL);
+        assert!(republishing.is_running());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_without_entry() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let _republishing = smarthome.republish_periodically("foo", INTERVAL);
+        settle().await;
+        advance(INTERVAL).await;
+        settle().await;
+        assert_eq!(smarthome.metrics().messages_published, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_recent_entry() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.publish("foo", 1, true).await.unwrap();
+        let _republishing = smarthome.republish_periodically("foo", INTERVAL);
+        settle().await;
+        advance(INTERVAL).await;
+        settle().await;
+        assert_eq!(smarthome.metrics().messages_published, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_stops() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome
+            .history
+            .insert_at("foo", "1", SystemTime::now() - Duration::from_hours(1));
+        let republishing = smarthome.republish_periodically("foo", INTERVAL);
+        republishing.cancel();
+        settle().await;
+        advance(INTERVAL).await;
+        settle().await;
+        assert_eq!(smarthome.metrics().messages_published, 0);
+        assert!(!republishing.is_running());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn giving_up_stops() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let republishing = smarthome.republish_periodically("foo", INTERVAL);
+        smarthome.set_max_reconnect_attempts(Some(1));
+        let error = crate::protocol::ConnectionError::Io(std::io::ErrorKind::Other.into());
+        smarthome.connection_failed(&error, 1).await;
+        settle().await;
+        assert!(!republishing.is_running());
+    }
+}
@@ -0,0 +1,179 @@
+use core::time::Duration;
+use std::sync::PoisonError;
+
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::task;
+use tokio::time::sleep;
+
+use crate::logging::warning;
+use crate::{IntoPayload, MqttSmarthome};
+
+#[derive(Debug)]
+enum Action {
+    Cancel,
+    FireNow,
+}
+
+/// Handle of a publish waiting for its delay to pass.
+///
+/// Created by [`publish_after`](crate::MqttSmarthome::publish_after).
+/// Dropping the handle does not cancel the publish.
+#[derive(Debug, Clone)]
+pub struct ScheduledPublish {
+    actions: Sender<Action>,
+}
+
+impl ScheduledPublish {
+    /// Do not publish. Has no effect when it was already published or cancelled.
+    pub fn cancel(&self) {
+        _ = self.actions.try_send(Action::Cancel);
+    }
+
+    /// Publish right away instead of waiting for the delay. Has no effect when it was already published or cancelled.
+    pub fn fire_now(&self) {
+        _ = self.actions.try_send(Action::FireNow);
+    }
+
+    /// Whether the publish is still waiting to happen.
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        !self.actions.is_closed()
+    }
+}
+
+impl MqttSmarthome {
+    /// Publish a `payload` to a MQTT `topic` once the `delay` passed.
+    ///
+    /// The returned handle can cancel the publish or do it right away.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn publish_after<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        delay: Duration,
+    ) -> ScheduledPublish
+    where
+        P: IntoPayload,
+    {
+        let (actions, mut receiver) = channel(1);
+        let smarthome = self.clone();
+        let topic = topic.to_owned();
+        let payload = payload.into_payload();
+        let delay = sleep(delay);
+        task::spawn(async move {
+            tokio::pin!(delay);
+            tokio::select! {
+                () = &mut delay => {}
+                action = receiver.recv() => match action {
+                    Some(Action::Cancel) => return,
+                    Some(Action::FireNow) => {}
+                    // Every handle is gone, nothing can interrupt the delay anymore
+                    None => delay.await,
+                },
+            }
+            drop(receiver);
+            if let Err(error) = smarthome.publish(&topic, payload, retain).await {
+                warning!(topic = topic.as_str(); "MQTT scheduled publish failed: {error}");
+            }
+        });
+        ScheduledPublish { actions }
+    }
+
+    /// Same as [`publish_after`](crate::MqttSmarthome::publish_after) but cancels the pending publish of the same `topic` scheduled by this method.
+    ///
+    /// This is useful for timeouts like turning off a light some time after the last motion was detected.
+    #[allow(clippy::needless_pass_by_value)]
+    pub fn publish_after_replacing<P>(
+        &self,
+        topic: &str,
+        payload: P,
+        retain: bool,
+        delay: Duration,
+    ) -> ScheduledPublish
+    where
+        P: IntoPayload,
+    {
+        let scheduled = self.publish_after(topic, payload, retain, delay);
+        let mut pending = self
+            .scheduled
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        pending.retain(|_, scheduled| scheduled.is_pending());
+        if let Some(previous) = pending.insert(topic.to_owned(), scheduled.clone()) {
+            previous.cancel();
+        }
+        drop(pending);
+        scheduled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::advance;
+
+    use super::*;
+
+    async fn settle() {
+        for _ in 0..10 {
+            task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn publishes_after_delay() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let scheduled = smarthome.publish_after("foo", "off", false, Duration::from_mins(10));
+        settle().await;
+        assert!(smarthome.last("foo").await.is_none());
+        assert!(scheduled.is_pending());
+
+        advance(Duration::from_mins(10)).await;
+        settle().await;
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "off");
+        assert!(!scheduled.is_pending());
+        scheduled.cancel();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_prevents_publish() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let scheduled = smarthome.publish_after("foo", "off", false, Duration::from_mins(10));
+        scheduled.cancel();
+        advance(Duration::from_mins(10)).await;
+        settle().await;
+        assert!(smarthome.last("foo").await.is_none());
+        assert!(!scheduled.is_pending());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fire_now_skips_delay() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let scheduled = smarthome.publish_after("foo", "off", false, Duration::from_mins(10));
+        scheduled.fire_now();
+        settle().await;
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "off");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_handle_still_publishes() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        drop(smarthome.publish_after("foo", "off", false, Duration::from_secs(1)));
+        advance(Duration::from_secs(1)).await;
+        settle().await;
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "off");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn replacing_cancels_previous() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let first = smarthome.publish_after_replacing("foo", "1", false, Duration::from_secs(1));
+        let _second = smarthome.publish_after_replacing("foo", "2", false, Duration::from_secs(2));
+        settle().await;
+        assert!(!first.is_pending());
+        advance(Duration::from_secs(2)).await;
+        settle().await;
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "2");
+        assert_eq!(smarthome.metrics().messages_published, 1);
+    }
+}
@@ -0,0 +1,106 @@
+use core::future::Future;
+use core::pin::Pin;
+
+use bytes::Bytes;
+use tokio::sync::mpsc::Receiver;
+
+use crate::watcher::ChannelPayload;
+use crate::{payload, HistoryEntry, MqttSmarthome, PublishError};
+
+/// Future returned by the [`SmarthomeClient`] methods.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The commonly used methods of [`MqttSmarthome`] so applications can swap it for a fake in tests.
+///
+/// The methods return boxed futures so the trait can be used as `Arc<dyn SmarthomeClient>`.
+/// See [`MqttSmarthome`] for the documentation of each method.
+pub trait SmarthomeClient: Send + Sync {
+    /// See [`MqttSmarthome::publish`].
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        payload: Bytes,
+        retain: bool,
+    ) -> BoxFuture<'a, Result<(), PublishError>>;
+
+    /// See [`MqttSmarthome::subscribe_and_watch`].
+    fn subscribe_channel<'a>(
+        &'a self,
+        topic: &'a str,
+        allow_retained: bool,
+    ) -> BoxFuture<'a, Receiver<ChannelPayload>>;
+
+    /// See [`MqttSmarthome::last`].
+    fn last<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, Option<HistoryEntry>>;
+
+    /// See [`MqttSmarthome::last_float`].
+    fn last_float<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, Option<f32>> {
+        Box::pin(async move { self.last(topic).await?.as_float() })
+    }
+
+    /// See [`MqttSmarthome::last_is_true`].
+    fn last_is_true<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            self.last(topic)
+                .await
+                .is_some_and(|entry| payload::is_true(&entry.payload()))
+        })
+    }
+}
+
+impl SmarthomeClient for MqttSmarthome {
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        payload: Bytes,
+        retain: bool,
+    ) -> BoxFuture<'a, Result<(), PublishError>> {
+        Box::pin(Self::publish(self, topic, payload, retain))
+    }
+
+    fn subscribe_channel<'a>(
+        &'a self,
+        topic: &'a str,
+        allow_retained: bool,
+    ) -> BoxFuture<'a, Receiver<ChannelPayload>> {
+        Box::pin(self.subscribe_and_watch(topic, allow_retained))
+    }
+
+    fn last<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, Option<HistoryEntry>> {
+        Box::pin(Self::last(self, topic))
+    }
+
+    fn last_float<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, Option<f32>> {
+        Box::pin(Self::last_float(self, topic))
+    }
+
+    fn last_is_true<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(Self::last_is_true(self, topic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::handle_incoming;
+
+    #[tokio::test]
+    async fn usable_as_trait_object() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let client: Arc<dyn SmarthomeClient> = Arc::new(smarthome.clone());
+        handle_incoming(&smarthome, "temp".to_owned(), "21.5".to_owned(), false).await;
+        handle_incoming(&smarthome, "switch".to_owned(), "on".to_owned(), false).await;
+        assert_eq!(client.last("temp").await.unwrap().payload(), "21.5");
+        assert_eq!(client.last_float("temp").await, Some(21.5));
+        assert!(client.last_is_true("switch").await);
+        assert!(!client.last_is_true("unknown").await);
+
+        client
+            .publish("temp", Bytes::from_static(b"22"), false)
+            .await
+            .unwrap();
+        assert_eq!(smarthome.last("temp").await.unwrap().payload(), "22");
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use tokio::sync::oneshot;
+
+use crate::protocol::{ClientError, QoS, SubAck, SubscribeFilter, SubscribeReasonCode};
+use crate::subscriptions::Added;
+use crate::{logging, MqttSmarthome, SubscribeError};
+
+type Waiter = oneshot::Sender<Result<(), SubscribeError>>;
+
+/// Subscriptions not yet acknowledged by the broker.
+///
+/// The packet id is only known once the eventloop sent the subscription.
+/// This happens in the order the requests were queued, so the queued requests are assigned to the packet ids in order.
+/// Subscriptions made directly on the [`client`](MqttSmarthome::client) mix this up.
+#[derive(Default)]
+pub struct SubscribeAcks {
+    /// Ensures the requests are queued in the same order as the filters
+    order: tokio::sync::Mutex<()>,
+    pending: Mutex<Pending>,
+}
+
+#[derive(Default)]
+struct Pending {
+    /// Filters of a single subscribe request
+    queued: VecDeque<(Vec<String>, Option<Waiter>)>,
+    sent: HashMap<u16, (Vec<String>, Option<Waiter>)>,
+}
+
+impl SubscribeAcks {
+    fn pending(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl MqttSmarthome {
+    /// Subscribe to the `topic` and wait until the broker acknowledged it.
+    ///
+    /// Rejected subscriptions are not remembered so they can be retried.
+    /// While disconnected this waits for the next connection.
+    /// Like [`subscribe`](Self::subscribe) this returns immediately when the `topic` is already subscribed
+    /// and unsubscribes filters covered by the `topic` once it is acknowledged.
+    ///
+    /// # Errors
+    /// Returns an error when the broker rejected the subscription, the connection was lost before the acknowledgement or the MQTT eventloop is gone.
+    pub async fn subscribe_confirmed(&self, topic: &str) -> Result<(), SubscribeError> {
+        self.subscribe_qos_confirmed(topic, QoS::AtLeastOnce).await
+    }
+
+    /// Same as [`subscribe_confirmed`](Self::subscribe_confirmed) with the given `qos`, see [`subscribe_qos`](Self::subscribe_qos).
+    ///
+    /// # Errors
+    /// See [`subscribe_confirmed`](Self::subscribe_confirmed).
+    pub async fn subscribe_qos_confirmed(
+        &self,
+        topic: &str,
+        qos: QoS,
+    ) -> Result<(), SubscribeError> {
+        let Added::New { superseded } = self.subscribed.write().await.add(topic, qos) else {
+            return Ok(());
+        };
+        let (sender, receiver) = oneshot::channel();
+        if let Err(error) = self
+            .send_subscribe(vec![(topic.to_owned(), qos)], Some(sender))
+            .await
+        {
+            {
+                let mut subscribed = self.subscribed.write().await;
+                subscribed.forget(topic);
+                for filter in superseded {
+                    subscribed.restore(filter);
+                }
+            }
+            return Err(error.into());
+        }
+        let acknowledged = receiver
+            .await
+            .unwrap_or(Err(SubscribeError::ConnectionLost));
+        self.unsubscribe_superseded(superseded, &acknowledged).await;
+        acknowledged
+    }
+
+    /// Subscribe to all `filters` with a single request. Does nothing without `filters`.
+    pub(crate) async fn send_subscribe(
+        &self,
+        filters: Vec<(String, QoS)>,
+        waiter: Option<Waiter>,
+    ) -> Result<(), ClientError> {
+        if filters.is_empty() {
+            return Ok(());
+        }
+        let _order = self.subscribe_acks.order.lock().await;
+        let (filters, request) = filters
+            .into_iter()
+            .map(|(filter, qos)| (filter.clone(), SubscribeFilter::new(filter, qos)))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        self.subscribe_acks
+            .pending()
+            .queued
+            .push_back((filters, waiter));
+        let result = self.client.subscribe_many(request).await;
+        if result.is_err() {
+            self.subscribe_acks.pending().queued.pop_back();
+        }
+        result
+    }
+
+    /// The eventloop sent the next queued subscription with the `pkid`.
+    pub(crate) fn subscribe_sent(&self, pkid: u16) {
+        let mut pending = self.subscribe_acks.pending();
+        if let Some(subscription) = pending.queued.pop_front() {
+            pending.sent.insert(pkid, subscription);
+        }
+    }
+
+    /// Sent subscriptions are not acknowledged anymore once the connection is lost.
+    pub(crate) fn subscribes_lost(&self) {
+        self.subscribe_acks.pending().sent.clear();
+    }
+
+    pub(crate) async fn handle_suback(&self, suback: &SubAck) {
+        let Some((filters, waiter)) = self.subscribe_acks.pending().sent.remove(&suback.pkid)
+        else {
+            return;
+        };
+        let mut result = Ok(());
+        for (filter, code) in filters.into_iter().zip(&suback.return_codes) {
+            if matches!(code, SubscribeReasonCode::Success(_)) {
+                continue;
+            }
+            logging::status_warning!(client_id = self.client_id.as_str(), filter = filter.as_str(); "MQTT subscription to {filter} rejected: {code:?}");
+            self.subscribed.write().await.forget(&filter);
+            if result.is_ok() {
+                result = Err(SubscribeError::Rejected {
+                    filter,
+                    code: *code,
+                });
+            }
+        }
+        if let Some(waiter) = waiter {
+            _ = waiter.send(result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{self, MqttOptions, Request};
+    use crate::LastWillConfig;
+
+    fn suback(pkid: u16, code: SubscribeReasonCode) -> SubAck {
+        protocol::suback(pkid, vec![code])
+    }
+
+    #[cfg(not(feature = "v5"))]
+    const REJECTED: SubscribeReasonCode = SubscribeReasonCode::Failure;
+    #[cfg(feature = "v5")]
+    const REJECTED: SubscribeReasonCode = SubscribeReasonCode::NotAuthorized;
+
+    #[tokio::test]
+    async fn rejected_subscription_is_removed() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.subscribe("allowed/#").await;
+        smarthome.subscribe("forbidden/#").await;
+        smarthome.subscribe_sent(1);
+        smarthome.subscribe_sent(2);
+        smarthome
+            .handle_suback(&suback(1, SubscribeReasonCode::Success(QoS::AtLeastOnce)))
+            .await;
+        smarthome.handle_suback(&suback(2, REJECTED)).await;
+        assert_eq!(smarthome.subscriptions().await, ["allowed/#"]);
+    }
+
+    #[tokio::test]
+    async fn rejected_filter_of_many_is_removed() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        {
+            let mut subscribed = smarthome.subscribed.write().await;
+            subscribed.add("allowed/#", QoS::AtLeastOnce);
+            subscribed.add("forbidden/#", QoS::AtLeastOnce);
+        }
+        smarthome
+            .send_subscribe(
+                vec![
+                    ("allowed/#".to_owned(), QoS::AtLeastOnce),
+                    ("forbidden/#".to_owned(), QoS::AtLeastOnce),
+                ],
+                None,
+            )
+            .await
+            .unwrap();
+        smarthome.subscribe_sent(1);
+        let codes = vec![SubscribeReasonCode::Success(QoS::AtLeastOnce), REJECTED];
+        smarthome.handle_suback(&protocol::suback(1, codes)).await;
+        assert_eq!(smarthome.subscriptions().await, ["allowed/#"]);
+    }
+
+    #[tokio::test]
+    async fn confirmed_with_qos() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let confirmed = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move {
+                smarthome
+                    .subscribe_qos_confirmed("exact", QoS::ExactlyOnce)
+                    .await
+            }
+        });
+        while smarthome.subscribe_acks.pending().queued.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        smarthome.subscribe_sent(1);
+        smarthome
+            .handle_suback(&suback(1, SubscribeReasonCode::Success(QoS::ExactlyOnce)))
+            .await;
+        assert!(confirmed.await.unwrap().is_ok());
+        assert_eq!(
+            smarthome.subscribed.read().await.with_qos(),
+            [("exact".to_owned(), QoS::ExactlyOnce)]
+        );
+    }
+
+    #[tokio::test]
+    async fn confirmed_waits_for_ack() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let confirmed = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move {
+                (
+                    smarthome.subscribe_confirmed("allowed/#").await,
+                    smarthome.subscribe_confirmed("forbidden/#").await,
+                )
+            }
+        });
+        for (pkid, code) in [
+            (1, SubscribeReasonCode::Success(QoS::AtLeastOnce)),
+            (2, REJECTED),
+        ] {
+            while smarthome.subscribe_acks.pending().queued.is_empty() {
+                tokio::task::yield_now().await;
+            }
+            smarthome.subscribe_sent(pkid);
+            smarthome.handle_suback(&suback(pkid, code)).await;
+        }
+        let (allowed, forbidden) = confirmed.await.unwrap();
+        assert!(allowed.is_ok());
+        assert!(matches!(
+            forbidden,
+            Err(SubscribeError::Rejected { filter, code }) if filter == "forbidden/#" && code == REJECTED
+        ));
+        assert_eq!(smarthome.subscriptions().await, ["allowed/#"]);
+    }
+
+    #[tokio::test]
+    async fn lost_connection_fails_waiting() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let confirmed = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move { smarthome.subscribe_confirmed("foo").await }
+        });
+        while smarthome.subscribe_acks.pending().queued.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        smarthome.subscribe_sent(1);
+        smarthome.subscribes_lost();
+        assert!(matches!(
+            confirmed.await.unwrap(),
+            Err(SubscribeError::ConnectionLost)
+        ));
+        assert_eq!(smarthome.subscriptions().await, ["foo"]);
+    }
+
+    async fn subscribe_superseding(
+        code: SubscribeReasonCode,
+    ) -> (MqttSmarthome, usize, Result<(), SubscribeError>) {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.subscribe("foo/bar").await;
+        smarthome.subscribe("foo/baz").await;
+        smarthome.subscribe_sent(1);
+        smarthome.subscribe_sent(2);
+        let confirmed = tokio::spawn({
+            let smarthome = smarthome.clone();
+            async move { smarthome.subscribe_confirmed("foo/+").await }
+        });
+        while smarthome.subscribe_acks.pending().queued.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        eventloop.clean();
+        let unsubscribed_early = eventloop
+            .pending
+            .drain(..)
+            .filter(|request| matches!(request, Request::Unsubscribe(_)))
+            .count();
+        assert_eq!(unsubscribed_early, 0);
+
+        smarthome.subscribe_sent(3);
+        smarthome.handle_suback(&suback(3, code)).await;
+        let result = confirmed.await.unwrap();
+        eventloop.clean();
+        let unsubscribed = eventloop
+            .pending
+            .drain(..)
+            .filter(|request| matches!(request, Request::Unsubscribe(_)))
+            .count();
+        (smarthome, unsubscribed, result)
+    }
+
+    #[tokio::test]
+    async fn superseded_are_unsubscribed_after_ack() {
+        let (smarthome, unsubscribed, result) =
+            subscribe_superseding(SubscribeReasonCode::Success(QoS::AtLeastOnce)).await;
+        assert!(result.is_ok());
+        assert_eq!(unsubscribed, 2);
+        assert_eq!(smarthome.subscriptions().await, ["foo/+"]);
+    }
+
+    #[tokio::test]
+    async fn superseded_are_kept_when_rejected() {
+        let (smarthome, unsubscribed, result) = subscribe_superseding(REJECTED).await;
+        assert!(result.is_err());
+        assert_eq!(unsubscribed, 0);
+        assert_eq!(smarthome.subscriptions().await, ["foo/bar", "foo/baz"]);
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+
+use crate::protocol::QoS;
+use crate::{logging, topic_filter, MqttSmarthome, SubscribeError};
+
+/// Topic filters subscribed on the broker with their `QoS`.
+///
+/// Filters covered by a broader one with at least the same `QoS` are dropped as the broader filter already delivers their messages.
+/// The requested filters are remembered so removing the broader filter restores them.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    /// Every filter requested and not yet removed
+    requested: HashMap<String, QoS>,
+    /// Filters subscribed on the broker
+    effective: HashMap<String, QoS>,
+}
+
+/// Whether the `broader` subscription delivers every message of the `narrower` one with at least its `QoS`.
+fn covers((broader, broader_qos): (&str, QoS), (narrower, narrower_qos): (&str, QoS)) -> bool {
+    broader != narrower && broader_qos >= narrower_qos && topic_filter::covers(broader, narrower)
+}
+
+/// Outcome of [`Subscriptions::add`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Added {
+    /// The filter was already subscribed with at least the `QoS`, nothing changed.
+    Existing,
+    /// The filter is new or its `QoS` was raised and needs to be subscribed on the broker.
+    ///
+    /// The `superseded` filters are covered by it and were removed.
+    /// They should only be unsubscribed on the broker once the new filter is active.
+    New { superseded: Vec<String> },
+}
+
+/// Broker operations needed after [`Subscriptions::remove`].
+///
+/// Subscribe before unsubscribing so there is no gap without a subscription.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Removed {
+    pub resubscribe: Vec<(String, QoS)>,
+    pub unsubscribe: Vec<String>,
+}
+
+impl Subscriptions {
+    /// Add the `filter` or raise its `qos`. A lower `qos` than already requested keeps the higher one.
+    pub fn add(&mut self, filter: &str, qos: QoS) -> Added {
+        match self.requested.get_mut(filter) {
+            Some(requested) if *requested >= qos => return Added::Existing,
+            Some(requested) => *requested = qos,
+            None => {
+                self.requested.insert(filter.to_owned(), qos);
+            }
+        }
+        self.effective.insert(filter.to_owned(), qos);
+        let mut superseded = Vec::new();
+        self.effective.retain(|existing, existing_qos| {
+            let covered = covers((filter, qos), (existing, *existing_qos));
+            if covered {
+                superseded.push(existing.clone());
+            }
+            !covered
+        });
+        superseded.sort_unstable();
+        Added::New { superseded }
+    }
+
+    /// Subscribe the `filter` on the broker again without superseding other filters.
+    pub fn restore(&mut self, filter: String) {
+        if let Some(qos) = self.requested.get(&filter) {
+            self.effective.insert(filter, *qos);
+        }
+    }
+
+    /// Remove the requested `filter`.
+    ///
+    /// Requested filters which were only covered by it need to be subscribed again.
+    /// Of these only the ones not covered by each other are returned.
+    pub fn remove(&mut self, filter: &str) -> Removed {
+        if self.requested.remove(filter).is_none() {
+            return Removed::default();
+        }
+        let Some(removed_qos) = self.effective.remove(filter) else {
+            return Removed::default();
+        };
+        let uncovered = self
+            .requested
+            .iter()
+            .map(|(requested, qos)| (requested.as_str(), *qos))
+            .filter(|&requested| covers((filter, removed_qos), requested))
+            .filter(|&requested| {
+                !self.effective.contains_key(requested.0)
+                    && !self
+                        .effective
+                        .iter()
+                        .any(|(effective, qos)| covers((effective, *qos), requested))
+            })
+            .collect::<Vec<_>>();
+        let mut resubscribe = uncovered
+            .iter()
+            .filter(|&&narrower| !uncovered.iter().any(|&broader| covers(broader, narrower)))
+            .map(|&(filter, qos)| (filter.to_owned(), qos))
+            .collect::<Vec<_>>();
+        resubscribe.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        self.effective.extend(resubscribe.iter().cloned());
+        Removed {
+            resubscribe,
+            unsubscribe: vec![filter.to_owned()],
+        }
+    }
+
+    /// Forget the `filter` without restoring the filters covered by it as it was never active on the broker.
+    pub fn forget(&mut self, filter: &str) {
+        self.requested.remove(filter);
+        self.effective.remove(filter);
+    }
+
+    /// Whether the `filter` is subscribed on the broker.
+    pub fn contains(&self, filter: &str) -> bool {
+        self.effective.contains_key(filter)
+    }
+
+    pub fn len(&self) -> usize {
+        self.effective.len()
+    }
+
+    /// Filters subscribed on the broker.
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.effective.keys()
+    }
+
+    /// Filters subscribed on the broker with their `QoS` sorted by the filter.
+    pub fn with_qos(&self) -> Vec<(String, QoS)> {
+        let mut filters = self
+            .effective
+            .iter()
+            .map(|(filter, qos)| (filter.clone(), *qos))
+            .collect::<Vec<_>>();
+        filters.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        filters
+    }
+}
+
+impl MqttSmarthome {
+    /// Stop the subscription of the `topic` on the broker.
+    ///
+    /// Previously subscribed filters covered by the `topic` (like `foo/bar` by `foo/+`) are subscribed again.
+    /// Watchers of the `topic` stay registered but only get messages still covered by other subscriptions.
+    /// # Panics
+    /// Panics when the MQTT eventloop is gone unless the client [gave up](Self::has_given_up).
+    pub async fn unsubscribe(&self, topic: &str) {
+        let Removed {
+            resubscribe,
+            unsubscribe,
+        } = self.subscribed.write().await.remove(topic);
+        self.forget_priority(topic);
+        if let Err(error) = self.send_subscribe(resubscribe, None).await {
+            self.request_failed("subscribe", &error);
+            return;
+        }
+        for filter in unsubscribe {
+            if let Err(error) = self.client.unsubscribe(filter).await {
+                self.request_failed("unsubscribe", &error);
+                return;
+            }
+        }
+    }
+
+    /// Unsubscribe the `superseded` filters on the broker once the broader filter is `acknowledged`.
+    ///
+    /// When the broader filter was rejected the superseded filters are kept as they are still active on the broker.
+    pub(crate) async fn unsubscribe_superseded(
+        &self,
+        superseded: Vec<String>,
+        acknowledged: &Result<(), SubscribeError>,
+    ) {
+        match acknowledged {
+            Ok(()) => {
+                for filter in superseded {
+                    // Subscribed again while waiting for the acknowledgement, for example after unsubscribing the broader filter
+                    if self.subscribed.read().await.contains(&filter) {
+                        continue;
+                    }
+                    if let Err(err) = self.client.unsubscribe(filter.as_str()).await {
+                        logging::status_warning!(client_id = self.client_id.as_str(), filter = filter.as_str(); "MQTT failed to unsubscribe superseded {filter}: {err}");
+                    }
+                }
+            }
+            Err(SubscribeError::Rejected { .. }) => {
+                let mut subscribed = self.subscribed.write().await;
+                for filter in superseded {
+                    subscribed.restore(filter);
+                }
+            }
+            // Not subscribed anymore after a new connection without a persistent session
+            Err(SubscribeError::Client(_) | SubscribeError::ConnectionLost) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{MqttOptions, Request};
+    use crate::LastWillConfig;
+
+    fn added(superseded: &[&str]) -> Added {
+        Added::New {
+            superseded: superseded.iter().map(|&filter| filter.to_owned()).collect(),
+        }
+    }
+
+    fn sorted(subscriptions: &Subscriptions) -> Vec<&str> {
+        let mut filters = subscriptions.iter().map(String::as_str).collect::<Vec<_>>();
+        filters.sort_unstable();
+        filters
+    }
+
+    #[test]
+    fn add_existing() {
+        let mut subscriptions = Subscriptions::default();
+        assert_eq!(subscriptions.add("foo/bar", QoS::AtLeastOnce), added(&[]));
+        assert_eq!(
+            subscriptions.add("foo/bar", QoS::AtLeastOnce),
+            Added::Existing
+        );
+        assert_eq!(subscriptions.len(), 1);
+    }
+
+    #[test]
+    fn broader_supersedes_narrower() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", QoS::AtLeastOnce);
+        subscriptions.add("foo/baz/deep", QoS::AtLeastOnce);
+        subscriptions.add("other/bar", QoS::AtLeastOnce);
+        assert_eq!(
+            subscriptions.add("foo/+", QoS::AtLeastOnce),
+            added(&["foo/bar"])
+        );
+        assert_eq!(
+            sorted(&subscriptions),
+            ["foo/+", "foo/baz/deep", "other/bar"]
+        );
+        assert_eq!(
+            subscriptions.add("foo/#", QoS::AtLeastOnce),
+            added(&["foo/+", "foo/baz/deep"])
+        );
+        assert_eq!(sorted(&subscriptions), ["foo/#", "other/bar"]);
+    }
+
+    #[test]
+    fn narrower_is_kept_next_to_broader() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/#", QoS::AtLeastOnce);
+        assert_eq!(subscriptions.add("foo/bar", QoS::AtLeastOnce), added(&[]));
+        assert_eq!(sorted(&subscriptions), ["foo/#", "foo/bar"]);
+    }
+
+    fn removed(resubscribe: &[&str], unsubscribe: &[&str]) -> Removed {
+        Removed {
+            resubscribe: resubscribe
+                .iter()
+                .map(|&filter| (filter.to_owned(), QoS::AtLeastOnce))
+                .collect(),
+            unsubscribe: unsubscribe
+                .iter()
+                .map(|&filter| filter.to_owned())
+                .collect(),
+        }
+    }
+
+    #[rstest::rstest]
+    #[case::unknown(&["foo/bar"], "other", &[], &[], &["foo/bar"])]
+    #[case::only(&["foo/bar"], "foo/bar", &[], &["foo/bar"], &[])]
+    #[case::superseded(&["foo/bar", "foo/+"], "foo/bar", &[], &[], &["foo/+"])]
+    #[case::restores_swallowed(&["foo/bar", "foo/+"], "foo/+", &["foo/bar"], &["foo/+"], &["foo/bar"])]
+    #[case::restores_all_swallowed(
+        &["foo/bar", "foo/baz", "other", "foo/+"],
+        "foo/+",
+        &["foo/bar", "foo/baz"],
+        &["foo/+"],
+        &["foo/bar", "foo/baz", "other"],
+    )]
+    #[case::restores_minimal(
+        &["foo/a/b", "foo/+/b", "foo/c", "foo/#"],
+        "foo/#",
+        &["foo/+/b", "foo/c"],
+        &["foo/#"],
+        &["foo/+/b", "foo/c"],
+    )]
+    #[case::restores_intermediate(&["foo/bar", "foo/+", "foo/#"], "foo/#", &["foo/+"], &["foo/#"], &["foo/+"])]
+    #[case::still_covered_by_other(
+        &["foo/bar", "foo/+", "+/bar"],
+        "foo/+",
+        &[],
+        &["foo/+"],
+        &["+/bar"],
+    )]
+    #[case::narrower_added_later(&["foo/#", "foo/bar"], "foo/#", &[], &["foo/#"], &["foo/bar"])]
+    #[case::shared_not_restored_by_plain(
+        &["$share/g/foo/bar", "foo/+"],
+        "foo/+",
+        &[],
+        &["foo/+"],
+        &["$share/g/foo/bar"],
+    )]
+    #[case::shared_restored(
+        &["$share/g/foo/bar", "$share/g/foo/+"],
+        "$share/g/foo/+",
+        &["$share/g/foo/bar"],
+        &["$share/g/foo/+"],
+        &["$share/g/foo/bar"],
+    )]
+    fn remove_works(
+        #[case] added: &[&str],
+        #[case] filter: &str,
+        #[case] resubscribe: &[&str],
+        #[case] unsubscribe: &[&str],
+        #[case] effective: &[&str],
+    ) {
+        let mut subscriptions = Subscriptions::default();
+        for filter in added {
+            subscriptions.add(filter, QoS::AtLeastOnce);
+        }
+        assert_eq!(
+            subscriptions.remove(filter),
+            removed(resubscribe, unsubscribe)
+        );
+        assert_eq!(sorted(&subscriptions), effective);
+    }
+
+    #[test]
+    fn remove_twice() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", QoS::AtLeastOnce);
+        subscriptions.add("foo/+", QoS::AtLeastOnce);
+        assert_eq!(
+            subscriptions.remove("foo/+"),
+            removed(&["foo/bar"], &["foo/+"])
+        );
+        assert_eq!(subscriptions.remove("foo/+"), Removed::default());
+        assert_eq!(subscriptions.remove("foo/bar"), removed(&[], &["foo/bar"]));
+        assert_eq!(subscriptions.len(), 0);
+    }
+
+    #[test]
+    fn removed_superseded_is_not_restored() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", QoS::AtLeastOnce);
+        subscriptions.add("foo/+", QoS::AtLeastOnce);
+        subscriptions.remove("foo/bar");
+        assert_eq!(subscriptions.remove("foo/+"), removed(&[], &["foo/+"]));
+        assert_eq!(subscriptions.len(), 0);
+    }
+
+    #[test]
+    fn forget_does_not_restore() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", QoS::AtLeastOnce);
+        subscriptions.add("foo/+", QoS::AtLeastOnce);
+        subscriptions.forget("foo/+");
+        assert_eq!(subscriptions.len(), 0);
+        subscriptions.restore("foo/bar".to_owned());
+        subscriptions.restore("foo/+".to_owned());
+        assert_eq!(sorted(&subscriptions), ["foo/bar"]);
+    }
+
+    #[rstest::rstest]
+    #[case::same(QoS::AtLeastOnce, QoS::AtLeastOnce, true)]
+    #[case::higher(QoS::ExactlyOnce, QoS::AtLeastOnce, true)]
+    #[case::lower(QoS::AtMostOnce, QoS::AtLeastOnce, false)]
+    #[case::lowest(QoS::AtMostOnce, QoS::ExactlyOnce, false)]
+    fn swallows_only_with_sufficient_qos(
+        #[case] broader: QoS,
+        #[case] narrower: QoS,
+        #[case] swallowed: bool,
+    ) {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", narrower);
+        let superseded = if swallowed {
+            vec!["foo/bar".to_owned()]
+        } else {
+            vec![]
+        };
+        assert_eq!(
+            subscriptions.add("foo/+", broader),
+            Added::New { superseded }
+        );
+        assert_eq!(subscriptions.contains("foo/bar"), !swallowed);
+        assert_eq!(
+            subscriptions
+                .with_qos()
+                .contains(&("foo/bar".to_owned(), narrower)),
+            !swallowed
+        );
+    }
+
+    #[test]
+    fn readding_raises_qos() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", QoS::AtLeastOnce);
+        assert_eq!(
+            subscriptions.add("foo/bar", QoS::AtMostOnce),
+            Added::Existing
+        );
+        assert_eq!(
+            subscriptions.add("foo/bar", QoS::AtLeastOnce),
+            Added::Existing
+        );
+        assert_eq!(subscriptions.add("foo/bar", QoS::ExactlyOnce), added(&[]));
+        assert_eq!(
+            subscriptions.with_qos(),
+            [("foo/bar".to_owned(), QoS::ExactlyOnce)]
+        );
+    }
+
+    #[test]
+    fn raising_qos_supersedes() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/+", QoS::AtMostOnce);
+        subscriptions.add("foo/bar", QoS::AtLeastOnce);
+        assert_eq!(sorted(&subscriptions), ["foo/+", "foo/bar"]);
+        assert_eq!(
+            subscriptions.add("foo/+", QoS::AtLeastOnce),
+            added(&["foo/bar"])
+        );
+        assert_eq!(
+            subscriptions.with_qos(),
+            [("foo/+".to_owned(), QoS::AtLeastOnce)]
+        );
+    }
+
+    #[test]
+    fn raising_swallowed_qos_subscribes_it() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/bar", QoS::AtMostOnce);
+        subscriptions.add("foo/+", QoS::AtLeastOnce);
+        assert!(!subscriptions.contains("foo/bar"));
+        assert_eq!(subscriptions.add("foo/bar", QoS::ExactlyOnce), added(&[]));
+        assert_eq!(
+            subscriptions.with_qos(),
+            [
+                ("foo/+".to_owned(), QoS::AtLeastOnce),
+                ("foo/bar".to_owned(), QoS::ExactlyOnce),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_restores_with_requested_qos() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/a", QoS::ExactlyOnce);
+        subscriptions.add("foo/b", QoS::AtMostOnce);
+        subscriptions.add("foo/#", QoS::ExactlyOnce);
+        assert_eq!(
+            subscriptions.remove("foo/#"),
+            Removed {
+                resubscribe: vec![
+                    ("foo/a".to_owned(), QoS::ExactlyOnce),
+                    ("foo/b".to_owned(), QoS::AtMostOnce),
+                ],
+                unsubscribe: vec!["foo/#".to_owned()],
+            }
+        );
+    }
+
+    #[test]
+    fn remove_restores_narrower_with_higher_qos_than_broader() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("foo/a", QoS::ExactlyOnce);
+        subscriptions.add("foo/+", QoS::AtMostOnce);
+        subscriptions.add("foo/#", QoS::ExactlyOnce);
+        assert_eq!(
+            subscriptions.remove("foo/#"),
+            Removed {
+                resubscribe: vec![
+                    ("foo/+".to_owned(), QoS::AtMostOnce),
+                    ("foo/a".to_owned(), QoS::ExactlyOnce),
+                ],
+                unsubscribe: vec!["foo/#".to_owned()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_resubscribes_before_unsubscribing() {
+        let mqttoptions = MqttOptions::new("test", "localhost", 1883);
+        let (smarthome, mut eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            mqttoptions,
+        );
+        smarthome.subscribe("foo/bar").await;
+        smarthome.subscribe("foo/+").await;
+        eventloop.clean();
+        eventloop.pending.clear();
+
+        smarthome.unsubscribe("foo/+").await;
+        eventloop.clean();
+        let requests = eventloop.pending.drain(..).collect::<Vec<_>>();
+        assert!(
+            matches!(
+                requests.as_slice(),
+                [Request::Subscribe(_), Request::Unsubscribe(_)]
+            ),
+            "{requests:?}"
+        );
+        assert_eq!(smarthome.subscriptions().await, ["foo/bar"]);
+    }
+
+    #[test]
+    fn shared_is_not_superseded_by_plain() {
+        let mut subscriptions = Subscriptions::default();
+        subscriptions.add("$share/workers/foo/bar", QoS::AtLeastOnce);
+        assert_eq!(subscriptions.add("foo/#", QoS::AtLeastOnce), added(&[]));
+        assert!(subscriptions.contains("$share/workers/foo/bar"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::PoisonError;
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::manual_ack::AckHandle;
+use crate::metrics::Metrics;
+use crate::MqttSmarthome;
+
+/// Message received from the broker, see [`tap`](crate::MqttSmarthome::tap).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retained: bool,
+    /// MQTT 5 user properties. Always empty without the `v5` feature.
+    pub user_properties: Vec<(String, String)>,
+    pub(crate) ack_handle: Option<AckHandle>,
+}
+
+impl MqttSmarthome {
+    /// Get every message received from the broker regardless of any watcher.
+    ///
+    /// This does not subscribe to anything, it only observes messages of existing subscriptions including retained ones.
+    /// Messages are skipped while the channel is full.
+    #[must_use]
+    pub fn tap(&self) -> Receiver<ReceivedMessage> {
+        let (sender, receiver) = channel(100);
+        self.taps
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    pub(crate) fn forward_to_taps(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        retained: bool,
+        user_properties: Vec<(String, String)>,
+    ) {
+        let mut taps = self.taps.lock().unwrap_or_else(PoisonError::into_inner);
+        if taps.is_empty() {
+            return;
+        }
+        let message = ReceivedMessage {
+            topic: topic.to_owned(),
+            payload: payload.to_vec(),
+            retained,
+            user_properties,
+            ack_handle: None,
+        };
+        taps.retain(|tap| match tap.try_send(message.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                Metrics::increase(&self.metrics.dropped);
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handle_incoming, handle_incoming_publish};
+
+    #[tokio::test]
+    async fn taps_get_everything() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut first = smarthome.tap();
+        let mut second = smarthome.tap();
+        handle_incoming(&smarthome, "foo".to_owned(), "1", true).await;
+        let expected = ReceivedMessage {
+            topic: "foo".to_owned(),
+            payload: b"1".to_vec(),
+            retained: true,
+            user_properties: Vec::new(),
+            ack_handle: None,
+        };
+        assert_eq!(first.try_recv().unwrap(), expected);
+        assert_eq!(second.try_recv().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn dropped_tap_is_removed() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut kept = smarthome.tap();
+        drop(smarthome.tap());
+        handle_incoming(&smarthome, "foo".to_owned(), "1", false).await;
+        assert_eq!(smarthome.taps.lock().unwrap().len(), 1);
+        assert!(kept.try_recv().is_ok());
+    }
+
+    #[tokio::test]
+    async fn user_properties_are_delivered() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut tap = smarthome.tap();
+        let user_properties = vec![("origin".to_owned(), "heating".to_owned())];
+        handle_incoming_publish(
+            &smarthome,
+            "foo".to_owned(),
+            "1".into(),
+            false,
+            user_properties.clone(),
+        )
+        .await;
+        assert_eq!(tap.try_recv().unwrap().user_properties, user_properties);
+    }
+}
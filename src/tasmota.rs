@@ -0,0 +1,174 @@
+use std::sync::PoisonError;
+
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task;
+
+use crate::json::JsonValue;
+use crate::{payload, MqttSmarthome, PublishError};
+
+/// Topic prefixes of Tasmota devices. Defaults to `cmnd`, `stat` and `tele`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TasmotaPrefixes {
+    pub command: String,
+    pub status: String,
+    pub telemetry: String,
+}
+
+impl Default for TasmotaPrefixes {
+    fn default() -> Self {
+        Self {
+            command: "cmnd".to_owned(),
+            status: "stat".to_owned(),
+            telemetry: "tele".to_owned(),
+        }
+    }
+}
+
+/// Common fields of the Tasmota `tele/{device}/STATE` message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TasmotaState {
+    pub power: Option<bool>,
+    /// Wifi signal strength in percent.
+    pub wifi_rssi: Option<u8>,
+    pub uptime_sec: Option<u64>,
+}
+
+impl TasmotaState {
+    /// Parse the JSON payload of the `STATE` message.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn parse(json: &str) -> Option<Self> {
+        let state = JsonValue::parse(json)?;
+        let power = state
+            .get("POWER")
+            .and_then(JsonValue::as_str)
+            .and_then(payload::as_bool);
+        let wifi_rssi = state
+            .get("Wifi")
+            .and_then(|wifi| wifi.get("RSSI"))
+            .and_then(JsonValue::as_f64)
+            .map(|rssi| rssi.clamp(0.0, 100.0) as u8);
+        let uptime_sec = state
+            .get("UptimeSec")
+            .and_then(JsonValue::as_f64)
+            .filter(|uptime| *uptime >= 0.0)
+            .map(|uptime| uptime as u64);
+        Some(Self {
+            power,
+            wifi_rssi,
+            uptime_sec,
+        })
+    }
+}
+
+impl MqttSmarthome {
+    /// Use other topic prefixes than the Tasmota defaults `cmnd`, `stat` and `tele`.
+    pub fn set_tasmota_prefixes(&self, prefixes: TasmotaPrefixes) {
+        *self
+            .tasmota_prefixes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = prefixes;
+    }
+
+    fn tasmota_prefixes(&self) -> TasmotaPrefixes {
+        self.tasmota_prefixes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Last known power state of the Tasmota `device` from its `stat/{device}/POWER` topic.
+    ///
+    /// The topic needs to be subscribed.
+    pub async fn tasmota_power(&self, device: &str) -> Option<bool> {
+        let topic = format!("{}/{device}/POWER", self.tasmota_prefixes().status);
+        self.last(&topic).await.map(|entry| entry.as_boolean())
+    }
+
+    /// Switch the Tasmota `device` on or off via its `cmnd/{device}/POWER` topic.
+    ///
+    /// # Errors
+    /// Returns an error when the device is not a valid topic or the MQTT eventloop is gone.
+    pub async fn tasmota_set_power(&self, device: &str, on: bool) -> Result<(), PublishError> {
+        let topic = format!("{}/{device}/POWER", self.tasmota_prefixes().command);
+        self.publish(&topic, if on { "ON" } else { "OFF" }, false)
+            .await
+    }
+
+    /// Subscribe to the `tele/{device}/STATE` topic of the Tasmota `device`.
+    ///
+    /// Messages which are not valid JSON are skipped.
+    pub async fn subscribe_tasmota_telemetry(&self, device: &str) -> Receiver<TasmotaState> {
+        let topic = format!("{}/{device}/STATE", self.tasmota_prefixes().telemetry);
+        let mut input = self.subscribe_and_watch(&topic, true).await;
+        let (sender, receiver) = channel(25);
+        task::spawn(async move {
+            while let Some((_, payload)) = input.recv().await {
+                if let Some(state) = TasmotaState::parse(&payload) {
+                    if sender.send(state).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handle_incoming;
+
+    const STATE: &str = r#"{"Time":"2024-05-03T17:00:00","Uptime":"0T01:00:00","UptimeSec":3600,"POWER":"ON","Wifi":{"AP":1,"SSId":"home","RSSI":76,"Signal":-62}}"#;
+
+    #[test]
+    fn parses_state() {
+        assert_eq!(
+            TasmotaState::parse(STATE),
+            Some(TasmotaState {
+                power: Some(true),
+                wifi_rssi: Some(76),
+                uptime_sec: Some(3600),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_fields_are_none() {
+        assert_eq!(TasmotaState::parse("{}"), Some(TasmotaState::default()));
+        assert_eq!(TasmotaState::parse("ON"), None);
+    }
+
+    #[tokio::test]
+    async fn power_uses_prefixes() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        handle_incoming(&smarthome, "stat/plug/POWER".to_owned(), "ON", true).await;
+        assert_eq!(smarthome.tasmota_power("plug").await, Some(true));
+        assert_eq!(smarthome.tasmota_power("other").await, None);
+
+        smarthome.set_tasmota_prefixes(TasmotaPrefixes {
+            status: "plug/stat".to_owned(),
+            ..TasmotaPrefixes::default()
+        });
+        assert_eq!(smarthome.tasmota_power("plug").await, None);
+    }
+
+    #[tokio::test]
+    async fn set_power_publishes_command() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.tasmota_set_power("plug", false).await.unwrap();
+        let entry = smarthome.last("cmnd/plug/POWER").await.unwrap();
+        assert_eq!(entry.payload(), "OFF");
+    }
+
+    #[tokio::test]
+    async fn telemetry_is_parsed() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut receiver = smarthome.subscribe_tasmota_telemetry("plug").await;
+        handle_incoming(&smarthome, "tele/plug/STATE".to_owned(), "invalid", false).await;
+        handle_incoming(&smarthome, "tele/plug/STATE".to_owned(), STATE, false).await;
+        let state = receiver.recv().await.unwrap();
+        assert_eq!(state.uptime_sec, Some(3600));
+    }
+}
@@ -0,0 +1,252 @@
+use core::time::Duration;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task;
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::protocol::{self, EventLoop, MqttOptions, QoS};
+use crate::smarthome_client::BoxFuture;
+use crate::watcher::ChannelPayload;
+use crate::{
+    handle_publish, topic_filter, HistoryEntry, LastWillConfig, MqttSmarthome, PublishError,
+    SmarthomeClient,
+};
+
+/// In-memory [`SmarthomeClient`] for tests of applications.
+///
+/// Publishes are recorded and update the last known payloads.
+/// Messages from [`inject`](Self::inject) reach the channels like messages from a broker.
+#[derive(Debug, Default)]
+pub struct FakeSmarthome {
+    published: Mutex<Vec<(String, Bytes, bool)>>,
+    last: Mutex<HashMap<String, HistoryEntry>>,
+    channels: Mutex<Vec<FakeChannel>>,
+}
+
+#[derive(Debug)]
+struct FakeChannel {
+    filter: String,
+    allow_retained: bool,
+    sender: Sender<ChannelPayload>,
+}
+
+impl FakeSmarthome {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every publish so far as `(topic, payload, retain)` in order.
+    pub fn published(&self) -> Vec<(String, Bytes, bool)> {
+        self.published
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Handle a message as if it was received from the broker.
+    ///
+    /// Channels which are full skip the message.
+    pub fn inject(&self, topic: &str, payload: &str, retained: bool) {
+        self.last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                topic.to_owned(),
+                HistoryEntry::new(payload).with_retained(retained),
+            );
+        self.channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|channel| {
+                if topic_filter::matches(topic, &channel.filter)
+                    && (channel.allow_retained || !retained)
+                {
+                    _ = channel
+                        .sender
+                        .try_send((topic.to_owned(), payload.to_owned()));
+                }
+                !channel.sender.is_closed()
+            });
+    }
+}
+
+impl SmarthomeClient for FakeSmarthome {
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        payload: Bytes,
+        retain: bool,
+    ) -> BoxFuture<'a, Result<(), PublishError>> {
+        Box::pin(async move {
+            PublishError::check_topic(topic)?;
+            self.last
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(
+                    topic.to_owned(),
+                    HistoryEntry::from_bytes(payload.to_vec()).with_retained(retain),
+                );
+            self.published
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push((topic.to_owned(), payload, retain));
+            Ok(())
+        })
+    }
+
+    fn subscribe_channel<'a>(
+        &'a self,
+        topic: &'a str,
+        allow_retained: bool,
+    ) -> BoxFuture<'a, Receiver<ChannelPayload>> {
+        assert!(topic_filter::is_valid(topic), "topic filter is not valid");
+        let (sender, receiver) = channel(25);
+        self.channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(FakeChannel {
+                filter: topic.to_owned(),
+                allow_retained,
+                sender,
+            });
+        Box::pin(async move { receiver })
+    }
+
+    fn last<'a>(&'a self, topic: &'a str) -> BoxFuture<'a, Option<HistoryEntry>> {
+        let last = self
+            .last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(topic)
+            .cloned();
+        Box::pin(async move { last })
+    }
+}
+
+impl MqttSmarthome {
+    /// Create a client which never connects to a broker, for tests of code using this crate.
+    ///
+    /// Requests like publishes and subscriptions are discarded without being sent anywhere.
+    /// The request queue is emptied every 10 ms, so many requests in a short time can wait for it.
+    /// Publishes still update the history. Waiting for acknowledgements like [`subscribe_confirmed`](Self::subscribe_confirmed) never completes.
+    /// Feed messages into it with [`inject_incoming`](Self::inject_incoming).
+    ///
+    /// Needs to be called within a tokio runtime.
+    #[must_use]
+    pub fn disconnected_for_tests(base_topic: &str) -> Self {
+        let (smarthome, eventloop) = Self::new_without_eventloop(
+            LastWillConfig::new(format!("{base_topic}/connected"), false),
+            MqttOptions::new(base_topic, "localhost", 1883),
+        );
+        drain_requests(&smarthome, eventloop);
+        smarthome
+    }
+
+    /// Handle a message as if it was received from the broker.
+    ///
+    /// Runs the same code as messages from the eventloop: history, watchers, taps and metrics are updated.
+    /// The message has `QoS` 0 so it is never treated as duplicate.
+    pub async fn inject_incoming(&self, topic: &str, payload: &str, retained: bool) {
+        let publish = protocol::publish(topic, QoS::AtMostOnce, payload.as_bytes(), retained);
+        handle_publish(self, publish).await;
+    }
+}
+
+/// Discard the requests until every clone of the client, and with it the request channel, is dropped.
+fn drain_requests(smarthome: &MqttSmarthome, mut eventloop: EventLoop) -> task::JoinHandle<()> {
+    let alive = Arc::downgrade(&smarthome.connected);
+    task::spawn(async move {
+        // The request channel is bounded, empty it regularly so requests do not pile up
+        let mut drain = interval(Duration::from_millis(10));
+        drain.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        while alive.strong_count() > 0 {
+            drain.tick().await;
+            eventloop.clean();
+            eventloop.pending.clear();
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fake_records_publishes_and_feeds_channels() {
+        let fake = Arc::new(FakeSmarthome::new());
+        let client: Arc<dyn SmarthomeClient> = fake.clone();
+        let mut receiver = client.subscribe_channel("sensor/+", false).await;
+        fake.inject("sensor/temp", "21.5", false);
+        fake.inject("sensor/hum", "50", true);
+        fake.inject("other", "1", false);
+        assert_eq!(
+            receiver.recv().await,
+            Some(("sensor/temp".to_owned(), "21.5".to_owned()))
+        );
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(client.last_float("sensor/temp").await, Some(21.5));
+
+        client
+            .publish("lamp/set", Bytes::from_static(b"on"), true)
+            .await
+            .unwrap();
+        assert!(client.last_is_true("lamp/set").await);
+        assert_eq!(
+            fake.published(),
+            [("lamp/set".to_owned(), Bytes::from_static(b"on"), true)]
+        );
+        assert!(client
+            .publish("invalid/#", Bytes::new(), false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn injected_message_reaches_history_and_channels() {
+        let smarthome = MqttSmarthome::disconnected_for_tests("test");
+        let mut receiver = smarthome.subscribe_and_watch("sensor/#", false).await;
+        smarthome.inject_incoming("sensor/temp", "21", false).await;
+        smarthome.inject_incoming("sensor/hum", "50", true).await;
+
+        assert_eq!(
+            receiver.recv().await,
+            Some(("sensor/temp".to_owned(), "21".to_owned()))
+        );
+        assert!(receiver.try_recv().is_err(), "retained is not delivered");
+        assert_eq!(smarthome.last("sensor/hum").await.unwrap().payload(), "50");
+        assert!(smarthome.last("sensor/hum").await.unwrap().retained());
+        assert!(smarthome.since_last_received().await.is_some());
+        assert_eq!(smarthome.metrics().messages_received, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn many_publishes_do_not_block() {
+        let smarthome = MqttSmarthome::disconnected_for_tests("test");
+        for index in 0..500 {
+            smarthome.publish("foo", index, false).await.unwrap();
+        }
+        assert_eq!(smarthome.last("foo").await.unwrap().payload(), "499");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_ends_with_the_client() {
+        let (smarthome, eventloop) = MqttSmarthome::new_without_eventloop(
+            LastWillConfig::new("test/connected".to_owned(), false),
+            MqttOptions::new("test", "localhost", 1883),
+        );
+        let drain = drain_requests(&smarthome, eventloop);
+        let clone = smarthome.clone();
+        drop(smarthome);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!drain.is_finished());
+        drop(clone);
+        tokio::time::timeout(Duration::from_millis(50), drain)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+//! MQTT topic filters including shared subscriptions (`$share/{group}/{filter}`).
+//!
+//! The broker delivers messages of shared subscriptions with their plain topic.
+//! Matching therefore happens against the filter without the `$share/{group}/` prefix while the broker gets the full filter.
+
+const SHARE_PREFIX: &str = "$share/";
+
+/// Split the `filter` into the share group and the filter without the `$share/{group}/` prefix.
+fn split_share(filter: &str) -> (Option<&str>, &str) {
+    filter
+        .strip_prefix(SHARE_PREFIX)
+        .and_then(|rest| rest.split_once('/'))
+        .map_or((None, filter), |(group, filter)| (Some(group), filter))
+}
+
+/// The filter without a `$share/{group}/` prefix.
+pub fn without_share(filter: &str) -> &str {
+    split_share(filter).1
+}
+
+pub fn is_valid(filter: &str) -> bool {
+    let Some(rest) = filter.strip_prefix(SHARE_PREFIX) else {
+        return rumqttc::mqttbytes::valid_filter(filter);
+    };
+    let Some((group, filter)) = rest.split_once('/') else {
+        return false;
+    };
+    !group.is_empty() && !group.contains(['+', '#']) && rumqttc::mqttbytes::valid_filter(filter)
+}
+
+pub fn matches(topic: &str, filter: &str) -> bool {
+    rumqttc::mqttbytes::matches(topic, without_share(filter))
+}
+
+/// Whether every topic matched by the `narrower` filter is also matched by the `broader` one.
+///
+/// Shared subscriptions only cover filters of the same group.
+pub fn covers(broader: &str, narrower: &str) -> bool {
+    let (broader_group, broader) = split_share(broader);
+    let (narrower_group, narrower) = split_share(narrower);
+    if broader_group != narrower_group {
+        return false;
+    }
+    // Wildcards on the first level do not match topics starting with $
+    if narrower.starts_with('$') && broader.starts_with(['+', '#']) {
+        return false;
+    }
+    let mut narrower = narrower.split('/');
+    for level in broader.split('/') {
+        if level == "#" {
+            return true;
+        }
+        match narrower.next() {
+            None | Some("#") => return false,
+            Some(narrow) if level != "+" && level != narrow => return false,
+            Some(_) => {}
+        }
+    }
+    narrower.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rstest::rstest]
+    #[case::plain("base/set/#", "base/set/#")]
+    #[case::shared("$share/workers/base/set/#", "base/set/#")]
+    #[case::without_filter("$share/workers", "$share/workers")]
+    #[case::other_dollar("$SYS/broker/uptime", "$SYS/broker/uptime")]
+    fn without_share_works(#[case] filter: &str, #[case] expected: &str) {
+        assert_eq!(without_share(filter), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::plain("base/set/#", true)]
+    #[case::shared("$share/workers/base/set/#", true)]
+    #[case::shared_single_level("$share/workers/+", true)]
+    #[case::missing_filter("$share/workers", false)]
+    #[case::empty_group("$share//base/#", false)]
+    #[case::wildcard_group("$share/+/base/#", false)]
+    #[case::invalid_filter("$share/workers/base/#/set", false)]
+    fn is_valid_works(#[case] filter: &str, #[case] expected: bool) {
+        assert_eq!(is_valid(filter), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::same("foo/bar", "foo/bar", true)]
+    #[case::single_level("foo/+", "foo/bar", true)]
+    #[case::multi_level("foo/#", "foo/bar/baz", true)]
+    #[case::multi_level_parent("foo/#", "foo", true)]
+    #[case::multi_level_covers_single("foo/#", "foo/+/baz", true)]
+    #[case::single_level_too_short("foo/+", "foo/bar/baz", false)]
+    #[case::single_level_not_multi("foo/+", "foo/#", false)]
+    #[case::other_level("foo/+", "bar/baz", false)]
+    #[case::narrower_is_broader("foo/bar", "foo/+", false)]
+    #[case::dollar_topic("#", "$SYS/uptime", false)]
+    #[case::dollar_literal("$SYS/#", "$SYS/uptime", true)]
+    #[case::shared_same_group("$share/g/foo/+", "$share/g/foo/bar", true)]
+    #[case::shared_other_group("$share/g/foo/+", "$share/h/foo/bar", false)]
+    #[case::shared_and_plain("foo/+", "$share/g/foo/bar", false)]
+    fn covers_works(#[case] broader: &str, #[case] narrower: &str, #[case] expected: bool) {
+        assert_eq!(covers(broader, narrower), expected);
+    }
+
+    #[test]
+    fn shared_matches_plain_topic() {
+        assert!(matches("base/set/lamp", "$share/workers/base/set/#"));
+        assert!(!matches("other/set/lamp", "$share/workers/base/set/#"));
+    }
+}
@@ -0,0 +1,158 @@
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::Ordering;
+
+use tokio::time::Instant;
+
+use crate::MqttSmarthome;
+
+/// Amount of reception times kept per topic to calculate the rate
+const RECENT_RECEPTIONS: usize = 20;
+
+/// Received messages of a topic. See [`topic_stats`](MqttSmarthome::topic_stats).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopicStats {
+    pub topic: String,
+    pub count: u64,
+    /// Based on the most recent messages
+    pub messages_per_minute: f32,
+}
+
+#[derive(Debug, Default)]
+pub struct TopicStatsCollector {
+    topics: Option<HashMap<String, TopicReceptions>>,
+}
+
+#[derive(Debug, Default)]
+struct TopicReceptions {
+    count: u64,
+    recent: VecDeque<Instant>,
+}
+
+impl TopicStatsCollector {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if enabled {
+            self.topics.get_or_insert_with(HashMap::new);
+        } else {
+            self.topics = None;
+        }
+    }
+
+    pub fn record(&mut self, topic: &str) {
+        let Some(topics) = &mut self.topics else {
+            return;
+        };
+        let receptions = topics.entry(topic.to_owned()).or_default();
+        receptions.count += 1;
+        if receptions.recent.len() >= RECENT_RECEPTIONS {
+            receptions.recent.pop_front();
+        }
+        receptions.recent.push_back(Instant::now());
+    }
+
+    /// Stats of every topic sorted by rate descending
+    pub fn stats(&self) -> Vec<TopicStats> {
+        let now = Instant::now();
+        let mut stats = self
+            .topics
+            .iter()
+            .flatten()
+            .map(|(topic, receptions)| TopicStats {
+                topic: topic.clone(),
+                count: receptions.count,
+                messages_per_minute: receptions.messages_per_minute(now),
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| {
+            b.messages_per_minute
+                .total_cmp(&a.messages_per_minute)
+                .then_with(|| a.topic.cmp(&b.topic))
+        });
+        stats
+    }
+}
+
+impl TopicReceptions {
+    #[allow(clippy::cast_precision_loss)]
+    fn messages_per_minute(&self, now: Instant) -> f32 {
+        let Some(oldest) = self.recent.front() else {
+            return 0.0;
+        };
+        let span = now.duration_since(*oldest).max(Duration::from_secs(1));
+        self.recent.len() as f32 * 60.0 / span.as_secs_f32()
+    }
+}
+
+impl MqttSmarthome {
+    /// Collect per topic statistics of the received messages. Disabled by default as it costs memory per topic.
+    ///
+    /// Disabling drops the collected statistics.
+    pub async fn set_topic_stats_enabled(&self, enabled: bool) {
+        self.topic_stats.write().await.set_enabled(enabled);
+        self.topic_stats_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Statistics of every received topic sorted by the rate of messages descending.
+    ///
+    /// Empty unless enabled via [`set_topic_stats_enabled`](Self::set_topic_stats_enabled).
+    pub async fn topic_stats(&self) -> Vec<TopicStats> {
+        self.topic_stats.read().await.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use float_eq::assert_float_eq;
+    use tokio::time::advance;
+
+    use super::*;
+
+    #[test]
+    fn disabled_records_nothing() {
+        let mut collector = TopicStatsCollector::default();
+        collector.record("foo");
+        assert!(collector.stats().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sorted_by_rate() {
+        let mut collector = TopicStatsCollector::default();
+        collector.set_enabled(true);
+        for _ in 0..10 {
+            collector.record("noisy");
+            collector.record("noisy");
+            collector.record("calm");
+            advance(Duration::from_secs(6)).await;
+        }
+        let stats = collector.stats();
+        assert_eq!(stats[0].topic, "noisy");
+        assert_eq!(stats[0].count, 20);
+        assert_float_eq!(stats[0].messages_per_minute, 20.0, abs <= 0.1);
+        assert_eq!(stats[1].topic, "calm");
+        assert_eq!(stats[1].count, 10);
+        assert_float_eq!(stats[1].messages_per_minute, 10.0, abs <= 0.1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn count_exceeds_recent_window() {
+        let mut collector = TopicStatsCollector::default();
+        collector.set_enabled(true);
+        for _ in 0..50 {
+            collector.record("foo");
+            advance(Duration::from_secs(1)).await;
+        }
+        let stats = collector.stats();
+        assert_eq!(stats[0].count, 50);
+        assert_float_eq!(stats[0].messages_per_minute, 60.0, abs <= 0.1);
+    }
+
+    #[tokio::test]
+    async fn incoming_messages_are_recorded() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        smarthome.set_topic_stats_enabled(true).await;
+        crate::handle_incoming(&smarthome, "foo".to_owned(), "1".to_owned(), false).await;
+        let stats = smarthome.topic_stats().await;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].count, 1);
+    }
+}
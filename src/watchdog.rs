@@ -0,0 +1,228 @@
+use core::time::Duration;
+use std::collections::HashSet;
+
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+use crate::connection_events::Link;
+use crate::{topic_filter, MqttSmarthome};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// No message was received on the `topic` for longer than allowed.
+    Silent { topic: String, since: Duration },
+    /// A message was received again on the previously silent `topic`.
+    Recovered { topic: String },
+}
+
+impl MqttSmarthome {
+    /// Get notified when topics matching the `filter` have not received a message for longer than `max_silence`.
+    ///
+    /// [`WatchdogEvent::Silent`] is sent once when a topic becomes silent and [`WatchdogEvent::Recovered`] when it receives a message again.
+    /// This is based on the history, so the topics need to be subscribed.
+    ///
+    /// With `unknown_is_silent` the `filter` also counts as silent when it received nothing within `max_silence` after the connection was established.
+    /// Wildcards are not supported then as there is no topic to report.
+    ///
+    /// # Panics
+    /// Panics when the `filter` is not valid or contains wildcards together with `unknown_is_silent`.
+    #[must_use]
+    pub fn watchdog(
+        &self,
+        filter: &str,
+        max_silence: Duration,
+        unknown_is_silent: bool,
+    ) -> Receiver<WatchdogEvent> {
+        assert!(topic_filter::is_valid(filter), "topic filter is not valid");
+        assert!(
+            !unknown_is_silent || !filter.contains(['+', '#']),
+            "unknown_is_silent does not support wildcards"
+        );
+        let unknown_topic = unknown_is_silent.then(|| filter.to_owned());
+        let filter = filter.to_owned();
+        let history = self.history.clone();
+        let clock = self.clock.clone();
+        let mut link = self.link.subscribe();
+        let (sender, receiver) = channel(25);
+        task::spawn(async move {
+            let mut connected_since = None;
+            let mut silent = HashSet::<String>::new();
+            let mut check = interval((max_silence / 4).max(Duration::from_millis(100)));
+            check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                let is_connected = matches!(
+                    *link.borrow_and_update(),
+                    Link::Connected | Link::Initialized
+                );
+                connected_since =
+                    is_connected.then(|| connected_since.unwrap_or_else(Instant::now));
+                tokio::select! {
+                    _ = check.tick() => {}
+                    changed = link.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                }
+                if sender.is_closed() {
+                    return;
+                }
+
+                let now = clock.now();
+                let mut events = Vec::new();
+                let known = history.filter_map(|topic, entry| {
+                    topic_filter::matches(topic, &filter).then(|| (topic.clone(), entry.time()))
+                });
+                for (topic, time) in known {
+                    let since = now.duration_since(time).unwrap_or_default();
+                    if since > max_silence {
+                        if silent.insert(topic.clone()) {
+                            events.push(WatchdogEvent::Silent { topic, since });
+                        }
+                    } else if silent.remove(&topic) {
+                        events.push(WatchdogEvent::Recovered { topic });
+                    }
+                }
+                if let (Some(topic), Some(connected_since)) = (&unknown_topic, connected_since) {
+                    let since = connected_since.elapsed();
+                    if history.get(topic).is_none()
+                        && since > max_silence
+                        && silent.insert(topic.clone())
+                    {
+                        events.push(WatchdogEvent::Silent {
+                            topic: topic.clone(),
+                            since,
+                        });
+                    }
+                }
+
+                for event in events {
+                    if sender.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::{handle_incoming, HistoryEntry};
+
+    const MAX_SILENCE: Duration = Duration::from_mins(5);
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_then_recovered() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let old = SystemTime::now() - MAX_SILENCE * 2;
+        smarthome
+            .history
+            .insert("sensor/temp".to_owned(), HistoryEntry::new_at("21", old));
+        handle_incoming(&smarthome, "sensor/hum".to_owned(), "50".to_owned(), false).await;
+
+        let mut events = smarthome.watchdog("sensor/#", MAX_SILENCE, false);
+        let Some(WatchdogEvent::Silent { topic, since }) = events.recv().await else {
+            panic!("expected silent event");
+        };
+        assert_eq!(topic, "sensor/temp");
+        assert!(since >= MAX_SILENCE * 2);
+
+        handle_incoming(&smarthome, "sensor/temp".to_owned(), "22".to_owned(), false).await;
+        assert_eq!(
+            events.recv().await,
+            Some(WatchdogEvent::Recovered {
+                topic: "sensor/temp".to_owned()
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silence_follows_the_clock() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let clock = Arc::new(MockClock::new(SystemTime::now()));
+        smarthome.set_clock(clock.clone());
+        handle_incoming(&smarthome, "sensor/temp".to_owned(), "21".to_owned(), false).await;
+
+        let mut events = smarthome.watchdog("sensor/temp", MAX_SILENCE, false);
+        tokio::time::sleep(MAX_SILENCE * 2).await;
+        assert!(events.try_recv().is_err(), "the clock did not move");
+
+        clock.advance(MAX_SILENCE * 2);
+        assert_eq!(
+            events.recv().await,
+            Some(WatchdogEvent::Silent {
+                topic: "sensor/temp".to_owned(),
+                since: MAX_SILENCE * 2,
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_is_not_repeated() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let old = SystemTime::now() - MAX_SILENCE * 2;
+        smarthome
+            .history
+            .insert("sensor/temp".to_owned(), HistoryEntry::new_at("21", old));
+
+        let mut events = smarthome.watchdog("sensor/temp", MAX_SILENCE, false);
+        assert!(matches!(
+            events.recv().await,
+            Some(WatchdogEvent::Silent { .. })
+        ));
+        tokio::time::sleep(MAX_SILENCE * 3).await;
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_is_silent_after_max_silence() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut events = smarthome.watchdog("sensor/temp", MAX_SILENCE, true);
+        tokio::time::sleep(MAX_SILENCE * 2).await;
+        assert!(events.try_recv().is_err(), "not connected yet");
+
+        let connected = Instant::now();
+        smarthome.link.send_replace(Link::Connected);
+        let Some(WatchdogEvent::Silent { topic, since }) = events.recv().await else {
+            panic!("expected silent event");
+        };
+        assert_eq!(topic, "sensor/temp");
+        assert!(since > MAX_SILENCE);
+        assert!(since <= connected.elapsed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_received_after_connecting_is_not_silent() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut events = smarthome.watchdog("sensor/temp", MAX_SILENCE, true);
+        smarthome.link.send_replace(Link::Connected);
+        tokio::time::sleep(MAX_SILENCE / 2).await;
+        handle_incoming(&smarthome, "sensor/temp".to_owned(), "21".to_owned(), true).await;
+        tokio::time::sleep(MAX_SILENCE).await;
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    #[should_panic = "unknown_is_silent does not support wildcards"]
+    async fn unknown_with_wildcard_panics() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        _ = smarthome.watchdog("sensor/+", MAX_SILENCE, true);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_is_not_silent_by_default() {
+        let smarthome = MqttSmarthome::new_for_tests();
+        let mut events = smarthome.watchdog("sensor/temp", MAX_SILENCE, false);
+        tokio::time::sleep(MAX_SILENCE * 3).await;
+        assert!(events.try_recv().is_err());
+    }
+}
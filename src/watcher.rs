@@ -1,26 +1,377 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::RwLock;
+use tokio::task;
+
+use crate::watchers::Watchers;
+use crate::{payload, topic_filter, EntrySource};
 
 pub type ChannelPayload = (String, String);
 
+/// Topic and payload shared by every channel getting the message
+pub type ArcPayload = (Arc<str>, Arc<str>);
+
+/// Topic, payload and whether it was received or published by this client
+pub type SourcedPayload = (String, String, EntrySource);
+
+/// Topic and the payload as it was received
+pub type BytesPayload = (String, Vec<u8>);
+
+/// Topic, payload and the previous payload of the topic
+pub type ChangePayload = (String, String, Option<String>);
+
+/// Topic, new value and the difference to the previous value
+pub type DeltaPayload = (String, f32, f32);
+
+/// New value and difference to the previous value when both are finite numbers.
+fn numeric_delta(previous: Option<&str>, payload: &str) -> Option<(f32, f32)> {
+    let previous = payload::as_f32(previous?).filter(|value| value.is_finite())?;
+    let value = payload::as_f32(payload).filter(|value| value.is_finite())?;
+    Some((value, value - previous))
+}
+
+/// Change of a boolean state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Changed from `false` to `true`
+    Rising,
+    /// Changed from `true` to `false`
+    Falling,
+}
+
+impl Edge {
+    /// Edge from the `previous` payload to the `payload` based on [`payload::is_true`].
+    #[must_use]
+    pub fn between(previous: Option<&str>, payload: &str) -> Option<Self> {
+        match (payload::is_true(previous?), payload::is_true(payload)) {
+            (false, true) => Some(Self::Rising),
+            (true, false) => Some(Self::Falling),
+            _ => None,
+        }
+    }
+}
+
+/// Values derived from a message, computed at most once no matter how many watchers need them.
+#[derive(Default)]
+pub struct Derived<'a> {
+    /// Payload of the topic in the history before this message
+    previous: Option<&'a str>,
+    /// Topic and payload shared between the senders of the same message
+    shared: OnceCell<ArcPayload>,
+    edge: OnceCell<Option<Edge>>,
+    delta: OnceCell<Option<(f32, f32)>>,
+}
+
+impl<'a> Derived<'a> {
+    pub fn new(previous: Option<&'a str>) -> Self {
+        Self {
+            previous,
+            ..Self::default()
+        }
+    }
+
+    fn shared(&self, topic: &str, payload: &str) -> ArcPayload {
+        self.shared
+            .get_or_init(|| (topic.into(), payload.into()))
+            .clone()
+    }
+
+    fn edge(&self, payload: &str) -> Option<Edge> {
+        *self
+            .edge
+            .get_or_init(|| Edge::between(self.previous, payload))
+    }
+
+    fn delta(&self, payload: &str) -> Option<(f32, f32)> {
+        *self
+            .delta
+            .get_or_init(|| numeric_delta(self.previous, payload))
+    }
+}
+
+#[derive(Clone)]
+pub enum WatcherSender {
+    Payload(Sender<ChannelPayload>),
+    Arc(Sender<ArcPayload>),
+    Sourced(Sender<SourcedPayload>),
+    Bytes(Sender<BytesPayload>),
+    Change(Sender<ChangePayload>),
+    Edge(Sender<(String, Edge)>),
+    Delta(Sender<DeltaPayload>),
+}
+
+impl WatcherSender {
+    /// `derived` is shared between the senders of the same message.
+    pub fn try_send(
+        &self,
+        topic: &str,
+        payload: &str,
+        raw: &[u8],
+        source: EntrySource,
+        derived: &Derived,
+    ) -> Result<(), TrySendError<()>> {
+        match self {
+            Self::Payload(sender) => sender
+                .try_send((topic.to_owned(), payload.to_owned()))
+                .map_err(|err| map_send_error(&err)),
+            Self::Arc(sender) => sender
+                .try_send(derived.shared(topic, payload))
+                .map_err(|err| map_send_error(&err)),
+            Self::Sourced(sender) => sender
+                .try_send((topic.to_owned(), payload.to_owned(), source))
+                .map_err(|err| map_send_error(&err)),
+            Self::Bytes(sender) => sender
+                .try_send((topic.to_owned(), raw.to_vec()))
+                .map_err(|err| map_send_error(&err)),
+            Self::Change(sender) => sender
+                .try_send((
+                    topic.to_owned(),
+                    payload.to_owned(),
+                    derived.previous.map(ToOwned::to_owned),
+                ))
+                .map_err(|err| map_send_error(&err)),
+            Self::Edge(sender) => {
+                let Some(edge) = derived.edge(payload) else {
+                    return Ok(());
+                };
+                sender
+                    .try_send((topic.to_owned(), edge))
+                    .map_err(|err| map_send_error(&err))
+            }
+            Self::Delta(sender) => {
+                let Some((value, delta)) = derived.delta(payload) else {
+                    return Ok(());
+                };
+                sender
+                    .try_send((topic.to_owned(), value, delta))
+                    .map_err(|err| map_send_error(&err))
+            }
+        }
+    }
+
+    /// Whether the message is of interest for this kind of sender
+    fn wants(&self, payload: &str, derived: &Derived) -> bool {
+        match self {
+            Self::Payload(_) | Self::Arc(_) | Self::Sourced(_) | Self::Bytes(_) => true,
+            Self::Change(_) => derived.previous != Some(payload),
+            Self::Edge(_) => derived.edge(payload).is_some(),
+            Self::Delta(_) => derived.delta(payload).is_some(),
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self {
+            Self::Payload(sender) => sender.is_closed(),
+            Self::Arc(sender) => sender.is_closed(),
+            Self::Sourced(sender) => sender.is_closed(),
+            Self::Bytes(sender) => sender.is_closed(),
+            Self::Change(sender) => sender.is_closed(),
+            Self::Edge(sender) => sender.is_closed(),
+            Self::Delta(sender) => sender.is_closed(),
+        }
+    }
+}
+
+const fn map_send_error<T>(err: &TrySendError<T>) -> TrySendError<()> {
+    match err {
+        TrySendError::Full(_) => TrySendError::Full(()),
+        TrySendError::Closed(_) => TrySendError::Closed(()),
+    }
+}
+
+/// Last payload per topic of a watcher only delivering distinct payloads
+type LastPayloads = Arc<Mutex<HashMap<String, String>>>;
+
+/// Remember the payload of the topic for a distinct watcher.
+fn remember_payload(last_payloads: &LastPayloads, topic: &str, payload: &str) {
+    last_payloads
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(topic.to_owned(), payload.to_owned());
+}
+
+/// Sender of a watcher the message should be delivered to.
+pub struct MatchedSender {
+    sender: WatcherSender,
+    last_payloads: Option<LastPayloads>,
+}
+
+impl MatchedSender {
+    /// See [`WatcherSender::try_send`]. A distinct watcher remembers the payload once it was sent.
+    pub fn try_send(
+        &self,
+        topic: &str,
+        payload: &str,
+        raw: &[u8],
+        source: EntrySource,
+        derived: &Derived,
+    ) -> Result<(), TrySendError<()>> {
+        self.sender.try_send(topic, payload, raw, source, derived)?;
+        if let Some(last_payloads) = &self.last_payloads {
+            remember_payload(last_payloads, topic, payload);
+        }
+        Ok(())
+    }
+}
+
 pub struct Watcher {
+    active: Arc<AtomicBool>,
     allow_retained: bool,
     filter: Box<str>,
-    sender: Sender<ChannelPayload>,
+    last_payloads: Option<LastPayloads>,
+    sender: WatcherSender,
 }
 
 impl Watcher {
     pub fn new(mqtt_topic_filter: &str, allow_retained: bool) -> (Self, Receiver<ChannelPayload>) {
+        Self::new_with_capacity(mqtt_topic_filter, allow_retained, 25)
+    }
+
+    pub fn new_with_capacity(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+        capacity: usize,
+    ) -> (Self, Receiver<ChannelPayload>) {
+        let (sender, receiver) = channel(capacity);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Payload(sender),
+        );
+        (watcher, receiver)
+    }
+
+    /// Watcher delivering topic and payload shared with the other channels of this kind.
+    pub fn new_arc(mqtt_topic_filter: &str, allow_retained: bool) -> (Self, Receiver<ArcPayload>) {
+        let (sender, receiver) = channel(25);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Arc(sender),
+        );
+        (watcher, receiver)
+    }
+
+    /// Watcher delivering the payload together with its [`EntrySource`].
+    pub fn new_sourced(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+    ) -> (Self, Receiver<SourcedPayload>) {
+        let (sender, receiver) = channel(25);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Sourced(sender),
+        );
+        (watcher, receiver)
+    }
+
+    /// Watcher delivering the payload as it was received.
+    pub fn new_bytes(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+    ) -> (Self, Receiver<BytesPayload>) {
+        let (sender, receiver) = channel(25);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Bytes(sender),
+        );
+        (watcher, receiver)
+    }
+
+    /// Watcher only delivering messages with a payload differing from the previous one in the history.
+    pub fn new_changes(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+    ) -> (Self, Receiver<ChangePayload>) {
+        let (sender, receiver) = channel(25);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Change(sender),
+        );
+        (watcher, receiver)
+    }
+
+    /// Watcher delivering the [`Edge`] between the previous payload in the history and the new one.
+    pub fn new_edges(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+    ) -> (Self, Receiver<(String, Edge)>) {
+        let (sender, receiver) = channel(25);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Edge(sender),
+        );
+        (watcher, receiver)
+    }
+
+    /// Watcher delivering the numeric difference between the previous payload in the history and the new one.
+    pub fn new_deltas(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+    ) -> (Self, Receiver<DeltaPayload>) {
+        let (sender, receiver) = channel(25);
+        let watcher = Self::with_sender(
+            mqtt_topic_filter,
+            allow_retained,
+            WatcherSender::Delta(sender),
+        );
+        (watcher, receiver)
+    }
+
+    pub fn with_sender(
+        mqtt_topic_filter: &str,
+        allow_retained: bool,
+        sender: WatcherSender,
+    ) -> Self {
         assert!(
-            rumqttc::mqttbytes::valid_filter(mqtt_topic_filter),
+            topic_filter::is_valid(mqtt_topic_filter),
             "topic filter is not valid"
         );
-        let (sender, receiver) = channel(25);
-        let watcher = Self {
+        Self {
+            active: Arc::new(AtomicBool::new(true)),
             allow_retained,
             filter: mqtt_topic_filter.into(),
+            last_payloads: None,
             sender,
-        };
-        (watcher, receiver)
+        }
+    }
+
+    /// The watcher does not match anything until the returned flag is set.
+    pub fn deactivated(self) -> (Self, Arc<AtomicBool>) {
+        self.active.store(false, Ordering::Relaxed);
+        let active = self.activation();
+        (self, active)
+    }
+
+    /// The activation flag also identifies the watcher, see [`remove_watcher`].
+    pub fn activation(&self) -> Arc<AtomicBool> {
+        self.active.clone()
+    }
+
+    /// Skip messages with the same payload as the previous one of the same topic.
+    ///
+    /// Retained messages are remembered even when they are not delivered.
+    /// Messages which could not be sent, like on a full channel, are not remembered so the payload is delivered again.
+    pub fn distinct(mut self) -> Self {
+        self.last_payloads = Some(Arc::default());
+        self
+    }
+
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    pub fn has_activation(&self, active: &Arc<AtomicBool>) -> bool {
+        Arc::ptr_eq(&self.active, active)
     }
 
     #[must_use]
@@ -28,11 +379,114 @@ impl Watcher {
         if retained && !self.allow_retained {
             return false;
         }
-        rumqttc::mqttbytes::matches(topic, &self.filter)
+        self.is_filter_match(topic)
+    }
+
+    fn is_filter_match(&self, topic: &str) -> bool {
+        self.active.load(Ordering::Relaxed) && topic_filter::matches(topic, &self.filter)
     }
 
-    pub fn matching_sender(&self, topic: &str, retained: bool) -> Option<Sender<ChannelPayload>> {
-        self.is_match(topic, retained).then(|| self.sender.clone())
+    /// Whether the payload is the same as the previous one of the topic for a distinct watcher.
+    fn is_repeated(&self, topic: &str, payload: &str) -> bool {
+        self.last_payloads.as_ref().is_some_and(|last_payloads| {
+            last_payloads
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(topic)
+                .is_some_and(|last| last == payload)
+        })
+    }
+
+    fn to_matched_sender(&self) -> MatchedSender {
+        MatchedSender {
+            sender: self.sender.clone(),
+            last_payloads: self.last_payloads.clone(),
+        }
+    }
+
+    /// The receiver is gone or the watcher is deactivated and the flag to activate it was dropped.
+    pub fn is_closed(&self) -> bool {
+        let is_abandoned =
+            !self.active.load(Ordering::Relaxed) && Arc::strong_count(&self.active) == 1;
+        is_abandoned || self.sender.is_closed()
+    }
+
+    /// Deliver a payload known from the history regardless of the retained setting.
+    pub fn replay(
+        &self,
+        topic: &str,
+        payload: &str,
+        source: EntrySource,
+    ) -> Result<(), TrySendError<()>> {
+        self.to_matched_sender().try_send(
+            topic,
+            payload,
+            payload.as_bytes(),
+            source,
+            &Derived::default(),
+        )
+    }
+
+    /// Returns the sender when the message should be delivered to this watcher.
+    ///
+    /// `derived` is shared between the watchers getting the same message.
+    pub fn matching_sender(
+        &self,
+        topic: &str,
+        payload: &str,
+        retained: bool,
+        derived: &Derived,
+    ) -> Option<MatchedSender> {
+        if !self.is_filter_match(topic) || self.is_repeated(topic, payload) {
+            return None;
+        }
+        if self.sender.wants(payload, derived) && self.is_match(topic, retained) {
+            return Some(self.to_matched_sender());
+        }
+        if let Some(last_payloads) = &self.last_payloads {
+            remember_payload(last_payloads, topic, payload);
+        }
+        None
+    }
+
+    /// Like [`matching_sender`](Self::matching_sender) for payloads only delivered to bytes channels.
+    pub fn matching_bytes_sender(&self, topic: &str, retained: bool) -> Option<MatchedSender> {
+        (matches!(self.sender, WatcherSender::Bytes(_)) && self.is_match(topic, retained)).then(
+            || MatchedSender {
+                sender: self.sender.clone(),
+                last_payloads: None,
+            },
+        )
+    }
+}
+
+/// Remove the watcher with the given activation flag.
+///
+/// Works without an async context so it can be used in [`Drop`] implementations.
+/// When the lock is currently not available the removal happens in the background.
+pub fn remove_watcher(watchers: &Arc<RwLock<Watchers>>, active: Arc<AtomicBool>) {
+    let remove = move |watchers: &mut Watchers| {
+        watchers.retain(|watcher| !watcher.has_activation(&active));
+    };
+    if let Ok(mut watchers) = watchers.try_write() {
+        remove(&mut watchers);
+    } else {
+        let watchers = watchers.clone();
+        task::spawn(async move {
+            remove(&mut *watchers.write().await);
+        });
+    }
+}
+
+/// Removes the watcher when dropped.
+pub struct RemoveWatcherOnDrop {
+    pub watchers: Arc<RwLock<Watchers>>,
+    pub active: Arc<AtomicBool>,
+}
+
+impl Drop for RemoveWatcherOnDrop {
+    fn drop(&mut self) {
+        remove_watcher(&self.watchers, self.active.clone());
     }
 }
 
@@ -57,6 +511,184 @@ fn is_match_matches() {
     assert!(!watcher.is_match("whatever/else", false));
 }
 
+#[test]
+fn is_match_shared_subscription() {
+    let (watcher, _receiver) = Watcher::new("$share/workers/foo/#", false);
+    assert!(watcher.is_match("foo/bar", false));
+    assert!(!watcher.is_match("$share/workers/foo/bar", false));
+}
+
+#[test]
+fn is_match_deactivated() {
+    let (watcher, _receiver) = Watcher::new("#", true);
+    let (watcher, active) = watcher.deactivated();
+    assert!(!watcher.is_match("foo/bar", false));
+    active.store(true, Ordering::Relaxed);
+    assert!(watcher.is_match("foo/bar", false));
+}
+
+/// Send the message like the dispatch does. `None` when the watcher does not want it.
+#[cfg(test)]
+fn send(watcher: &Watcher, topic: &str, payload: &str) -> Option<Result<(), TrySendError<()>>> {
+    let derived = Derived::default();
+    let sender = watcher.matching_sender(topic, payload, false, &derived)?;
+    Some(sender.try_send(
+        topic,
+        payload,
+        payload.as_bytes(),
+        EntrySource::Incoming,
+        &derived,
+    ))
+}
+
+#[test]
+fn deactivated_is_closed_without_flag() {
+    let (watcher, _receiver) = Watcher::new("#", false);
+    let (watcher, active) = watcher.deactivated();
+    assert!(!watcher.is_closed());
+    drop(active);
+    assert!(watcher.is_closed());
+
+    let (watcher, _receiver) = Watcher::new("#", false);
+    let (watcher, active) = watcher.deactivated();
+    active.store(true, Ordering::Relaxed);
+    drop(active);
+    assert!(!watcher.is_closed());
+}
+
+#[test]
+fn distinct_skips_same_payload() {
+    let (watcher, _receiver) = Watcher::new("#", false);
+    let watcher = watcher.distinct();
+    assert_eq!(send(&watcher, "foo", "1"), Some(Ok(())));
+    assert_eq!(send(&watcher, "foo", "1"), None);
+    assert_eq!(send(&watcher, "bar", "1"), Some(Ok(())));
+    assert_eq!(send(&watcher, "foo", "2"), Some(Ok(())));
+    assert_eq!(send(&watcher, "foo", "1"), Some(Ok(())));
+}
+
+#[test]
+fn distinct_remembers_only_sent_payloads() {
+    let (watcher, mut receiver) = Watcher::new_with_capacity("#", false, 1);
+    let watcher = watcher.distinct();
+    assert_eq!(send(&watcher, "foo", "1"), Some(Ok(())));
+    assert_eq!(
+        send(&watcher, "foo", "2"),
+        Some(Err(TrySendError::Full(())))
+    );
+    receiver.try_recv().unwrap();
+    assert_eq!(
+        send(&watcher, "foo", "2"),
+        Some(Ok(())),
+        "the full channel did not get it"
+    );
+    receiver.try_recv().unwrap();
+    assert_eq!(send(&watcher, "foo", "2"), None);
+}
+
+#[test]
+fn distinct_remembers_not_allowed_retained() {
+    let (watcher, _receiver) = Watcher::new("#", false);
+    let watcher = watcher.distinct();
+    assert!(watcher
+        .matching_sender("foo", "1", true, &Derived::new(None))
+        .is_none());
+    assert!(watcher
+        .matching_sender("foo", "1", false, &Derived::new(None))
+        .is_none());
+    assert!(watcher
+        .matching_sender("foo", "2", false, &Derived::new(None))
+        .is_some());
+}
+
+#[test]
+fn not_distinct_delivers_same_payload() {
+    let (watcher, _receiver) = Watcher::new("#", false);
+    assert!(watcher
+        .matching_sender("foo", "1", false, &Derived::new(None))
+        .is_some());
+    assert!(watcher
+        .matching_sender("foo", "1", false, &Derived::new(None))
+        .is_some());
+}
+
+#[test]
+fn changes_skip_payload_equal_to_previous() {
+    let (watcher, _receiver) = Watcher::new_changes("#", false);
+    assert!(watcher
+        .matching_sender("foo", "1", false, &Derived::new(None))
+        .is_some());
+    assert!(watcher
+        .matching_sender("foo", "1", false, &Derived::new(Some("1")))
+        .is_none());
+    assert!(watcher
+        .matching_sender("foo", "2", false, &Derived::new(Some("1")))
+        .is_some());
+}
+
+#[cfg(test)]
+#[rstest::rstest]
+#[case::cold_start(None, "true", None)]
+#[case::stays_true(Some("true"), "true", None)]
+#[case::stays_false(Some("off"), "0", None)]
+#[case::rising(Some("false"), "true", Some(Edge::Rising))]
+#[case::falling(Some("true"), "false", Some(Edge::Falling))]
+#[case::falling_mixed_words(Some("ON"), "0", Some(Edge::Falling))]
+fn edge_between(
+    #[case] previous: Option<&str>,
+    #[case] payload: &str,
+    #[case] expected: Option<Edge>,
+) {
+    assert_eq!(Edge::between(previous, payload), expected);
+}
+
+#[cfg(test)]
+#[rstest::rstest]
+#[case::no_previous(None, "42", None)]
+#[case::previous_not_numeric(Some("unknown"), "42", None)]
+#[case::not_numeric(Some("40"), "unknown", None)]
+#[case::not_finite(Some("NaN"), "42", None)]
+#[case::increase(Some("40 W"), "540 W", Some((540.0, 500.0)))]
+#[case::decrease(Some("40"), "30.5", Some((30.5, -9.5)))]
+fn numeric_delta_works(
+    #[case] previous: Option<&str>,
+    #[case] payload: &str,
+    #[case] expected: Option<(f32, f32)>,
+) {
+    assert_eq!(numeric_delta(previous, payload), expected);
+}
+
+#[test]
+fn edges_only_match_on_change() {
+    let (watcher, _receiver) = Watcher::new_edges("#", false);
+    assert!(watcher
+        .matching_sender("foo", "true", false, &Derived::new(None))
+        .is_none());
+    assert!(watcher
+        .matching_sender("foo", "true", false, &Derived::new(Some("true")))
+        .is_none());
+    assert!(watcher
+        .matching_sender("foo", "false", false, &Derived::new(Some("true")))
+        .is_some());
+}
+
+#[test]
+fn edge_is_shared_between_watchers() {
+    let (first, mut first_receiver) = Watcher::new_edges("#", false);
+    let (second, mut second_receiver) = Watcher::new_edges("foo", false);
+    let derived = Derived::new(Some("off"));
+    for watcher in [first, second] {
+        watcher
+            .matching_sender("foo", "on", false, &derived)
+            .unwrap()
+            .try_send("foo", "on", b"on", EntrySource::Incoming, &derived)
+            .unwrap();
+    }
+    assert_eq!(derived.edge.get(), Some(&Some(Edge::Rising)));
+    assert_eq!(first_receiver.try_recv().unwrap().1, Edge::Rising);
+    assert_eq!(second_receiver.try_recv().unwrap().1, Edge::Rising);
+}
+
 #[test]
 #[should_panic = "topic filter is not valid"]
 fn bad_filter_panics() {
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::topic_filter;
+use crate::watcher::Watcher;
+
+/// Watchers indexed by the levels of their topic filter.
+///
+/// Finding the watchers of a topic only walks the branches of its levels, `+` and `#` instead of matching every filter.
+#[derive(Default)]
+pub struct Watchers {
+    /// Ordered by their id which increases with every added watcher
+    by_id: BTreeMap<u64, Watcher>,
+    next_id: u64,
+    root: Node,
+}
+
+/// Level of the topic filters. The numbers are ids of the watchers.
+#[derive(Default)]
+struct Node {
+    literal: HashMap<Box<str>, Self>,
+    single_level: Option<Box<Self>>,
+    /// Filters ending with `#` after this level
+    multi_level: Vec<u64>,
+    /// Filters ending at this level
+    end: Vec<u64>,
+}
+
+impl Node {
+    fn insert<'a>(&mut self, mut levels: impl Iterator<Item = &'a str>, id: u64) {
+        match levels.next() {
+            None => self.end.push(id),
+            Some("#") => self.multi_level.push(id),
+            Some("+") => self
+                .single_level
+                .get_or_insert_with(Box::default)
+                .insert(levels, id),
+            Some(level) => self
+                .literal
+                .entry(level.into())
+                .or_default()
+                .insert(levels, id),
+        }
+    }
+
+    /// Remove the `id` inserted with the same `levels` and drop branches becoming empty.
+    fn remove<'a>(&mut self, mut levels: impl Iterator<Item = &'a str>, id: u64) {
+        match levels.next() {
+            None => self.end.retain(|other| *other != id),
+            Some("#") => self.multi_level.retain(|other| *other != id),
+            Some("+") => {
+                if let Some(node) = &mut self.single_level {
+                    node.remove(levels, id);
+                    if node.is_empty() {
+                        self.single_level = None;
+                    }
+                }
+            }
+            Some(level) => {
+                if let Some(node) = self.literal.get_mut(level) {
+                    node.remove(levels, id);
+                    if node.is_empty() {
+                        self.literal.remove(level);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.literal.is_empty()
+            && self.single_level.is_none()
+            && self.multi_level.is_empty()
+            && self.end.is_empty()
+    }
+
+    /// Same semantics as [`topic_filter::matches`](crate::topic_filter::matches).
+    fn collect(&self, levels: &[&str], matching: &mut Vec<u64>) {
+        matching.extend(&self.multi_level);
+        let Some((level, rest)) = levels.split_first() else {
+            matching.extend(&self.end);
+            return;
+        };
+        if *level == "#" {
+            return;
+        }
+        if let Some(node) = self.literal.get(*level) {
+            node.collect(rest, matching);
+        }
+        if let Some(node) = &self.single_level {
+            node.collect(rest, matching);
+        }
+    }
+}
+
+impl Watchers {
+    pub fn push(&mut self, watcher: Watcher) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.root
+            .insert(topic_filter::without_share(watcher.filter()).split('/'), id);
+        self.by_id.insert(id, watcher);
+    }
+
+    /// Only the index entries of the removed watchers are touched.
+    pub fn retain(&mut self, mut keep: impl FnMut(&Watcher) -> bool) {
+        let root = &mut self.root;
+        self.by_id.retain(|id, watcher| {
+            let kept = keep(watcher);
+            if !kept {
+                root.remove(
+                    topic_filter::without_share(watcher.filter()).split('/'),
+                    *id,
+                );
+            }
+            kept
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.by_id.clear();
+        self.root = Node::default();
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    /// The watcher identified by the activation flag, see [`Watcher::activation`].
+    pub fn with_activation(&self, active: &Arc<AtomicBool>) -> Option<&Watcher> {
+        self.by_id
+            .values()
+            .find(|watcher| watcher.has_activation(active))
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// Watchers with a filter matching the `topic` in the order they were added.
+    pub fn matching(&self, topic: &str) -> impl Iterator<Item = &Watcher> {
+        let mut matching = Vec::new();
+        // Like rumqttc no filter matches topics starting with $
+        if !topic.starts_with('$') {
+            let levels = topic.split('/').collect::<Vec<_>>();
+            self.root.collect(&levels, &mut matching);
+            matching.sort_unstable();
+        }
+        matching.into_iter().map(|id| &self.by_id[&id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [&str; 14] = [
+        "#",
+        "+",
+        "foo",
+        "foo/#",
+        "foo/+",
+        "foo/bar",
+        "foo/+/baz",
+        "+/bar",
+        "+/+",
+        "foo//bar",
+        "foo/bar/#",
+        "$SYS/#",
+        "$share/group/foo/+",
+        "other/#",
+    ];
+
+    fn watchers(filters: &[&str]) -> Watchers {
+        let mut watchers = Watchers::default();
+        for filter in filters {
+            watchers.push(Watcher::new(filter, true).0);
+        }
+        watchers
+    }
+
+    fn matching<'a>(watchers: &'a Watchers, topic: &str) -> Vec<&'a str> {
+        watchers.matching(topic).map(Watcher::filter).collect()
+    }
+
+    #[rstest::rstest]
+    fn matches_like_linear_scan(
+        #[values(
+            "",
+            "foo",
+            "foo/bar",
+            "foo/baz",
+            "foo/bar/baz",
+            "foo/x/baz",
+            "foo//bar",
+            "bar/bar",
+            "foo/bar/baz/deep",
+            "other",
+            "$SYS/uptime",
+            "foo/",
+            "/foo",
+            "foo/#"
+        )]
+        topic: &str,
+    ) {
+        let watchers = watchers(&FILTERS);
+        let expected = FILTERS
+            .into_iter()
+            .filter(|filter| topic_filter::matches(topic, filter))
+            .collect::<Vec<_>>();
+        assert_eq!(matching(&watchers, topic), expected);
+    }
+
+    #[test]
+    fn retain_removes_from_index() {
+        let mut watchers = watchers(&["foo/#", "foo/bar", "+/bar", "foo/bar"]);
+        watchers.retain(|watcher| watcher.filter() != "foo/#");
+        assert_eq!(watchers.len(), 3);
+        assert_eq!(
+            matching(&watchers, "foo/bar"),
+            ["foo/bar", "+/bar", "foo/bar"]
+        );
+        watchers.retain(|watcher| watcher.filter() != "+/bar");
+        assert!(watchers.root.single_level.is_none());
+        watchers.push(Watcher::new("foo/#", true).0);
+        assert_eq!(
+            matching(&watchers, "foo/bar"),
+            ["foo/bar", "foo/bar", "foo/#"]
+        );
+        watchers.clear();
+        assert!(watchers.is_empty());
+        assert!(matching(&watchers, "foo/bar").is_empty());
+    }
+}
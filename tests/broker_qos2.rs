@@ -0,0 +1,34 @@
+//! Needs an MQTT broker on localhost:1883, run with `cargo test -- --ignored`.
+
+use core::time::Duration;
+
+use mqtt_smarthome::protocol::QoS;
+use mqtt_smarthome::MqttSmarthome;
+use tokio::time::timeout;
+
+#[tokio::test]
+#[ignore = "requires an MQTT broker on localhost:1883"]
+async fn exactly_once_is_delivered_once() {
+    let smarthome = MqttSmarthome::new("mqtt-smarthome-qos2-test", "localhost", 1883, false);
+    let topic = "mqtt-smarthome-qos2-test/set/counter";
+    smarthome
+        .subscribe_qos_confirmed(topic, QoS::ExactlyOnce)
+        .await
+        .expect("broker should accept the subscription");
+    let mut receiver = smarthome.watch(topic, false).await;
+
+    smarthome
+        .publish_qos(topic, "increment", false, QoS::ExactlyOnce)
+        .await
+        .unwrap();
+
+    let (_, payload) = timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("message should arrive")
+        .unwrap();
+    assert_eq!(payload, "increment");
+    assert!(timeout(Duration::from_secs(2), receiver.recv())
+        .await
+        .is_err());
+    smarthome.disconnect().await.unwrap();
+}